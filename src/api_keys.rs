@@ -0,0 +1,83 @@
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+
+use todo_core::clock::SharedClock;
+use todo_core::models::ApiKey;
+use todo_core::repository;
+use todo_core::validation::check_key_expiry;
+
+use crate::auth::{generate_api_key, hash_api_key, AuthenticatedUser};
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+
+// A newly created key. This is the only response that includes the key.
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct IssuedKey {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    key: String,
+}
+
+// API key routes
+
+#[openapi(tag = "API keys")]
+#[get("/api-keys")]
+async fn list_api_keys(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<Listing<ApiKey>, ApiError> {
+    let api_keys = conn
+        .run(move |c| repository::api_keys::list(c, user.id))
+        .await?;
+
+    Ok(Listing::all(api_keys))
+}
+
+#[openapi(tag = "API keys")]
+#[post("/api-keys", format = "json", data = "<api_key>")]
+async fn create_api_key(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    api_key: ValidatedJson<ApiKey>,
+) -> Result<status::Created<Json<IssuedKey>>, ApiError> {
+    let mut api_key = api_key.into_inner();
+    let now = clock.now();
+    check_key_expiry(&api_key, now).map_err(ApiError::Invalid)?;
+    api_key.created_at = Some(now);
+    let key = generate_api_key();
+    let key_hash = hash_api_key(&key);
+
+    let api_key = conn
+        .run(move |c| repository::api_keys::insert(c, user.id, &api_key, &key_hash))
+        .await?;
+    let location = format!("/api-keys/{}", api_key.id.unwrap_or_default());
+
+    Ok(status::Created::new(location).body(Json(IssuedKey { api_key, key })))
+}
+
+// Revoke a key; requests using it fail with 401 from then on
+#[openapi(tag = "API keys")]
+#[delete("/api-keys/<api_key_id>")]
+async fn delete_api_key(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    api_key_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::api_keys::delete(c, user.id, api_key_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![list_api_keys, create_api_key, delete_api_key]
+}
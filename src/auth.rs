@@ -0,0 +1,367 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rocket::http::{Header as HttpHeader, Status};
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{
+    Object, OpenApi, Responses, SecurityRequirement, SecurityScheme, SecuritySchemeData,
+};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+
+use todo_core::clock::SharedClock;
+use todo_core::models::User;
+use todo_core::repository;
+use todo_core::validation::{FieldError, Validate};
+
+use crate::db::DbConn;
+use crate::error::{add_error_response, is_duplicate, ApiError};
+use crate::guards::ValidatedJson;
+use crate::{rate_limit, request_id};
+
+const MIN_PASSWORD_CHARS: usize = 8;
+
+// API keys start with this, which tells them apart from JWTs
+const API_KEY_PREFIX: &str = "todo_";
+const API_KEY_BYTES: usize = 32;
+
+// Signing keys for access tokens
+pub struct TokenKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl_secs: i64,
+}
+
+impl TokenKeys {
+    pub fn new(secret: &str, ttl_secs: i64) -> Self {
+        TokenKeys {
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            ttl_secs,
+        }
+    }
+
+    // A token for `user`, valid for the TTL from `now`
+    fn issue(&self, user: User, now: DateTime<Utc>) -> Result<Session, ApiError> {
+        Ok(Session {
+            token: self.sign(user.id, now)?,
+            token_type: "Bearer",
+            expires_in: self.ttl_secs,
+            user,
+        })
+    }
+
+    // A signed token for the user with id `user_id`
+    pub fn sign(&self, user_id: u32, now: DateTime<Utc>) -> Result<String, ApiError> {
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp: now.timestamp() + self.ttl_secs,
+        };
+        encode(&Header::default(), &claims, &self.encoding)
+            .map_err(|e| ApiError::Internal(format!("Failed to sign token: {}", e)))
+    }
+
+    // The user id a token was issued to, if it is genuine and unexpired at
+    // `now`. Expiry is checked here rather than by jsonwebtoken, which would
+    // use the system time instead of the server's clock.
+    fn verify(&self, token: &str, now: DateTime<Utc>) -> Option<u32> {
+        let mut validation = Validation::default();
+        validation.validate_exp = false;
+
+        decode::<Claims>(token, &self.decoding, &validation)
+            .ok()
+            .filter(|data| data.claims.exp > now.timestamp())
+            .and_then(|data| data.claims.sub.parse().ok())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Claims {
+    sub: String,
+    exp: i64,
+}
+
+// A new random API key
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; API_KEY_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", API_KEY_PREFIX, hex)
+}
+
+// What is stored in place of an API key. Keys are random, so a plain digest
+// is enough and keeps the lookup a single indexed query.
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// The user a request's `Authorization: Bearer <token>` header was issued to.
+// The token is either a JWT from /auth/login or an API key from /api-keys.
+//
+// Requests without a valid token end with 401.
+pub struct AuthenticatedUser {
+    pub id: u32,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let keys = req
+            .rocket()
+            .state::<TokenKeys>()
+            .expect("TokenKeys must be managed");
+        let clock = req
+            .rocket()
+            .state::<SharedClock>()
+            .expect("SharedClock must be managed");
+
+        let Some(token) = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+        else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        let user_id = if token.starts_with(API_KEY_PREFIX) {
+            let mut conn = try_outcome!(req
+                .guard::<DbConn>()
+                .await
+                .map_error(|(status, _)| (status, ())));
+            let key_hash = hash_api_key(token);
+            let now = clock.now().naive_utc();
+
+            match conn
+                .run(move |c| repository::api_keys::find_user(c, &key_hash, now))
+                .await
+            {
+                Ok(user_id) => user_id,
+                Err(e) => {
+                    tracing::error!(
+                        request_id = request_id::of(req),
+                        error = %e,
+                        "API key lookup failed"
+                    );
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            }
+        } else {
+            keys.verify(token, clock.now())
+        };
+
+        let Some(id) = user_id else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+        req.local_cache(|| SignedIn(Some(id)));
+
+        if rate_limit::admit(req, id) {
+            Outcome::Success(AuthenticatedUser { id })
+        } else {
+            Outcome::Error((Status::TooManyRequests, ()))
+        }
+    }
+}
+
+// The user a request authenticated as, for the request log
+struct SignedIn(Option<u32>);
+
+pub fn signed_in_user(req: &Request<'_>) -> Option<u32> {
+    req.local_cache(|| SignedIn(None)).0
+}
+
+impl<'r> OpenApiFromRequest<'r> for AuthenticatedUser {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        let scheme = SecurityScheme {
+            description: Some("A JWT from /auth/login or an API key from /api-keys".to_string()),
+            data: SecuritySchemeData::Http {
+                scheme: "bearer".to_string(),
+                bearer_format: None,
+            },
+            extensions: Object::default(),
+        };
+        let mut requirement = SecurityRequirement::new();
+        requirement.insert("bearer".to_string(), Vec::new());
+
+        Ok(RequestHeaderInput::Security(
+            "bearer".to_string(),
+            scheme,
+            requirement,
+        ))
+    }
+
+    fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_error_response(gen, &mut responses, 401)?;
+        add_error_response(gen, &mut responses, 429)?;
+        Ok(responses)
+    }
+}
+
+// 401 body telling the client to authenticate with a bearer token
+#[derive(Responder)]
+#[response(status = 401, content_type = "json")]
+pub struct Unauthorized {
+    body: Json<Value>,
+    authenticate: HttpHeader<'static>,
+}
+
+#[catch(401)]
+pub fn unauthorized(req: &Request<'_>) -> Unauthorized {
+    Unauthorized {
+        body: Json(json!({
+            "status": 401,
+            "code": "unauthorized",
+            "message": "A valid bearer token is required",
+            "request_id": request_id::of(req),
+        })),
+        authenticate: HttpHeader::new("WWW-Authenticate", "Bearer"),
+    }
+}
+
+// Body of POST /auth/register and POST /auth/login
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Credentials {
+    email: String,
+    password: String,
+}
+
+impl Credentials {
+    // Emails are matched case-insensitively
+    fn email(&self) -> String {
+        self.email.trim().to_lowercase()
+    }
+}
+
+impl Validate for Credentials {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        if !self.email().contains('@') {
+            errors.push(FieldError::new("email", "must be an email address"));
+        }
+        if self.password.chars().count() < MIN_PASSWORD_CHARS {
+            errors.push(FieldError::new(
+                "password",
+                format!("must be at least {} characters", MIN_PASSWORD_CHARS),
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// A freshly issued token and the account it belongs to
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Session {
+    token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    user: User,
+}
+
+fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError::Internal(format!("Failed to hash password: {}", e)))
+}
+
+fn password_matches(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// Auth routes
+
+#[openapi(tag = "Auth")]
+#[post("/auth/register", format = "json", data = "<credentials>")]
+async fn register(
+    mut conn: DbConn,
+    keys: &State<TokenKeys>,
+    clock: &State<SharedClock>,
+    credentials: ValidatedJson<Credentials>,
+) -> Result<status::Custom<Json<Session>>, ApiError> {
+    let email = credentials.email();
+    let password = credentials.into_inner().password;
+    let now = clock.now();
+
+    let user = conn
+        .run(move |c| {
+            let password_hash = hash_password(&password)?;
+            repository::users::insert(c, &email, &password_hash, now).map_err(|e| {
+                if is_duplicate(&e) {
+                    ApiError::Conflict("an account with this email already exists".to_string())
+                } else {
+                    ApiError::Database(e)
+                }
+            })
+        })
+        .await?;
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(keys.issue(user, now)?),
+    ))
+}
+
+#[openapi(tag = "Auth")]
+#[post("/auth/login", format = "json", data = "<credentials>")]
+async fn login(
+    mut conn: DbConn,
+    keys: &State<TokenKeys>,
+    clock: &State<SharedClock>,
+    credentials: Json<Credentials>,
+) -> Result<Json<Session>, ApiError> {
+    let email = credentials.email();
+    let password = credentials.into_inner().password;
+
+    let user = conn
+        .run(move |c| {
+            Ok::<_, ApiError>(
+                repository::users::credentials(c, &email)?
+                    .filter(|(_, hash)| password_matches(&password, hash))
+                    .map(|(user, _)| user),
+            )
+        })
+        .await?
+        .ok_or(ApiError::Unauthorized("invalid email or password"))?;
+
+    Ok(Json(keys.issue(user, clock.now())?))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![register, login]
+}
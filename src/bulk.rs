@@ -0,0 +1,254 @@
+use chrono::{DateTime, Utc};
+use mysql::{PooledConn, TxOpts};
+use rocket::form::Form;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+
+use todo_core::clock::SharedClock;
+use todo_core::models::Task;
+use todo_core::repository;
+use todo_core::validation::{FieldError, Validate};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::events::{TaskEvent, TaskEvents};
+use crate::guards::ValidatedJson;
+use crate::TaskFilters;
+
+// Bulk task changes
+//
+// POST /tasks/complete and POST /tasks/reschedule apply to either a list of
+// task ids or every task matching a filter written like the query string of
+// GET /tasks:
+//
+//     {"ids": [3, 5, 8]}
+//     {"filter": "list_id=3&completed=false", "days": 7}
+//
+// Each runs in one transaction, so a missing id changes nothing, and
+// answers with how many tasks were picked out and which of them changed.
+
+const MAX_IDS: usize = 1000;
+const MAX_SHIFT_DAYS: i64 = 10 * 366;
+
+// The tasks a bulk change applies to
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Selection {
+    ids: Option<Vec<u32>>,
+    filter: Option<String>,
+}
+
+impl Selection {
+    fn check(&self, errors: &mut Vec<FieldError>) {
+        match (&self.ids, &self.filter) {
+            (Some(_), Some(_)) | (None, None) => {
+                errors.push(FieldError::new("ids", "give either ids or filter"))
+            }
+            (Some(ids), None) if ids.is_empty() || ids.len() > MAX_IDS => errors.push(
+                FieldError::new("ids", format!("must list 1 to {} tasks", MAX_IDS)),
+            ),
+            _ => {}
+        }
+    }
+
+    // Ids of the selected tasks, judging filters at `now`
+    fn resolve(
+        &self,
+        conn: &mut impl mysql::prelude::Queryable,
+        user_id: u32,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u32>, ApiError> {
+        if let Some(filter) = &self.filter {
+            let filters: TaskFilters<'_> = Form::parse(filter)
+                .map_err(|_| ApiError::BadRequest("invalid filter".to_string()))?;
+            let query = filters.into_query(now)?;
+            return Ok(repository::tasks::matching_ids(conn, user_id, &query)?);
+        }
+
+        let mut ids = self.ids.clone().unwrap_or_default();
+        ids.sort_unstable();
+        ids.dedup();
+        for &id in &ids {
+            if repository::tasks::parent_id(conn, user_id, id)?.is_none() {
+                return Err(ApiError::NotFound("task"));
+            }
+        }
+        Ok(ids)
+    }
+}
+
+// Body of POST /tasks/complete
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Completion {
+    #[serde(flatten)]
+    selection: Selection,
+}
+
+impl Validate for Completion {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        self.selection.check(&mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Body of POST /tasks/reschedule: move start and due dates by `days`, or set
+// every due date to `due_date`
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Reschedule {
+    #[serde(flatten)]
+    selection: Selection,
+    days: Option<i64>,
+    due_date: Option<DateTime<Utc>>,
+}
+
+impl Validate for Reschedule {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        self.selection.check(&mut errors);
+
+        match (self.days, self.due_date) {
+            (Some(_), Some(_)) | (None, None) => {
+                errors.push(FieldError::new("days", "give either days or due_date"))
+            }
+            (Some(days), None) if days == 0 || days.abs() > MAX_SHIFT_DAYS => {
+                errors.push(FieldError::new(
+                    "days",
+                    format!("must be between -{0} and {0}, not 0", MAX_SHIFT_DAYS),
+                ))
+            }
+            _ => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Summary {
+    // Tasks the ids or filter picked out
+    matched: usize,
+    // Those the change applied to, e.g. not already completed
+    changed: usize,
+    task_ids: Vec<u32>,
+}
+
+// Apply `change` to each selected task in one transaction, returning the
+// summary and the changed tasks as saved
+fn apply<F>(
+    conn: &mut PooledConn,
+    user_id: u32,
+    selection: &Selection,
+    now: DateTime<Utc>,
+    mut change: F,
+) -> Result<(Summary, Vec<Task>), ApiError>
+where
+    F: FnMut(&mut mysql::Transaction, u32) -> mysql::Result<bool>,
+{
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    let ids = selection.resolve(&mut tx, user_id, now)?;
+
+    let mut tasks = Vec::new();
+    for &id in &ids {
+        if change(&mut tx, id)? {
+            tasks.extend(repository::tasks::find(&mut tx, user_id, id)?);
+        }
+    }
+    tx.commit()?;
+
+    let summary = Summary {
+        matched: ids.len(),
+        changed: tasks.len(),
+        task_ids: tasks.iter().filter_map(|task| task.id).collect(),
+    };
+    Ok((summary, tasks))
+}
+
+#[openapi(tag = "Tasks")]
+#[post("/tasks/complete", format = "json", data = "<completion>")]
+async fn complete_tasks(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    completion: ValidatedJson<Completion>,
+) -> Result<Json<Summary>, ApiError> {
+    let completion = completion.into_inner();
+    let now = clock.now();
+    let (summary, tasks) = conn
+        .run(move |c| {
+            apply(c, user.id, &completion.selection, now, |tx, id| {
+                repository::tasks::complete(tx, user.id, id)
+            })
+        })
+        .await?;
+    for task in tasks {
+        events.publish(
+            user.id,
+            TaskEvent::Updated {
+                task,
+                completed: true,
+            },
+        );
+    }
+
+    Ok(Json(summary))
+}
+
+#[openapi(tag = "Tasks")]
+#[post("/tasks/reschedule", format = "json", data = "<reschedule>")]
+async fn reschedule_tasks(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    reschedule: ValidatedJson<Reschedule>,
+) -> Result<Json<Summary>, ApiError> {
+    let reschedule = reschedule.into_inner();
+    let now = clock.now();
+    let (summary, tasks) = conn
+        .run(move |c| {
+            apply(c, user.id, &reschedule.selection, now, |tx, id| {
+                match (reschedule.days, reschedule.due_date) {
+                    (Some(days), _) => repository::tasks::shift_dates(tx, user.id, id, days),
+                    (None, Some(due_date)) => {
+                        repository::tasks::set_due_date(tx, user.id, id, due_date.naive_utc())
+                    }
+                    (None, None) => Ok(false),
+                }
+            })
+        })
+        .await?;
+    for task in tasks {
+        events.publish(
+            user.id,
+            TaskEvent::Updated {
+                task,
+                completed: false,
+            },
+        );
+    }
+
+    Ok(Json(summary))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![complete_tasks, reschedule_tasks]
+}
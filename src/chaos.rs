@@ -0,0 +1,198 @@
+use rand::Rng;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{status, Response};
+use rocket::serde::json::{json, Json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Data, State};
+use std::io::Cursor;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use todo_core::validation::{FieldError, Validate};
+
+use crate::auth::AuthenticatedUser;
+use crate::guards::ValidatedJson;
+use crate::request_id;
+
+// Fault injection for exercising clients against a misbehaving server
+//
+// Only available when Rocket runs with the `test` profile
+// (ROCKET_PROFILE=test). Faults are configured at runtime with
+// PUT /admin/chaos and apply to a percentage of requests each:
+//
+// - latency delays the request before it is handled
+// - database errors fail the request's connection checkout, ending in the
+//   same 503 a real outage gives
+// - errors replace the response with a 500 after the handler ran, like a
+//   response lost on the way back
+//
+// Requests to /admin/chaos itself are left alone so it can be switched off.
+// There are no admin accounts, so any signed-in user may change the settings;
+// that is fine only because the test profile never faces real users.
+
+const PROFILE: &str = "test";
+const ADMIN_PATH: &str = "/admin/chaos";
+const MAX_LATENCY_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ChaosSettings {
+    #[serde(default)]
+    latency_ms: u64,
+    #[serde(default)]
+    latency_percent: u8,
+    #[serde(default)]
+    db_error_percent: u8,
+    #[serde(default)]
+    error_percent: u8,
+}
+
+impl Validate for ChaosSettings {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        if self.latency_ms > MAX_LATENCY_MS {
+            errors.push(FieldError::new(
+                "latency_ms",
+                format!("must be at most {}", MAX_LATENCY_MS),
+            ));
+        }
+        for (field, percent) in [
+            ("latency_percent", self.latency_percent),
+            ("db_error_percent", self.db_error_percent),
+            ("error_percent", self.error_percent),
+        ] {
+            if percent > 100 {
+                errors.push(FieldError::new(field, "must be between 0 and 100"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Current settings, shared by the fairing and the admin routes
+#[derive(Default)]
+struct Chaos {
+    settings: RwLock<ChaosSettings>,
+}
+
+impl Chaos {
+    fn settings(&self) -> ChaosSettings {
+        *self.settings.read().unwrap()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fault {
+    Database,
+    Internal,
+}
+
+// The fault picked for a request, if any
+struct Injected(Option<Fault>);
+
+fn roll(percent: u8) -> bool {
+    percent > 0 && rand::thread_rng().gen_range(0..100) < percent
+}
+
+// Whether chaos mode wants this request's database connection to fail
+pub fn fails_database(req: &Request<'_>) -> bool {
+    req.local_cache(|| Injected(None)).0 == Some(Fault::Database)
+}
+
+struct ChaosFairing;
+
+#[rocket::async_trait]
+impl Fairing for ChaosFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Chaos",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        if req.uri().path().starts_with(ADMIN_PATH) {
+            return;
+        }
+        let Some(chaos) = req.rocket().state::<Chaos>() else {
+            return;
+        };
+        let settings = chaos.settings();
+
+        if roll(settings.latency_percent) {
+            rocket::tokio::time::sleep(Duration::from_millis(settings.latency_ms)).await;
+        }
+
+        let fault = if roll(settings.db_error_percent) {
+            Some(Fault::Database)
+        } else if roll(settings.error_percent) {
+            Some(Fault::Internal)
+        } else {
+            None
+        };
+        req.local_cache(|| Injected(fault));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if req.local_cache(|| Injected(None)).0 != Some(Fault::Internal) {
+            return;
+        }
+
+        let body = json!({
+            "status": 500,
+            "code": "internal_error",
+            "message": "Internal server error",
+            "request_id": request_id::of(req),
+        })
+        .to_string();
+        res.set_status(Status::InternalServerError);
+        res.set_header(ContentType::JSON);
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+// Admin routes
+
+#[get("/admin/chaos")]
+fn get_chaos(chaos: &State<Chaos>, _user: AuthenticatedUser) -> Json<ChaosSettings> {
+    Json(chaos.settings())
+}
+
+#[put("/admin/chaos", format = "json", data = "<settings>")]
+fn set_chaos(
+    chaos: &State<Chaos>,
+    _user: AuthenticatedUser,
+    settings: ValidatedJson<ChaosSettings>,
+) -> Json<ChaosSettings> {
+    *chaos.settings.write().unwrap() = settings.into_inner();
+    Json(chaos.settings())
+}
+
+// Turn every fault off
+#[delete("/admin/chaos")]
+fn reset_chaos(chaos: &State<Chaos>, _user: AuthenticatedUser) -> status::NoContent {
+    *chaos.settings.write().unwrap() = ChaosSettings::default();
+    status::NoContent
+}
+
+// Chaos mode under the test profile, nothing otherwise
+pub fn fairing() -> AdHoc {
+    AdHoc::on_ignite("Chaos mode", |rocket| async move {
+        if rocket.figment().profile() != PROFILE {
+            return rocket;
+        }
+
+        rocket
+            .manage(Chaos::default())
+            .mount("/", routes![get_chaos, set_chaos, reset_chaos])
+            .attach(ChaosFairing)
+    })
+}
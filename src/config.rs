@@ -0,0 +1,368 @@
+use dotenv::dotenv;
+use rocket::figment::providers::Env;
+use rocket::figment::value::{Dict, Value};
+use rocket::figment::Figment;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use todo_core::clock::{SharedClock, SystemClock};
+
+use crate::cors::{self, CorsSettings, Origins};
+use crate::db::AcquireTimeouts;
+use crate::logging::{self, LogFormat};
+
+// Configuration
+//
+// Settings come from Rocket's figment: Rocket.toml, ROCKET_* environment
+// variables, and the unprefixed variables below (also read from .env), which
+// win over both. In Rocket.toml they are written in lower case, e.g.
+// `database_url = "mysql://..."`. Everything is read and checked once at
+// startup, and every missing or malformed setting is reported together.
+
+// Unprefixed environment variables taken as settings
+const ENV_KEYS: &[&str] = &[
+    "DATABASE_URL",
+    "DB_POOL_SIZE",
+    "DB_QUERY_TIMEOUT_MS",
+    "DB_ACQUIRE_TIMEOUT_MS",
+    "JWT_SECRET",
+    "JWT_TTL_SECS",
+    "TRASH_RETENTION_DAYS",
+    "RATE_LIMIT_PER_MINUTE",
+    "TRUSTED_PROXIES",
+    "CORS_ALLOWED_ORIGINS",
+    "CORS_ALLOWED_METHODS",
+    "CORS_ALLOW_CREDENTIALS",
+    "CORS_MAX_AGE_SECS",
+    "BACKGROUND_JOBS",
+    "LOG_FORMAT",
+];
+
+// LOG_LEVEL is taken as `log_filter`, as Rocket has a `log_level` of its own
+const LOG_FILTER_VAR: &str = "LOG_LEVEL";
+
+// Per-route acquire timeouts are DB_ACQUIRE_TIMEOUT_MS_<ROUTE>, e.g.
+// DB_ACQUIRE_TIMEOUT_MS_LIST_TASKS=2000 for the `list_tasks` handler
+const ACQUIRE_TIMEOUT_ROUTE_PREFIX: &str = "db_acquire_timeout_ms_";
+
+// Defaults used when the corresponding setting is missing
+const DEFAULT_QUERY_TIMEOUT_MS: &str = "30000";
+const DEFAULT_ACQUIRE_TIMEOUT_MS: &str = "5000";
+const DEFAULT_TOKEN_TTL_SECS: &str = "86400";
+const DEFAULT_TRASH_RETENTION_DAYS: &str = "30";
+const DEFAULT_RATE_LIMIT_PER_MINUTE: &str = "600";
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_PORT: &str = "8000";
+
+// Settings the server is built from. A deployment loads them with
+// `AppConfig::load`; tests fill them in directly.
+pub struct AppConfig {
+    pub database_url: String,
+    // Most connections the pool opens at once, if not the driver's default
+    pub db_pool_size: Option<usize>,
+    // Socket timeout for database queries
+    pub query_timeout: Duration,
+    // How long a request waits for a pooled connection
+    pub acquire_timeouts: AcquireTimeouts,
+    // Where the server listens, ROCKET_ADDRESS and ROCKET_PORT
+    pub address: IpAddr,
+    pub port: u16,
+    // Secret access tokens are signed with, and how long they stay valid
+    pub jwt_secret: String,
+    pub token_ttl_secs: i64,
+    // How long deleted tasks stay in the trash
+    pub trash_retention_days: i64,
+    // Requests each user may make per minute
+    pub rate_limit_per_minute: u32,
+    // Origins, methods and caching for cross-origin browsers
+    pub cors: CorsSettings,
+    // Peers whose X-Request-Id is taken instead of making up a new one
+    pub trusted_proxies: Vec<IpAddr>,
+    // Whether the schedule runner, trash purger and goal recorder run in the
+    // background
+    pub background_jobs: bool,
+    // What handlers and background jobs take the current time from
+    pub clock: SharedClock,
+    // Tracing filter and output format for the logs
+    pub log_level: String,
+    pub log_format: LogFormat,
+}
+
+// Every problem found while loading the configuration
+#[derive(Debug)]
+pub struct ConfigErrors(Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+// The environment variable a setting is named by in errors
+fn variable(key: &str) -> String {
+    match key {
+        "address" | "port" => format!("ROCKET_{}", key.to_uppercase()),
+        "log_filter" => LOG_FILTER_VAR.to_string(),
+        _ => key.to_uppercase(),
+    }
+}
+
+// Reads settings out of the figment, collecting errors instead of stopping
+// at the first one
+struct Settings {
+    figment: Figment,
+    errors: Vec<String>,
+}
+
+impl Settings {
+    fn load() -> Self {
+        dotenv().ok();
+
+        let figment = rocket::Config::figment()
+            .merge(Env::raw().only(ENV_KEYS).global())
+            .merge(
+                Env::raw()
+                    .only(&[LOG_FILTER_VAR])
+                    .map(|_| "log_filter".into())
+                    .global(),
+            )
+            .merge(
+                Env::raw()
+                    .filter(|key| {
+                        key.as_str()
+                            .to_ascii_lowercase()
+                            .starts_with(ACQUIRE_TIMEOUT_ROUTE_PREFIX)
+                    })
+                    .global(),
+            );
+        Settings {
+            figment,
+            errors: Vec::new(),
+        }
+    }
+
+    // A setting as text, None when it is missing. Lists in Rocket.toml are
+    // joined with commas like their environment variable form.
+    fn text(&mut self, key: &str) -> Option<String> {
+        let value = self.figment.find_value(key).ok()?;
+        let text = match &value {
+            Value::String(_, text) => Some(text.clone()),
+            Value::Array(_, items) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .map(|items| items.join(",")),
+            Value::Num(..) | Value::Bool(..) => value
+                .deserialize::<rocket::serde::json::Value>()
+                .ok()
+                .map(|json| json.to_string()),
+            _ => None,
+        };
+
+        if text.is_none() {
+            self.errors
+                .push(format!("{} must be a single value", variable(key)));
+        }
+        text
+    }
+
+    // The names of all settings starting with `prefix`
+    fn keys_with_prefix(&self, prefix: &str) -> BTreeSet<String> {
+        let Ok(settings) = self.figment.extract::<Dict>() else {
+            return BTreeSet::new();
+        };
+        settings
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        self.text(key).unwrap_or_else(|| {
+            self.errors.push(format!("{} must be set", variable(key)));
+            String::new()
+        })
+    }
+
+    // A setting run through `parse`, falling back to `default` when it is
+    // missing or doesn't parse
+    fn with<T>(
+        &mut self,
+        key: &str,
+        default: &str,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> T {
+        let text = self.text(key).unwrap_or_else(|| default.to_string());
+        parse(&text).unwrap_or_else(|e| {
+            self.errors.push(format!("{}: {}", variable(key), e));
+            parse(default).unwrap_or_else(|_| panic!("default for {} must parse", key))
+        })
+    }
+
+    // A setting parsed with FromStr, `what` describing it for the error
+    fn parsed<T: FromStr>(&mut self, key: &str, default: &str, what: &str) -> T {
+        self.with(key, default, |text| {
+            text.trim()
+                .parse()
+                .map_err(|_| format!("must be {}, not {:?}", what, text))
+        })
+    }
+
+    fn finish<T>(self, config: T) -> Result<T, ConfigErrors> {
+        if self.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigErrors(self.errors))
+        }
+    }
+}
+
+// A comma-separated list of IP addresses
+fn parse_ips(text: &str) -> Result<Vec<IpAddr>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| {
+            ip.parse()
+                .map_err(|_| format!("{} is not an IP address", ip))
+        })
+        .collect()
+}
+
+fn parse_pool_size(text: &str) -> Result<Option<usize>, String> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    match text.trim().parse() {
+        Ok(size) if size > 0 => Ok(Some(size)),
+        _ => Err(format!("must be a number of connections, not {:?}", text)),
+    }
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self, ConfigErrors> {
+        let mut settings = Settings::load();
+
+        let database_url = settings.required("database_url");
+        if !database_url.is_empty() {
+            if let Err(e) = todo_core::opts_from_url(&database_url) {
+                settings
+                    .errors
+                    .push(format!("DATABASE_URL is not a valid MySQL URL: {}", e));
+            }
+        }
+        let db_pool_size = settings.with("db_pool_size", "", parse_pool_size);
+        let query_timeout_ms: u64 = settings.parsed(
+            "db_query_timeout_ms",
+            DEFAULT_QUERY_TIMEOUT_MS,
+            "a number of milliseconds",
+        );
+        let acquire_timeout_ms: u64 = settings.parsed(
+            "db_acquire_timeout_ms",
+            DEFAULT_ACQUIRE_TIMEOUT_MS,
+            "a number of milliseconds",
+        );
+        let mut per_route = HashMap::new();
+        for key in settings.keys_with_prefix(ACQUIRE_TIMEOUT_ROUTE_PREFIX) {
+            let millis: u64 =
+                settings.parsed(&key, DEFAULT_ACQUIRE_TIMEOUT_MS, "a number of milliseconds");
+            let route = &key[ACQUIRE_TIMEOUT_ROUTE_PREFIX.len()..];
+            per_route.insert(route.to_string(), Duration::from_millis(millis));
+        }
+        let acquire_timeouts = AcquireTimeouts {
+            default: Duration::from_millis(acquire_timeout_ms),
+            per_route,
+        };
+        let address = settings.parsed("address", DEFAULT_ADDRESS, "an IP address");
+        let port = settings.parsed("port", DEFAULT_PORT, "a port number");
+
+        let jwt_secret = settings.required("jwt_secret");
+        let token_ttl_secs = settings.parsed(
+            "jwt_ttl_secs",
+            DEFAULT_TOKEN_TTL_SECS,
+            "a number of seconds",
+        );
+        let trash_retention_days = settings.parsed(
+            "trash_retention_days",
+            DEFAULT_TRASH_RETENTION_DAYS,
+            "a number of days",
+        );
+        let rate_limit_per_minute = settings.parsed(
+            "rate_limit_per_minute",
+            DEFAULT_RATE_LIMIT_PER_MINUTE,
+            "a number of requests",
+        );
+        let trusted_proxies = settings.with("trusted_proxies", "", parse_ips);
+
+        let cors = CorsSettings {
+            origins: settings.with(
+                "cors_allowed_origins",
+                cors::DEFAULT_ORIGINS,
+                cors::parse_origins,
+            ),
+            methods: settings.with(
+                "cors_allowed_methods",
+                cors::DEFAULT_METHODS,
+                cors::parse_methods,
+            ),
+            allow_credentials: settings.parsed("cors_allow_credentials", "true", "true or false"),
+            max_age_secs: settings.with("cors_max_age_secs", "", |text| {
+                if text.is_empty() {
+                    return Ok(None);
+                }
+                text.trim()
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("must be a number of seconds, not {:?}", text))
+            }),
+        };
+        // Any origin would be echoed back with credentials, letting every
+        // site make signed-in requests
+        if cors.allow_credentials && cors.origins == Origins::Any {
+            settings
+                .errors
+                .push("CORS_ALLOWED_ORIGINS=* needs CORS_ALLOW_CREDENTIALS=false".to_string());
+        }
+
+        let background_jobs = settings.parsed("background_jobs", "true", "true or false");
+        let log_level = settings.with("log_filter", logging::DEFAULT_LEVEL, logging::parse_level);
+        let log_format = settings.with("log_format", "json", logging::parse_format);
+
+        let config = AppConfig {
+            database_url,
+            db_pool_size,
+            query_timeout: Duration::from_millis(query_timeout_ms),
+            acquire_timeouts,
+            address,
+            port,
+            jwt_secret,
+            token_ttl_secs,
+            trash_retention_days,
+            rate_limit_per_minute,
+            cors,
+            trusted_proxies,
+            background_jobs,
+            clock: Arc::new(SystemClock),
+            log_level,
+            log_format,
+        };
+        settings.finish(config)
+    }
+}
+
+// Just the database URL, for tools like the terminal client that need
+// nothing else
+pub fn database_url() -> Result<String, ConfigErrors> {
+    let mut settings = Settings::load();
+    let database_url = settings.required("database_url");
+    settings.finish(database_url)
+}
@@ -0,0 +1,169 @@
+use rocket::http::Method;
+use rocket_cors::{AllowedOrigins, CorsOptions};
+use std::str::FromStr;
+
+use crate::{rate_limit, request_id};
+
+// CORS
+//
+// Set like the rest of the configuration, e.g. from the environment:
+//
+//     CORS_ALLOWED_ORIGINS=https://app.example.com,https://*.example.com
+//     CORS_ALLOWED_METHODS=GET,POST,PUT,PATCH,DELETE,OPTIONS
+//     CORS_ALLOW_CREDENTIALS=true
+//     CORS_MAX_AGE_SECS=3600
+//
+// Origins are comma-separated. A `*` in the host stands for one or more
+// subdomain labels and must be followed by at least two literal labels, as
+// in `*.example.com`. `*` alone allows any origin, though only without
+// credentials. Each setting is checked at startup, so a typo stops the
+// server instead of silently blocking browsers.
+
+pub const DEFAULT_ORIGINS: &str = "http://localhost:8000";
+pub const DEFAULT_METHODS: &str = "GET,POST,PUT,PATCH,DELETE,OPTIONS";
+
+// What a subdomain wildcard matches, in the regex rocket_cors compiles
+const WILDCARD_PATTERN: &str = "[a-z0-9-]+(\\.[a-z0-9-]+)*";
+
+// Which origins browsers may call from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origins {
+    Any,
+    // Exact origins and `*` patterns, e.g. https://*.example.com
+    Some {
+        exact: Vec<String>,
+        patterns: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsSettings {
+    pub origins: Origins,
+    pub methods: Vec<Method>,
+    pub allow_credentials: bool,
+    // How long browsers may cache a preflight, if they're told at all
+    pub max_age_secs: Option<usize>,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        CorsSettings {
+            origins: parse_origins(DEFAULT_ORIGINS).expect("default origins"),
+            methods: parse_methods(DEFAULT_METHODS).expect("default methods"),
+            allow_credentials: true,
+            max_age_secs: None,
+        }
+    }
+}
+
+// Check one origin: scheme, host and optional port, no path
+fn check_origin(origin: &str) -> Result<(), String> {
+    let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| format!("origin {} must start with http:// or https://", origin))?;
+    let (name, port) = host.split_once(':').unwrap_or((host, ""));
+
+    let name_ok = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '*'));
+    if !name_ok {
+        return Err(format!(
+            "origin {} must be a scheme and host, with no path",
+            origin
+        ));
+    }
+    if !port.is_empty() && port.parse::<u16>().is_err() {
+        return Err(format!("origin {} has an invalid port", origin));
+    }
+    // A wildcard must stay under one registrable domain, or `https://*`
+    // would allow every site while dodging the credentials check on `*`
+    if let Some((_, suffix)) = name.rsplit_once('*') {
+        let labels: Vec<&str> = suffix.split('.').skip(1).collect();
+        if !suffix.starts_with('.') || labels.len() < 2 || labels.contains(&"") {
+            return Err(format!(
+                "origin {} must end its wildcard in a domain, as in https://*.example.com",
+                origin
+            ));
+        }
+    }
+    Ok(())
+}
+
+// `https://*.example.com` as an anchored regex
+fn pattern_regex(pattern: &str) -> String {
+    let escaped = pattern.replace('.', "\\.").replace('*', WILDCARD_PATTERN);
+    format!("^{}$", escaped)
+}
+
+pub fn parse_origins(value: &str) -> Result<Origins, String> {
+    let origins: Vec<String> = value
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_lowercase())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(Origins::Any);
+    }
+    if origins.is_empty() {
+        return Err("at least one origin is needed".to_string());
+    }
+
+    let mut exact = Vec::new();
+    let mut patterns = Vec::new();
+    for origin in origins {
+        check_origin(&origin)?;
+        if origin.contains('*') {
+            patterns.push(origin);
+        } else {
+            exact.push(origin);
+        }
+    }
+    Ok(Origins::Some { exact, patterns })
+}
+
+pub fn parse_methods(value: &str) -> Result<Vec<Method>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|method| !method.is_empty())
+        .map(|method| {
+            Method::from_str(&method.to_uppercase())
+                .map_err(|_| format!("{} is not an HTTP method", method))
+        })
+        .collect()
+}
+
+impl CorsSettings {
+    pub fn fairing(&self) -> rocket_cors::Cors {
+        let allowed_origins = match &self.origins {
+            Origins::Any => AllowedOrigins::all(),
+            Origins::Some { exact, patterns } => {
+                let regexes: Vec<String> = patterns.iter().map(|p| pattern_regex(p)).collect();
+                AllowedOrigins::some(exact, &regexes)
+            }
+        };
+
+        CorsOptions {
+            allowed_origins,
+            allowed_methods: self.methods.iter().copied().map(From::from).collect(),
+            allow_credentials: self.allow_credentials,
+            max_age: self.max_age_secs,
+            // Let cross-origin clients read their quota and request id
+            expose_headers: [
+                request_id::HEADER,
+                rate_limit::LIMIT_HEADER,
+                rate_limit::REMAINING_HEADER,
+                rate_limit::RESET_HEADER,
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            ..Default::default()
+        }
+        .to_cors()
+        .expect("Failed to create CORS options")
+    }
+}
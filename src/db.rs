@@ -0,0 +1,308 @@
+use mysql::*;
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::{json, Json, Value};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::chaos;
+use crate::config::AppConfig;
+use crate::error::add_error_response;
+use crate::metrics::{header, Histogram};
+use crate::request_id;
+
+// Seconds clients are told to wait before retrying a 503
+pub const RETRY_AFTER_SECS: u64 = 1;
+
+// How long a request may wait for a pooled connection, by route name
+#[derive(Clone)]
+pub struct AcquireTimeouts {
+    pub default: Duration,
+    pub per_route: HashMap<String, Duration>,
+}
+
+impl AcquireTimeouts {
+    fn for_route(&self, route: &str) -> Duration {
+        self.per_route.get(route).copied().unwrap_or(self.default)
+    }
+}
+
+// Database connection pool shared by all requests
+//
+// `Pool` is already a cheap, thread-safe handle, so it is used without a lock.
+// Clones share the pool and its counters.
+#[derive(Clone)]
+pub struct DbConnPool {
+    pub pool: Pool,
+    // Most connections the pool opens at once
+    pub max_connections: usize,
+    timeouts: AcquireTimeouts,
+    stats: Arc<DbStats>,
+}
+
+// Counters exposed on /metrics, shared with the connections checked out
+#[derive(Default)]
+struct DbStats {
+    // Acquire timeouts per route name
+    timed_out: Mutex<BTreeMap<String, u64>>,
+    // Connections currently held by request handlers
+    in_use: AtomicUsize,
+    // Time spent in `DbConn::run` per route name
+    queries: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl DbConnPool {
+    pub fn new(pool: Pool, max_connections: usize, timeouts: AcquireTimeouts) -> Self {
+        DbConnPool {
+            pool,
+            max_connections,
+            timeouts,
+            stats: Arc::default(),
+        }
+    }
+
+    // Check out a connection, giving up after the route's acquire timeout.
+    // Waiting happens on the blocking thread pool so other requests keep going.
+    pub async fn get_conn(&self, route: &str) -> Result<PooledConn> {
+        let pool = self.pool.clone();
+        let timeout = self.timeouts.for_route(route);
+        let result = rocket::tokio::task::spawn_blocking(move || pool.try_get_conn(timeout))
+            .await
+            .expect("connection checkout panicked");
+
+        if let Err(Error::DriverError(DriverError::Timeout)) = result {
+            *self
+                .stats
+                .timed_out
+                .lock()
+                .unwrap()
+                .entry(route.to_string())
+                .or_default() += 1;
+        }
+
+        result
+    }
+
+    // Check out a connection for `route`, counted and timed like the ones
+    // handlers get through the DbConn guard
+    pub async fn checkout(&self, route: &str) -> Result<DbConn> {
+        let conn = self.get_conn(route).await?;
+        self.stats.in_use.fetch_add(1, Ordering::Relaxed);
+
+        Ok(DbConn {
+            conn: Some(conn),
+            route: route.to_string(),
+            stats: self.stats.clone(),
+        })
+    }
+
+    // Acquire timeouts across all routes
+    pub fn acquire_timeouts(&self) -> u64 {
+        self.stats.timed_out.lock().unwrap().values().sum()
+    }
+
+    // Prometheus text exposition of the pool's counters
+    pub fn render_metrics(&self, out: &mut String) {
+        header(
+            out,
+            "db_acquire_timeouts_total",
+            "counter",
+            "Requests that gave up waiting for a database connection.",
+        );
+        for (route, count) in self.stats.timed_out.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "db_acquire_timeouts_total{{route=\"{}\"}} {}",
+                route, count
+            );
+        }
+
+        header(
+            out,
+            "db_pool_connections_in_use",
+            "gauge",
+            "Database connections held by request handlers.",
+        );
+        let _ = writeln!(
+            out,
+            "db_pool_connections_in_use {}",
+            self.stats.in_use.load(Ordering::Relaxed)
+        );
+        header(
+            out,
+            "db_pool_max_connections",
+            "gauge",
+            "Most connections the pool opens at once.",
+        );
+        let _ = writeln!(out, "db_pool_max_connections {}", self.max_connections);
+
+        header(
+            out,
+            "db_query_duration_seconds",
+            "histogram",
+            "Time request handlers spent running queries.",
+        );
+        for (route, histogram) in self.stats.queries.lock().unwrap().iter() {
+            let labels = format!("route=\"{}\"", route);
+            histogram.render(out, "db_query_duration_seconds", &labels);
+        }
+    }
+}
+
+// Function to create a new database pool
+pub fn init_pool(config: &AppConfig) -> DbConnPool {
+    let opts = todo_core::opts_from_url(&config.database_url).expect("Invalid database URL");
+    let query_timeout = Some(config.query_timeout);
+
+    // The socket timeouts make a stuck connection fail instead of hanging the
+    // request forever
+    let opts = opts
+        .tcp_connect_timeout(query_timeout)
+        .read_timeout(query_timeout)
+        .write_timeout(query_timeout);
+
+    // DB_POOL_SIZE caps the pool, which otherwise follows the URL's pool_max
+    let pool_opts = Opts::from(opts.clone()).get_pool_opts().clone();
+    let pool_opts = match config.db_pool_size {
+        Some(size) => {
+            let min = pool_opts.constraints().min().min(size);
+            let constraints = PoolConstraints::new(min, size).expect("min is at most max");
+            pool_opts.with_constraints(constraints)
+        }
+        None => pool_opts,
+    };
+    let max_connections = pool_opts.constraints().max();
+    let opts = opts.pool_opts(pool_opts);
+
+    DbConnPool::new(
+        Pool::new(opts).expect("Failed to create database pool"),
+        max_connections,
+        config.acquire_timeouts.clone(),
+    )
+}
+
+// A pooled connection checked out for the current request
+//
+// Fails the request with 503 when no connection frees up within the route's
+// acquire timeout. Queries go through `run`, which keeps the blocking driver
+// off the async workers.
+pub struct DbConn {
+    conn: Option<PooledConn>,
+    route: String,
+    stats: Arc<DbStats>,
+}
+
+impl DbConn {
+    // Run `f` with the connection on the blocking thread pool
+    pub async fn run<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut PooledConn) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut conn = self
+            .conn
+            .take()
+            .expect("connection used after a query panicked");
+
+        let started = Instant::now();
+        let (conn, result) = rocket::tokio::task::spawn_blocking(move || {
+            let result = f(&mut conn);
+            (conn, result)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        self.stats
+            .queries
+            .lock()
+            .unwrap()
+            .entry(self.route.clone())
+            .or_default()
+            .observe(started.elapsed());
+
+        self.conn = Some(conn);
+        result
+    }
+}
+
+impl Drop for DbConn {
+    fn drop(&mut self) {
+        self.stats.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DbConn {
+    type Error = Error;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let db = req
+            .rocket()
+            .state::<DbConnPool>()
+            .expect("DbConnPool must be managed");
+        let route = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or("unknown");
+
+        if chaos::fails_database(req) {
+            let e = Error::IoError(std::io::Error::other("injected by chaos mode"));
+            return Outcome::Error((Status::ServiceUnavailable, e));
+        }
+
+        match db.checkout(route).await {
+            Ok(conn) => Outcome::Success(conn),
+            Err(e) => {
+                tracing::warn!(
+                    request_id = request_id::of(req),
+                    route,
+                    error = %e,
+                    "No database connection"
+                );
+                Outcome::Error((Status::ServiceUnavailable, e))
+            }
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for DbConn {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+
+    fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_error_response(gen, &mut responses, 503)?;
+        Ok(responses)
+    }
+}
+
+// 503 body with a Retry-After hint
+#[derive(Responder)]
+#[response(status = 503, content_type = "json")]
+pub struct Unavailable {
+    body: Json<Value>,
+    retry_after: Header<'static>,
+}
+
+#[catch(503)]
+pub fn service_unavailable(req: &Request<'_>) -> Unavailable {
+    Unavailable {
+        body: Json(json!({
+            "status": 503,
+            "code": "database_unavailable",
+            "message": "Database unavailable, try again shortly",
+            "request_id": request_id::of(req),
+        })),
+        retry_after: Header::new("Retry-After", RETRY_AFTER_SECS.to_string()),
+    }
+}
@@ -0,0 +1,183 @@
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::Serialize;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::{add_default_response_schema, add_schema_response};
+use schemars::JsonSchema;
+use todo_core::validation::FieldError;
+
+use crate::db::RETRY_AFTER_SECS;
+use crate::request_id;
+
+// Errors a handler can end with, reported as
+// `{ "status": 404, "code": "not_found", "message": "task not found" }`
+#[derive(Debug)]
+pub enum ApiError {
+    // A query failed; details are logged rather than sent to the client
+    Database(mysql::Error),
+    // No database connection could be had in time
+    Unavailable(mysql::Error),
+    // The named resource does not exist
+    NotFound(&'static str),
+    // The request was well-formed but cannot be carried out
+    BadRequest(String),
+    // The body parsed but fails a check beyond the payload itself, e.g.
+    // against the stored data
+    Invalid(Vec<FieldError>),
+    // The caller could not be authenticated
+    Unauthorized(&'static str),
+    // The request clashes with existing data, e.g. a taken email
+    Conflict(String),
+    // Something else went wrong on our side
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> Status {
+        match self {
+            ApiError::Database(_) | ApiError::Internal(_) => Status::InternalServerError,
+            ApiError::Unavailable(_) => Status::ServiceUnavailable,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Invalid(_) => Status::UnprocessableEntity,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::Conflict(_) => Status::Conflict,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Database(_) => "database_error",
+            ApiError::Unavailable(_) => "database_unavailable",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Invalid(_) => "invalid_body",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::Database(_) => "The database could not complete the request".to_string(),
+            ApiError::Unavailable(_) => "Database unavailable, try again shortly".to_string(),
+            ApiError::NotFound(what) => format!("{} not found", what),
+            ApiError::BadRequest(message) | ApiError::Conflict(message) => message.clone(),
+            ApiError::Invalid(_) => "Unprocessable Entity".to_string(),
+            ApiError::Unauthorized(message) => message.to_string(),
+            ApiError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+
+    // The JSON body the error is reported with
+    pub fn body(&self) -> Value {
+        json!(ErrorBody {
+            status: self.status().code,
+            code: self.code(),
+            message: self.message(),
+            errors: match self {
+                ApiError::Invalid(errors) => Some(errors.clone()),
+                _ => None,
+            },
+            request_id: None,
+        })
+    }
+}
+
+// Every error body; `errors` is only present when a request body failed
+// validation, and `request_id` is the X-Request-Id of the request
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct ErrorBody {
+    status: u16,
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+// MySQL's error code for a duplicate unique key
+const ER_DUP_ENTRY: u16 = 1062;
+
+// Whether a write failed because it would duplicate a unique key
+pub fn is_duplicate(e: &mysql::Error) -> bool {
+    matches!(e, mysql::Error::MySqlError(e) if e.code == ER_DUP_ENTRY)
+}
+
+impl From<mysql::Error> for ApiError {
+    fn from(e: mysql::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let id = request_id::of(req);
+        let route = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or("unknown");
+        match &self {
+            ApiError::Database(e) => {
+                tracing::error!(request_id = id, route, error = %e, "Database error")
+            }
+            ApiError::Unavailable(e) => {
+                tracing::warn!(request_id = id, route, error = %e, "No database connection")
+            }
+            ApiError::Internal(e) => {
+                tracing::error!(request_id = id, route, error = %e, "Internal error")
+            }
+            _ => {}
+        }
+
+        let mut body = self.body();
+        body["request_id"] = id.into();
+        let mut response = Response::build_from(Json(body).respond_to(req)?);
+        response.status(self.status());
+        match self {
+            ApiError::Unauthorized(_) => {
+                response.header(Header::new("WWW-Authenticate", "Bearer"));
+            }
+            ApiError::Unavailable(_) => {
+                response.header(Header::new("Retry-After", RETRY_AFTER_SECS.to_string()));
+            }
+            _ => {}
+        }
+
+        response.ok()
+    }
+}
+
+impl OpenApiResponderInner for ApiError {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_default_response_schema(
+            &mut responses,
+            "application/json",
+            gen.json_schema::<ErrorBody>(),
+        );
+        Ok(responses)
+    }
+}
+
+// Document that a request can end with `status` and the usual error body,
+// for guards that fail requests themselves
+pub fn add_error_response(
+    gen: &mut OpenApiGenerator,
+    responses: &mut Responses,
+    status: u16,
+) -> rocket_okapi::Result<()> {
+    add_schema_response(
+        responses,
+        status,
+        "application/json",
+        gen.json_schema::<ErrorBody>(),
+    )
+}
@@ -0,0 +1,135 @@
+use rocket::futures::Stream;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::{json, Value};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Shutdown, State};
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+
+use todo_core::models::Task;
+
+use crate::auth::AuthenticatedUser;
+
+// Events a slow client can fall behind by before it has to resync
+const CHANNEL_CAPACITY: usize = 256;
+
+// A change to one of a user's tasks, made through the API
+#[derive(Clone)]
+pub enum TaskEvent {
+    Created(Task),
+    // `completed` is set when this change is the one that marked it done
+    Updated { task: Task, completed: bool },
+    Deleted(u32),
+}
+
+impl TaskEvent {
+    // The event as a WebSocket message body
+    pub fn to_json(&self) -> Value {
+        match self {
+            TaskEvent::Created(task) => json!({ "type": "created", "task": task }),
+            TaskEvent::Updated { task, .. } => json!({ "type": "updated", "task": task }),
+            TaskEvent::Deleted(id) => json!({ "type": "deleted", "id": id }),
+        }
+    }
+
+    fn to_sse(&self) -> Event {
+        match self {
+            TaskEvent::Created(task) => Event::json(task).event("created"),
+            TaskEvent::Updated { task, .. } => Event::json(task).event("updated"),
+            TaskEvent::Deleted(id) => Event::json(&json!({ "id": id })).event("deleted"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Published {
+    pub user_id: u32,
+    // The /ws socket the change was made through, which already knows
+    pub origin: Option<u64>,
+    pub event: TaskEvent,
+}
+
+// Fan-out of task changes from the mutation handlers to every open
+// /tasks/events stream and /ws socket
+pub struct TaskEvents {
+    sender: broadcast::Sender<Published>,
+}
+
+impl Default for TaskEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        TaskEvents { sender }
+    }
+}
+
+impl TaskEvents {
+    pub fn publish(&self, user_id: u32, event: TaskEvent) {
+        self.send(Published {
+            user_id,
+            origin: None,
+            event,
+        });
+    }
+
+    // Publish a change made through the socket `origin`
+    pub fn publish_from(&self, user_id: u32, origin: u64, event: TaskEvent) {
+        self.send(Published {
+            user_id,
+            origin: Some(origin),
+            event,
+        });
+    }
+
+    fn send(&self, published: Published) {
+        // Sending only fails when nobody is listening
+        let _ = self.sender.send(published);
+    }
+
+    // Changes published from now on, by every user
+    pub fn subscribe(&self) -> broadcast::Receiver<Published> {
+        self.sender.subscribe()
+    }
+}
+
+// Live changes to the user's tasks as server-sent events: `created` and
+// `updated` carry the task, `deleted` its id. Deleting a task sends one
+// `deleted` per task moved to the trash, subtasks included; restoring one
+// sends `created` for the restored task only. Scheduled and imported tasks
+// aren't streamed.
+//
+// A client that falls too far behind gets a `resync` event and should
+// reload the list.
+#[openapi(tag = "Sync")]
+#[get("/tasks/events")]
+fn task_events(
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    mut shutdown: Shutdown,
+) -> EventStream<impl Stream<Item = Event>> {
+    let mut receiver = events.subscribe();
+
+    EventStream! {
+        loop {
+            let published = select! {
+                published = receiver.recv() => match published {
+                    Ok(published) => published,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => {
+                        yield Event::empty().event("resync");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if published.user_id == user.id {
+                yield published.event.to_sse();
+            }
+        }
+    }
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![task_events]
+}
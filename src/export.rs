@@ -0,0 +1,344 @@
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rocket::futures::stream;
+use rocket::http::{ContentType, Header};
+use rocket::request::Request;
+use rocket::response::stream::ReaderStream;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::sync::mpsc;
+use rocket::State;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, OpenApi, Responses};
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::{add_content_response, produce_any_responses};
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+use std::collections::HashSet;
+use std::io::{self, BufWriter, Cursor, Write};
+
+use mysql::{PooledConn, TxOpts};
+use todo_core::clock::SharedClock;
+use todo_core::models::{Holiday, Schedule, Task, TaskList};
+use todo_core::repository;
+use todo_core::validation::{FieldError, Validate};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::guards::ValidatedJson;
+use crate::lists::check_list;
+use crate::subtasks::check_parent;
+
+// Chunks waiting to be sent before the archive builder blocks
+const CHUNKS_IN_FLIGHT: usize = 8;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Everything needed to rebuild a user's data
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Backup {
+    lists: Vec<TaskList>,
+    tasks: Vec<Task>,
+    schedules: Vec<Schedule>,
+    holidays: Vec<Holiday>,
+}
+
+impl Backup {
+    fn load(conn: &mut PooledConn, user_id: u32) -> mysql::Result<Self> {
+        Ok(Backup {
+            lists: repository::lists::list(conn, user_id)?,
+            tasks: repository::tasks::list(conn, user_id)?,
+            schedules: repository::schedules::list(conn, user_id)?,
+            holidays: repository::holidays::list(conn, user_id)?,
+        })
+    }
+
+    // One JSON file per table
+    fn files(&self) -> serde_json::Result<[(&'static str, Vec<u8>); 4]> {
+        Ok([
+            ("lists.json", serde_json::to_vec_pretty(&self.lists)?),
+            ("tasks.json", serde_json::to_vec_pretty(&self.tasks)?),
+            (
+                "schedules.json",
+                serde_json::to_vec_pretty(&self.schedules)?,
+            ),
+            ("holidays.json", serde_json::to_vec_pretty(&self.holidays)?),
+        ])
+    }
+}
+
+#[derive(FromFormField, JsonSchema, Clone, Copy)]
+enum Archive {
+    #[field(value = "zip")]
+    #[schemars(rename = "zip")]
+    Zip,
+    #[field(value = "tar.gz")]
+    #[schemars(rename = "tar.gz")]
+    TarGz,
+}
+
+impl Archive {
+    fn extension(self) -> &'static str {
+        match self {
+            Archive::Zip => "zip",
+            Archive::TarGz => "tar.gz",
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            Archive::Zip => ContentType::ZIP,
+            Archive::TarGz => ContentType::new("application", "gzip"),
+        }
+    }
+
+    fn write(self, files: &[(&str, Vec<u8>)], out: impl Write) -> io::Result<()> {
+        match self {
+            Archive::TarGz => {
+                let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+                for (name, contents) in files {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_size(contents.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_cksum();
+                    tar.append_data(&mut header, name, contents.as_slice())?;
+                }
+                tar.into_inner()?.finish()?.flush()
+            }
+            // Zip writes its central directory by seeking back, so the archive
+            // is assembled in memory and sent as one chunk
+            Archive::Zip => {
+                let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated);
+                for (name, contents) in files {
+                    zip.start_file(*name, options)?;
+                    zip.write_all(contents)?;
+                }
+                let mut out = out;
+                out.write_all(&zip.finish()?.into_inner())?;
+                out.flush()
+            }
+        }
+    }
+}
+
+// Hands written bytes to the response stream as they are produced
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// An archive streamed to the client while a blocking task builds it
+struct Archived {
+    archive: Archive,
+    chunks: mpsc::Receiver<Vec<u8>>,
+}
+
+impl Archived {
+    fn build(archive: Archive, files: [(&'static str, Vec<u8>); 4]) -> Self {
+        let (tx, chunks) = mpsc::channel(CHUNKS_IN_FLIGHT);
+
+        rocket::tokio::task::spawn_blocking(move || {
+            let out = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter(tx));
+            if let Err(e) = archive.write(&files, out) {
+                tracing::error!(error = %e, "Export archive failed");
+            }
+        });
+
+        Archived { archive, chunks }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Archived {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let chunks = stream::unfold(self.chunks, |mut chunks| async move {
+            chunks
+                .recv()
+                .await
+                .map(|chunk| (Cursor::new(chunk), chunks))
+        });
+        let disposition = format!(
+            "attachment; filename=\"todo-export.{}\"",
+            self.archive.extension()
+        );
+
+        Response::build()
+            .header(self.archive.content_type())
+            .header(Header::new("Content-Disposition", disposition))
+            .streamed_body(ReaderStream::from(chunks))
+            .ok()
+    }
+}
+
+impl OpenApiResponderInner for Archived {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        for archive in [Archive::Zip, Archive::TarGz] {
+            let media = MediaType {
+                schema: Some(gen.json_schema::<Vec<u8>>()),
+                ..MediaType::default()
+            };
+            add_content_response(&mut responses, 200, archive.content_type(), media)?;
+        }
+        Ok(responses)
+    }
+}
+
+#[derive(Responder)]
+enum Export {
+    Json(Json<Backup>),
+    Archive(Archived),
+}
+
+impl OpenApiResponderInner for Export {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        produce_any_responses(Json::<Backup>::responses(gen)?, Archived::responses(gen)?)
+    }
+}
+
+// Full backup as JSON, or with `?archive=zip` / `?archive=tar.gz` as a
+// compressed archive holding one JSON file per table
+#[openapi(tag = "Export")]
+#[get("/export?<archive>")]
+async fn export(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    archive: Option<Archive>,
+) -> Result<Export, ApiError> {
+    let backup = conn.run(move |c| Backup::load(c, user.id)).await?;
+
+    match archive {
+        None => Ok(Export::Json(Json(backup))),
+        Some(archive) => {
+            let files = backup
+                .files()
+                .map_err(|e| ApiError::Internal(e.to_string()))?;
+            Ok(Export::Archive(Archived::build(archive, files)))
+        }
+    }
+}
+
+// Tasks to import, in the shape `/export` produces. Other sections of an
+// export are accepted and ignored, so a task's `list_id` and `parent_id` must
+// name one of the user's existing lists and tasks.
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Import {
+    tasks: Vec<Task>,
+}
+
+impl Validate for Import {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (i, task) in self.tasks.iter().enumerate() {
+            if let Err(task_errors) = task.validate() {
+                errors.extend(task_errors.into_iter().map(|e| {
+                    FieldError::new("tasks", format!("task {}: {} {}", i, e.field, e.message))
+                }));
+            }
+            if let Some(external_id) = &task.external_id {
+                if !seen.insert(external_id) {
+                    errors.push(FieldError::new(
+                        "tasks",
+                        format!(
+                            "task {}: external_id {} appears more than once",
+                            i, external_id
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Imported {
+    created: usize,
+    updated: usize,
+}
+
+// Update the user's tasks whose external id is already known and insert the
+// rest, all or nothing. Known tasks in the trash are restored first. Ids in
+// the payload are ignored.
+fn import_tasks(
+    conn: &mut PooledConn,
+    user_id: u32,
+    tasks: &[Task],
+    now: DateTime<Utc>,
+) -> Result<Imported, ApiError> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    let mut imported = Imported {
+        created: 0,
+        updated: 0,
+    };
+
+    for task in tasks {
+        check_list(&mut tx, user_id, task.list_id)?;
+        let existing = match &task.external_id {
+            Some(external_id) => {
+                repository::tasks::restore_by_external_id(&mut tx, user_id, external_id)?;
+                repository::tasks::find_by_external_id(&mut tx, user_id, external_id)?
+            }
+            None => None,
+        };
+        let existing = existing.and_then(|existing| existing.id);
+        check_parent(&mut tx, user_id, existing, task.parent_id)?;
+
+        match existing {
+            Some(id) => {
+                repository::tasks::update(&mut tx, user_id, id, task)?;
+                imported.updated += 1;
+            }
+            None => {
+                repository::tasks::insert(&mut tx, user_id, task, now)?;
+                imported.created += 1;
+            }
+        }
+    }
+
+    tx.commit()?;
+    Ok(imported)
+}
+
+#[openapi(tag = "Export")]
+#[post("/import", format = "json", data = "<import>")]
+async fn import(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    import: ValidatedJson<Import>,
+) -> Result<Json<Imported>, ApiError> {
+    let tasks = import.into_inner().tasks;
+    let now = clock.now();
+    Ok(Json(
+        conn.run(move |c| import_tasks(c, user.id, &tasks, now))
+            .await?,
+    ))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![export, import]
+}
@@ -0,0 +1,217 @@
+use chrono::Duration as ChronoDuration;
+use mysql::prelude::Queryable;
+use rocket::fairing::AdHoc;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use std::time::Duration;
+
+use todo_core::clock::SharedClock;
+use todo_core::models::{Goal, GoalProgressPoint};
+use todo_core::{goals, repository};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::{DbConn, DbConnPool};
+use crate::error::ApiError;
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+use crate::lists::check_list;
+use crate::schedules::parse_range;
+
+// How often every goal's progress is read for its history
+const RECORD_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Days of history GET /goals/<id>/progress covers by default, about a quarter
+const DEFAULT_HISTORY_DAYS: u32 = 92;
+
+// Goals may only link the user's own tasks and lists
+fn check_links<Q: Queryable>(conn: &mut Q, user_id: u32, goal: &Goal) -> Result<(), ApiError> {
+    for &task_id in &goal.task_ids {
+        if repository::tasks::parent_id(conn, user_id, task_id)?.is_none() {
+            return Err(ApiError::BadRequest(format!(
+                "task {} does not exist",
+                task_id
+            )));
+        }
+    }
+    for &list_id in &goal.list_ids {
+        check_list(conn, user_id, Some(list_id))?;
+    }
+    Ok(())
+}
+
+// Goal routes
+
+#[openapi(tag = "Goals")]
+#[get("/goals")]
+async fn list_goals(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+) -> Result<Listing<Goal>, ApiError> {
+    let now = clock.now();
+    let goals = conn
+        .run(move |c| {
+            repository::goals::list(c, user.id)?
+                .into_iter()
+                .map(|goal| goals::with_progress(c, user.id, goal, now))
+                .collect::<mysql::Result<Vec<_>>>()
+        })
+        .await?;
+
+    Ok(Listing::all(goals))
+}
+
+#[openapi(tag = "Goals")]
+#[get("/goals/<goal_id>")]
+async fn get_goal(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    goal_id: u32,
+) -> Result<Json<Goal>, ApiError> {
+    let now = clock.now();
+    conn.run(move |c| -> Result<_, ApiError> {
+        let goal =
+            repository::goals::find(c, user.id, goal_id)?.ok_or(ApiError::NotFound("goal"))?;
+        Ok(goals::with_progress(c, user.id, goal, now)?)
+    })
+    .await
+    .map(Json)
+}
+
+#[openapi(tag = "Goals")]
+#[post("/goals", format = "json", data = "<goal>")]
+async fn create_goal(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    goal: ValidatedJson<Goal>,
+) -> Result<status::Created<Json<Goal>>, ApiError> {
+    let now = clock.now();
+    let mut goal = goal.into_inner();
+    goal.created_at = Some(now);
+
+    let goal = conn
+        .run(move |c| -> Result<_, ApiError> {
+            check_links(c, user.id, &goal)?;
+            let id = repository::goals::insert(c, user.id, &goal)?;
+            let goal =
+                repository::goals::find(c, user.id, id)?.ok_or(ApiError::NotFound("goal"))?;
+            Ok(goals::with_progress(c, user.id, goal, now)?)
+        })
+        .await?;
+
+    let location = format!("/goals/{}", goal.id.unwrap_or_default());
+    Ok(status::Created::new(location).body(Json(goal)))
+}
+
+#[openapi(tag = "Goals")]
+#[put("/goals/<goal_id>", format = "json", data = "<goal>")]
+async fn update_goal(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    goal_id: u32,
+    goal: ValidatedJson<Goal>,
+) -> Result<Json<Goal>, ApiError> {
+    let now = clock.now();
+    let goal = goal.into_inner();
+    conn.run(move |c| -> Result<_, ApiError> {
+        check_links(c, user.id, &goal)?;
+        if !repository::goals::update(c, user.id, goal_id, &goal)? {
+            return Err(ApiError::NotFound("goal"));
+        }
+        let goal =
+            repository::goals::find(c, user.id, goal_id)?.ok_or(ApiError::NotFound("goal"))?;
+        Ok(goals::with_progress(c, user.id, goal, now)?)
+    })
+    .await
+    .map(Json)
+}
+
+#[openapi(tag = "Goals")]
+#[delete("/goals/<goal_id>")]
+async fn delete_goal(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    goal_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::goals::delete(c, user.id, goal_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+// A goal's progress day by day over the last `range` days, oldest first and
+// ending with today, e.g. GET /goals/3/progress?range=90d
+#[openapi(tag = "Goals")]
+#[get("/goals/<goal_id>/progress?<range>")]
+async fn goal_progress(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    goal_id: u32,
+    range: Option<&str>,
+) -> Result<Listing<GoalProgressPoint>, ApiError> {
+    let days = parse_range(range, DEFAULT_HISTORY_DAYS)?;
+    let now = clock.now();
+    let since = now - ChronoDuration::days(days.into());
+
+    let points = conn
+        .run(move |c| -> Result<_, ApiError> {
+            repository::goals::find(c, user.id, goal_id)?.ok_or(ApiError::NotFound("goal"))?;
+            Ok(goals::history(c, user.id, goal_id, since, now)?)
+        })
+        .await?;
+
+    Ok(Listing::all(points))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        list_goals,
+        get_goal,
+        create_goal,
+        update_goal,
+        delete_goal,
+        goal_progress
+    ]
+}
+
+// Background job reading every goal's progress, so its history has a point
+// for each day
+pub fn recorder() -> AdHoc {
+    AdHoc::on_liftoff("Goal progress recorder", |rocket| {
+        Box::pin(async move {
+            let pool = rocket
+                .state::<DbConnPool>()
+                .expect("DbConnPool must be managed")
+                .pool
+                .clone();
+            let clock = rocket
+                .state::<SharedClock>()
+                .expect("SharedClock must be managed")
+                .clone();
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(RECORD_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let pool = pool.clone();
+                    let now = clock.now();
+                    let result =
+                        rocket::tokio::task::spawn_blocking(move || goals::record_all(&pool, now))
+                            .await;
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => tracing::error!(error = %e, "Goal progress recording failed"),
+                        Err(e) => tracing::error!(error = %e, "Goal progress recording panicked"),
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,94 @@
+use rocket::data::{self, Data, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::Request;
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::Deserialize;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::RequestBody;
+use rocket_okapi::request::OpenApiFromData;
+use schemars::JsonSchema;
+use std::ops::Deref;
+use todo_core::validation::{FieldError, Validate};
+
+use crate::request_id;
+
+// Errors from the body guard, kept for the catcher to report
+struct BodyErrors(Vec<FieldError>);
+
+// A JSON body that parsed and passed `Validate`
+//
+// Parse and validation failures end the request with 422 (or 400 for bodies
+// that could not be read), and `invalid_body` reports the field errors.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn fail<'r, T>(
+    req: &'r Request<'_>,
+    status: Status,
+    errors: Vec<FieldError>,
+) -> data::Outcome<'r, T, Vec<FieldError>> {
+    req.local_cache(|| BodyErrors(errors.clone()));
+    Outcome::Error((status, errors))
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r> + Validate> FromData<'r> for ValidatedJson<T> {
+    type Error = Vec<FieldError>;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        match Json::<T>::from_data(req, data).await {
+            Outcome::Success(Json(value)) => match value.validate() {
+                Ok(()) => Outcome::Success(ValidatedJson(value)),
+                Err(errors) => fail(req, Status::UnprocessableEntity, errors),
+            },
+            Outcome::Error((status, e)) => {
+                fail(req, status, vec![FieldError::new("body", e.to_string())])
+            }
+            Outcome::Forward(forward) => Outcome::Forward(forward),
+        }
+    }
+}
+
+// Documented as the plain JSON body it parses
+impl<'r, T: Deserialize<'r> + Validate + JsonSchema> OpenApiFromData<'r> for ValidatedJson<T> {
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        Json::<T>::request_body(gen)
+    }
+}
+
+// JSON body for 400 and 422, listing field errors when a body guard left some
+#[catch(400)]
+pub fn bad_request(req: &Request) -> Json<Value> {
+    invalid_body(Status::BadRequest, "bad_request", req)
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(req: &Request) -> Json<Value> {
+    invalid_body(Status::UnprocessableEntity, "invalid_body", req)
+}
+
+fn invalid_body(status: Status, code: &str, req: &Request) -> Json<Value> {
+    let errors = &req.local_cache(|| BodyErrors(Vec::new())).0;
+
+    Json(json!({
+        "status": status.code,
+        "code": code,
+        "message": status.reason().unwrap_or("Invalid request"),
+        "errors": errors,
+        "request_id": request_id::of(req),
+    }))
+}
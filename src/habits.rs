@@ -0,0 +1,182 @@
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+
+use todo_core::clock::SharedClock;
+use todo_core::models::{Habit, HabitProgress};
+use todo_core::{habits, repository};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+
+// Habits
+//
+// A habit is a target like "3 times per week" rather than a task to finish.
+// POST /habits/<id>/check-ins records doing it once; adherence and streaks
+// are worked out from the check-ins whenever progress is asked for, and GET
+// /views/habits shows every habit's progress at once.
+
+// Habit routes
+
+#[openapi(tag = "Habits")]
+#[get("/habits")]
+async fn list_habits(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<Listing<Habit>, ApiError> {
+    let habits = conn
+        .run(move |c| repository::habits::list(c, user.id))
+        .await?;
+
+    Ok(Listing::all(habits))
+}
+
+#[openapi(tag = "Habits")]
+#[get("/habits/<habit_id>")]
+async fn get_habit(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    habit_id: u32,
+) -> Result<Json<HabitProgress>, ApiError> {
+    let now = clock.now();
+    conn.run(move |c| -> Result<_, ApiError> {
+        let habit =
+            repository::habits::find(c, user.id, habit_id)?.ok_or(ApiError::NotFound("habit"))?;
+        Ok(habits::load_progress(c, habit, now)?)
+    })
+    .await
+    .map(Json)
+}
+
+#[openapi(tag = "Habits")]
+#[post("/habits", format = "json", data = "<habit>")]
+async fn create_habit(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    habit: ValidatedJson<Habit>,
+) -> Result<status::Created<Json<Habit>>, ApiError> {
+    let mut new_habit = habit.into_inner();
+    new_habit.created_at = Some(clock.now());
+    let row = new_habit.clone();
+    let last_id = conn
+        .run(move |c| repository::habits::insert(c, user.id, &row))
+        .await?;
+    new_habit.id = Some(last_id);
+
+    Ok(status::Created::new(format!("/habits/{}", last_id)).body(Json(new_habit)))
+}
+
+#[openapi(tag = "Habits")]
+#[put("/habits/<habit_id>", format = "json", data = "<habit>")]
+async fn update_habit(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    habit_id: u32,
+    habit: ValidatedJson<Habit>,
+) -> Result<Json<Habit>, ApiError> {
+    let habit = habit.into_inner();
+    conn.run(move |c| -> Result<_, ApiError> {
+        if !repository::habits::update(c, user.id, habit_id, &habit)? {
+            return Err(ApiError::NotFound("habit"));
+        }
+        repository::habits::find(c, user.id, habit_id)?.ok_or(ApiError::NotFound("habit"))
+    })
+    .await
+    .map(Json)
+}
+
+#[openapi(tag = "Habits")]
+#[delete("/habits/<habit_id>")]
+async fn delete_habit(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    habit_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::habits::delete(c, user.id, habit_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+// Check-in routes
+
+#[openapi(tag = "Habits")]
+#[post("/habits/<habit_id>/check-ins")]
+async fn check_in(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    habit_id: u32,
+) -> Result<Json<HabitProgress>, ApiError> {
+    let now = clock.now();
+    conn.run(move |c| -> Result<_, ApiError> {
+        let habit =
+            repository::habits::find(c, user.id, habit_id)?.ok_or(ApiError::NotFound("habit"))?;
+        repository::habits::check_in(c, habit_id, now.naive_utc())?;
+        Ok(habits::load_progress(c, habit, now)?)
+    })
+    .await
+    .map(Json)
+}
+
+// Take back the latest check-in, e.g. one made by mistake
+#[openapi(tag = "Habits")]
+#[delete("/habits/<habit_id>/check-ins/latest")]
+async fn undo_check_in(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    habit_id: u32,
+) -> Result<Json<HabitProgress>, ApiError> {
+    let now = clock.now();
+    conn.run(move |c| -> Result<_, ApiError> {
+        let habit =
+            repository::habits::find(c, user.id, habit_id)?.ok_or(ApiError::NotFound("habit"))?;
+        if !repository::habits::undo_check_in(c, habit_id)? {
+            return Err(ApiError::NotFound("check-in"));
+        }
+        Ok(habits::load_progress(c, habit, now)?)
+    })
+    .await
+    .map(Json)
+}
+
+#[openapi(tag = "Habits")]
+#[get("/views/habits")]
+async fn habits_view(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+) -> Result<Listing<HabitProgress>, ApiError> {
+    let now = clock.now();
+    let progress = conn
+        .run(move |c| {
+            repository::habits::list(c, user.id)?
+                .into_iter()
+                .map(|habit| habits::load_progress(c, habit, now))
+                .collect::<mysql::Result<Vec<_>>>()
+        })
+        .await?;
+
+    Ok(Listing::all(progress))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        list_habits,
+        get_habit,
+        create_habit,
+        update_habit,
+        delete_habit,
+        check_in,
+        undo_check_in,
+        habits_view
+    ]
+}
@@ -0,0 +1,124 @@
+use mysql::prelude::Queryable;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+use std::time::Instant;
+
+use crate::db::DbConnPool;
+
+// Probes for orchestrators
+//
+// GET /healthz answers as long as the process is serving requests. GET
+// /readyz also runs `SELECT 1` on a pooled connection and answers 503 while
+// the database can't be reached, so traffic is held back until it can.
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Health {
+    status: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct DatabaseHealth {
+    connected: bool,
+    // Time spent waiting for a pooled connection and running the query
+    acquire_ms: Option<u128>,
+    query_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct PoolHealth {
+    max_connections: usize,
+    // Requests that gave up waiting for a connection since startup
+    acquire_timeouts: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Readiness {
+    status: &'static str,
+    version: &'static str,
+    database: DatabaseHealth,
+    pool: PoolHealth,
+}
+
+#[openapi(tag = "Monitoring")]
+#[get("/healthz")]
+fn healthz() -> Json<Health> {
+    Json(Health {
+        status: "ok",
+        version: VERSION,
+    })
+}
+
+// Check out a connection and run `SELECT 1`, timing both steps
+async fn check_database(db: &DbConnPool) -> DatabaseHealth {
+    let started = Instant::now();
+    let mut conn = match db.get_conn("readyz").await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return DatabaseHealth {
+                connected: false,
+                acquire_ms: None,
+                query_ms: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    let acquire_ms = started.elapsed().as_millis();
+
+    let started = Instant::now();
+    let result =
+        rocket::tokio::task::spawn_blocking(move || conn.query_first::<u8, _>("SELECT 1")).await;
+    let query_ms = started.elapsed().as_millis();
+
+    let error = match result {
+        Ok(Ok(Some(1))) => None,
+        Ok(Ok(_)) => Some("SELECT 1 returned an unexpected result".to_string()),
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(e) => Some(format!("Query panicked: {}", e)),
+    };
+    DatabaseHealth {
+        connected: error.is_none(),
+        acquire_ms: Some(acquire_ms),
+        query_ms: Some(query_ms),
+        error,
+    }
+}
+
+#[openapi(tag = "Monitoring")]
+#[get("/readyz")]
+async fn readyz(db: &State<DbConnPool>) -> (Status, Json<Readiness>) {
+    let database = check_database(db).await;
+    let (status, label) = if database.connected {
+        (Status::Ok, "ok")
+    } else {
+        (Status::ServiceUnavailable, "unavailable")
+    };
+
+    let readiness = Readiness {
+        status: label,
+        version: VERSION,
+        database,
+        pool: PoolHealth {
+            max_connections: db.max_connections,
+            acquire_timeouts: db.acquire_timeouts(),
+        },
+    };
+    (status, Json(readiness))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![healthz, readyz]
+}
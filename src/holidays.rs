@@ -0,0 +1,64 @@
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+
+use todo_core::models::Holiday;
+use todo_core::repository;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+
+// Holiday routes
+//
+// Each user keeps their own holiday calendar, which their schedules skip or
+// move around.
+
+#[openapi(tag = "Holidays")]
+#[get("/holidays")]
+async fn list_holidays(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<Listing<Holiday>, ApiError> {
+    Ok(Listing::all(
+        conn.run(move |c| repository::holidays::list(c, user.id))
+            .await?,
+    ))
+}
+
+#[openapi(tag = "Holidays")]
+#[post("/holidays", format = "json", data = "<holiday>")]
+async fn create_holiday(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    holiday: ValidatedJson<Holiday>,
+) -> Result<status::Created<Json<Holiday>>, ApiError> {
+    let mut new_holiday = holiday.into_inner();
+    let row = new_holiday.clone();
+    let id = conn
+        .run(move |c| repository::holidays::upsert(c, user.id, &row))
+        .await?;
+    new_holiday.id = Some(id);
+
+    Ok(status::Created::new(format!("/holidays/{}", id)).body(Json(new_holiday)))
+}
+
+#[openapi(tag = "Holidays")]
+#[delete("/holidays/<holiday_id>")]
+async fn delete_holiday(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    holiday_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::holidays::delete(c, user.id, holiday_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![list_holidays, create_holiday, delete_holiday]
+}
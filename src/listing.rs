@@ -0,0 +1,462 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{Json, Value};
+use rocket::serde::Serialize;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{Parameter, Responses};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::add_schema_response;
+use schemars::{schema_for, JsonSchema};
+use std::collections::BTreeMap;
+use todo_core::models::{
+    ApiKey, Goal, GoalProgressPoint, Habit, HabitProgress, Holiday, Schedule, ScheduleException,
+    ScheduleRun, Tag, Task, TaskList, Webhook,
+};
+
+use crate::openapi::query_parameter;
+
+// Media type clients send in Accept to get the enveloped list format
+const ENVELOPE_MEDIA_TYPE: (&str, &str) = ("application", "vnd.todo.v2+json");
+
+const DEFAULT_PER_PAGE: u64 = 50;
+const MAX_PER_PAGE: u64 = 500;
+
+// Element names used when a list is rendered as XML
+pub trait Named {
+    const COLLECTION: &'static str;
+    const ITEM: &'static str;
+}
+
+impl Named for Task {
+    const COLLECTION: &'static str = "tasks";
+    const ITEM: &'static str = "task";
+}
+
+impl Named for Schedule {
+    const COLLECTION: &'static str = "schedules";
+    const ITEM: &'static str = "schedule";
+}
+
+impl Named for ScheduleException {
+    const COLLECTION: &'static str = "exceptions";
+    const ITEM: &'static str = "exception";
+}
+
+impl Named for ScheduleRun {
+    const COLLECTION: &'static str = "occurrences";
+    const ITEM: &'static str = "occurrence";
+}
+
+impl Named for Goal {
+    const COLLECTION: &'static str = "goals";
+    const ITEM: &'static str = "goal";
+}
+
+impl Named for GoalProgressPoint {
+    const COLLECTION: &'static str = "progress";
+    const ITEM: &'static str = "point";
+}
+
+impl Named for Habit {
+    const COLLECTION: &'static str = "habits";
+    const ITEM: &'static str = "habit";
+}
+
+impl Named for HabitProgress {
+    const COLLECTION: &'static str = "habits";
+    const ITEM: &'static str = "habit";
+}
+
+impl Named for TaskList {
+    const COLLECTION: &'static str = "lists";
+    const ITEM: &'static str = "list";
+}
+
+impl Named for Tag {
+    const COLLECTION: &'static str = "tags";
+    const ITEM: &'static str = "tag";
+}
+
+impl Named for Holiday {
+    const COLLECTION: &'static str = "holidays";
+    const ITEM: &'static str = "holiday";
+}
+
+impl Named for ApiKey {
+    const COLLECTION: &'static str = "api_keys";
+    const ITEM: &'static str = "api_key";
+}
+
+impl Named for Webhook {
+    const COLLECTION: &'static str = "webhooks";
+    const ITEM: &'static str = "webhook";
+}
+
+// Representations a list can be rendered in
+enum Format {
+    Json,
+    Envelope,
+    Csv,
+    Xml,
+}
+
+// A list endpoint's result
+//
+// Responds with a bare JSON array by default. Clients that accept
+// `application/vnd.todo.v2+json` get
+// `{ "data": [...], "meta": { total, page, per_page }, "links": {...} }`
+// instead, so pagination details don't have to come from the X-Total-Count
+// and Link headers sent with every format.
+// `text/csv` and `application/xml` render the same serialized items as
+// spreadsheet rows or XML elements.
+pub struct Listing<T> {
+    items: Vec<T>,
+    total: u64,
+    page: u64,
+    per_page: u64,
+}
+
+// The `application/vnd.todo.v2+json` body
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Envelope<T> {
+    data: Vec<T>,
+    meta: PageMeta,
+    links: PageLinks,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct PageMeta {
+    total: u64,
+    page: u64,
+    per_page: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct PageLinks {
+    #[serde(rename = "self")]
+    current: String,
+    first: String,
+    last: String,
+    prev: Option<String>,
+    next: Option<String>,
+}
+
+// `?page=` and `?per_page=` for list endpoints, read as a request guard so
+// routes can take their other query parameters as a form
+//
+// Pages start at 1. `per_page` defaults to DEFAULT_PER_PAGE and is capped at
+// MAX_PER_PAGE; out-of-range values are clamped rather than rejected, while
+// values that aren't numbers fail the request with 422.
+#[derive(Default, Clone, Copy)]
+pub struct Pagination {
+    page: Option<u64>,
+    per_page: Option<u64>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Pagination {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let page = req.query_value::<u64>("page").transpose();
+        let per_page = req.query_value::<u64>("per_page").transpose();
+
+        match (page, per_page) {
+            (Ok(page), Ok(per_page)) => Outcome::Success(Pagination { page, per_page }),
+            _ => Outcome::Error((Status::UnprocessableEntity, ())),
+        }
+    }
+}
+
+impl Pagination {
+    pub fn page(&self) -> u64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> u64 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    // Rows to skip before this page
+    pub fn offset(&self) -> u64 {
+        (self.page() - 1).saturating_mul(self.per_page())
+    }
+}
+
+impl<T> Listing<T> {
+    // One page out of `total` rows
+    pub fn page(items: Vec<T>, total: u64, pagination: Pagination) -> Self {
+        Listing {
+            items,
+            total,
+            page: pagination.page(),
+            per_page: pagination.per_page(),
+        }
+    }
+
+    // Every row, returned as one page
+    pub fn all(items: Vec<T>) -> Self {
+        let total = items.len() as u64;
+
+        Listing {
+            items,
+            total,
+            page: 1,
+            per_page: total.max(1),
+        }
+    }
+}
+
+// Pick the most preferred representation the client accepts, JSON otherwise
+fn negotiate(req: &Request) -> Format {
+    let Some(accept) = req.accept() else {
+        return Format::Json;
+    };
+
+    let mut media_types: Vec<_> = accept.iter().collect();
+    media_types.sort_by(|a, b| b.weight_or(1.0).total_cmp(&a.weight_or(1.0)));
+
+    let (envelope_top, envelope_sub) = ENVELOPE_MEDIA_TYPE;
+    media_types
+        .into_iter()
+        .find_map(|media| match (media.top().as_str(), media.sub().as_str()) {
+            (top, sub) if top == envelope_top && sub == envelope_sub => Some(Format::Envelope),
+            ("text", "csv") => Some(Format::Csv),
+            ("application" | "text", "xml") => Some(Format::Xml),
+            ("application", "json") | ("application" | "*", "*") => Some(Format::Json),
+            _ => None,
+        })
+        .unwrap_or(Format::Json)
+}
+
+// One column per field, in declaration order. The columns come from the
+// item's schema, so an empty list still gets its header row and fields left
+// out of one item become empty cells. CSV has no nesting, so list fields such
+// as a task's tags become one `;`-separated cell.
+fn to_csv<T: Serialize + JsonSchema>(items: &[T]) -> Result<String, Status> {
+    let columns: Vec<String> = schema_for!(T)
+        .schema
+        .object
+        .map(|object| object.properties.into_keys().collect())
+        .unwrap_or_default();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(&columns)
+        .map_err(|_| Status::InternalServerError)?;
+    for item in items {
+        let Value::Object(fields) =
+            serde_json::to_value(item).map_err(|_| Status::InternalServerError)?
+        else {
+            return Err(Status::InternalServerError);
+        };
+
+        writer
+            .write_record(
+                columns
+                    .iter()
+                    .map(|column| fields.get(column).map(csv_cell).unwrap_or_default()),
+            )
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|_| Status::InternalServerError)?;
+    String::from_utf8(bytes).map_err(|_| Status::InternalServerError)
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => escape_formula(s.clone()),
+        Value::Array(values) => escape_formula(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    other => csv_cell(other),
+                })
+                .collect::<Vec<_>>()
+                .join(";"),
+        ),
+        other => other.to_string(),
+    }
+}
+
+// Spreadsheets run text starting with one of these as a formula, so such
+// cells get a leading `'` to keep them text
+fn escape_formula(text: String) -> String {
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text
+    }
+}
+
+fn to_xml<T: Serialize + Named>(items: &[T]) -> Result<String, Status> {
+    // A one-entry map becomes <collection><item>..</item><item>..</item></collection>
+    let body = BTreeMap::from([(T::ITEM, items)]);
+    quick_xml::se::to_string_with_root(T::COLLECTION, &body)
+        .map_err(|_| Status::InternalServerError)
+}
+
+// Link to another page of the current URI, keeping its other query parameters
+fn page_link(req: &Request, page: u64, per_page: u64) -> String {
+    let mut query: Vec<String> = req
+        .uri()
+        .query()
+        .map(|query| {
+            query
+                .raw_segments()
+                .filter(|segment| {
+                    let name = segment.as_str().split('=').next().unwrap_or("");
+                    name != "page" && name != "per_page"
+                })
+                .map(|segment| segment.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    query.push(format!("page={}", page));
+    query.push(format!("per_page={}", per_page));
+
+    format!("{}?{}", req.uri().path(), query.join("&"))
+}
+
+impl<'r, T: Serialize + JsonSchema + Named> Responder<'r, 'static> for Listing<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let total = self.total;
+        let links: Vec<String> = self
+            .links(req)
+            .into_iter()
+            .map(|(rel, uri)| format!("<{}>; rel=\"{}\"", uri, rel))
+            .collect();
+
+        let response = match negotiate(req) {
+            Format::Json => (ContentType::JSON, Json(self.items)).respond_to(req)?,
+            Format::Csv => (
+                ContentType::new("text", "csv").with_params(("charset", "utf-8")),
+                to_csv(&self.items)?,
+            )
+                .respond_to(req)?,
+            Format::Xml => (ContentType::XML, to_xml(&self.items)?).respond_to(req)?,
+            Format::Envelope => {
+                let (top, sub) = ENVELOPE_MEDIA_TYPE;
+                let body = self.envelope(req);
+                (ContentType::new(top, sub), body).respond_to(req)?
+            }
+        };
+
+        Response::build_from(response)
+            .header(Header::new("Vary", "Accept"))
+            .header(Header::new("X-Total-Count", total.to_string()))
+            .header(Header::new("Link", links.join(", ")))
+            .ok()
+    }
+}
+
+impl<T: Serialize> Listing<T> {
+    fn last_page(&self) -> u64 {
+        self.total.div_ceil(self.per_page).max(1)
+    }
+
+    // Navigation links, with prev/next only where those pages exist
+    fn links(&self, req: &Request) -> Vec<(&'static str, String)> {
+        let last_page = self.last_page();
+        let link = |page: u64| page_link(req, page, self.per_page);
+
+        let mut links = vec![("first", link(1))];
+        if self.page > 1 {
+            links.push(("prev", link(self.page - 1)));
+        }
+        if self.page < last_page {
+            links.push(("next", link(self.page + 1)));
+        }
+        links.push(("last", link(last_page)));
+        links
+    }
+
+    fn envelope(self, req: &Request) -> Json<Envelope<T>> {
+        let link = |page: u64| page_link(req, page, self.per_page);
+        let last_page = self.last_page();
+
+        Json(Envelope {
+            meta: PageMeta {
+                total: self.total,
+                page: self.page,
+                per_page: self.per_page,
+            },
+            links: PageLinks {
+                current: link(self.page),
+                first: link(1),
+                last: link(last_page),
+                prev: (self.page > 1).then(|| link(self.page - 1)),
+                next: (self.page < last_page).then(|| link(self.page + 1)),
+            },
+            data: self.items,
+        })
+    }
+}
+
+// API description
+
+pub fn per_page_parameter(gen: &mut OpenApiGenerator) -> Parameter {
+    query_parameter(
+        "per_page",
+        &format!(
+            "Items per page, {} by default and at most {}",
+            DEFAULT_PER_PAGE, MAX_PER_PAGE
+        ),
+        gen.json_schema::<u64>(),
+    )
+}
+
+impl<'r> OpenApiFromRequest<'r> for Pagination {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::Parameter(query_parameter(
+            "page",
+            "Page to return, starting at 1",
+            gen.json_schema::<u64>(),
+        )))
+    }
+}
+
+impl<T: Serialize + JsonSchema + Named> OpenApiResponderInner for Listing<T> {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let (top, sub) = ENVELOPE_MEDIA_TYPE;
+        let mut responses = Responses::default();
+        add_schema_response(
+            &mut responses,
+            200,
+            "application/json",
+            gen.json_schema::<Vec<T>>(),
+        )?;
+        add_schema_response(
+            &mut responses,
+            200,
+            format!("{}/{}", top, sub),
+            gen.json_schema::<Envelope<T>>(),
+        )?;
+        add_schema_response(&mut responses, 200, "text/csv", gen.json_schema::<String>())?;
+        add_schema_response(
+            &mut responses,
+            200,
+            "application/xml",
+            gen.json_schema::<String>(),
+        )?;
+        Ok(responses)
+    }
+}
@@ -0,0 +1,187 @@
+use mysql::prelude::Queryable;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+
+use todo_core::clock::SharedClock;
+use todo_core::models::{Task, TaskList};
+use todo_core::repository;
+use todo_core::repository::tasks::TaskQuery;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::events::{TaskEvent, TaskEvents};
+use crate::guards::ValidatedJson;
+use crate::listing::{Listing, Pagination};
+use crate::subtasks::check_parent;
+use crate::TaskFilters;
+
+// Tasks may only be filed under the user's own lists
+pub fn check_list<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    list_id: Option<u32>,
+) -> Result<(), ApiError> {
+    match list_id {
+        Some(list_id) if repository::lists::find(conn, user_id, list_id)?.is_none() => Err(
+            ApiError::BadRequest(format!("list {} does not exist", list_id)),
+        ),
+        _ => Ok(()),
+    }
+}
+
+// List routes
+
+#[openapi(tag = "Lists")]
+#[get("/lists")]
+async fn list_lists(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<Listing<TaskList>, ApiError> {
+    let lists = conn
+        .run(move |c| repository::lists::list(c, user.id))
+        .await?;
+
+    Ok(Listing::all(lists))
+}
+
+#[openapi(tag = "Lists")]
+#[get("/lists/<list_id>")]
+async fn get_list(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    list_id: u32,
+) -> Result<Json<TaskList>, ApiError> {
+    conn.run(move |c| repository::lists::find(c, user.id, list_id))
+        .await?
+        .map(Json)
+        .ok_or(ApiError::NotFound("list"))
+}
+
+#[openapi(tag = "Lists")]
+#[post("/lists", format = "json", data = "<list>")]
+async fn create_list(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    list: ValidatedJson<TaskList>,
+) -> Result<status::Created<Json<TaskList>>, ApiError> {
+    let mut new_list = list.into_inner();
+    new_list.created_at = Some(clock.now());
+    let row = new_list.clone();
+    let last_id = conn
+        .run(move |c| repository::lists::insert(c, user.id, &row))
+        .await?;
+    new_list.id = Some(last_id);
+
+    Ok(status::Created::new(format!("/lists/{}", last_id)).body(Json(new_list)))
+}
+
+#[openapi(tag = "Lists")]
+#[put("/lists/<list_id>", format = "json", data = "<list>")]
+async fn update_list(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    list_id: u32,
+    list: ValidatedJson<TaskList>,
+) -> Result<Json<TaskList>, ApiError> {
+    let list = list.into_inner();
+    conn.run(move |c| {
+        if !repository::lists::update(c, user.id, list_id, &list)? {
+            return Ok(None);
+        }
+        repository::lists::find(c, user.id, list_id)
+    })
+    .await?
+    .map(Json)
+    .ok_or(ApiError::NotFound("list"))
+}
+
+// Deleting a list deletes the tasks filed under it
+#[openapi(tag = "Lists")]
+#[delete("/lists/<list_id>")]
+async fn delete_list(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    list_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::lists::delete(c, user.id, list_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+// The tasks in one list; takes the same filters as GET /tasks
+#[openapi(tag = "Lists")]
+#[get("/lists/<list_id>/tasks?<filters..>")]
+async fn list_list_tasks(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    list_id: u32,
+    filters: TaskFilters<'_>,
+    pagination: Pagination,
+) -> Result<Listing<Task>, ApiError> {
+    let query = TaskQuery {
+        list_id: Some(list_id),
+        ..filters.into_query(clock.now())?
+    };
+    let (limit, offset) = (pagination.per_page(), pagination.offset());
+    let (tasks, total) = conn
+        .run(move |c| -> Result<_, ApiError> {
+            if repository::lists::find(c, user.id, list_id)?.is_none() {
+                return Err(ApiError::NotFound("list"));
+            }
+            let tasks = repository::tasks::search(c, user.id, &query, limit, offset)?;
+            Ok((tasks, repository::tasks::count(c, user.id, &query)?))
+        })
+        .await?;
+
+    Ok(Listing::page(tasks, total, pagination))
+}
+
+// Create a task filed under the list
+#[openapi(tag = "Lists")]
+#[post("/lists/<list_id>/tasks", format = "json", data = "<task>")]
+async fn create_list_task(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    list_id: u32,
+    task: ValidatedJson<Task>,
+) -> Result<status::Created<Json<Task>>, ApiError> {
+    let mut new_task = task.into_inner();
+    new_task.list_id = Some(list_id);
+    let now = clock.now();
+    new_task.created_at = Some(now);
+    let row = new_task.clone();
+    let last_id = conn
+        .run(move |c| -> Result<_, ApiError> {
+            if repository::lists::find(c, user.id, list_id)?.is_none() {
+                return Err(ApiError::NotFound("list"));
+            }
+            check_parent(c, user.id, None, row.parent_id)?;
+            Ok(repository::tasks::insert(c, user.id, &row, now)?)
+        })
+        .await?;
+    new_task.id = Some(last_id);
+    events.publish(user.id, TaskEvent::Created(new_task.clone()));
+
+    Ok(status::Created::new(format!("/tasks/{}", last_id)).body(Json(new_task)))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        list_lists,
+        get_list,
+        create_list,
+        update_list,
+        delete_list,
+        list_list_tasks,
+        create_list_task
+    ]
+}
@@ -0,0 +1,95 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::Request;
+use rocket::response::Response;
+use rocket::{Data, Orbit, Rocket};
+use std::time::Instant;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::AppConfig;
+use crate::{auth, request_id};
+
+// Structured logs
+//
+// Rocket's own console output is switched off in favour of one line per
+// request, logged at INFO with the target `request`:
+//
+//     {"timestamp":"...","level":"INFO","fields":{"message":"request","method":"GET",
+//      "path":"/tasks","status":200,"latency_ms":3.2,"request_id":"...","user_id":7},
+//      "target":"request"}
+//
+// LOG_LEVEL takes a tracing filter, by default `info,rocket=off`; Rocket's
+// messages are still there with e.g. `info,rocket=warn`. LOG_FORMAT is
+// `json` (the default) or `text`.
+
+pub const DEFAULT_LEVEL: &str = "info,rocket=off";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    Text,
+}
+
+// A LOG_LEVEL setting, kept as text once it is known to be a valid filter
+pub fn parse_level(text: &str) -> Result<String, String> {
+    EnvFilter::try_new(text)
+        .map(|_| text.to_string())
+        .map_err(|e| format!("must be a tracing filter: {}", e))
+}
+
+pub fn parse_format(text: &str) -> Result<LogFormat, String> {
+    match text.trim() {
+        "json" => Ok(LogFormat::Json),
+        "text" => Ok(LogFormat::Text),
+        other => Err(format!("must be json or text, not {:?}", other)),
+    }
+}
+
+// Install the global subscriber the configuration asks for
+pub fn init(config: &AppConfig) {
+    let filter = EnvFilter::try_new(&config.log_level).expect("LOG_LEVEL was checked on load");
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+// When the request came in
+struct Received(Instant);
+
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logger",
+            kind: Kind::Liftoff | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.config();
+        tracing::info!(address = %config.address, port = config.port, "listening");
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| Received(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let latency = req.local_cache(|| Received(Instant::now())).0.elapsed();
+
+        tracing::info!(
+            target: "request",
+            method = req.method().as_str(),
+            path = req.uri().path().as_str(),
+            status = res.status().code,
+            latency_ms = latency.as_secs_f64() * 1000.0,
+            request_id = request_id::of(req),
+            user_id = auth::signed_in_user(req),
+            "request"
+        );
+    }
+}
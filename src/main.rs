@@ -1,215 +1,1154 @@
 #[macro_use]
 extern crate rocket;
 
-use dotenv::dotenv;
-use mysql::prelude::*;
-use mysql::Opts;
-use mysql::*;
-use rocket::http::Method;
+mod api_keys;
+mod auth;
+mod bulk;
+mod chaos;
+mod config;
+mod cors;
+mod db;
+mod error;
+mod events;
+mod export;
+mod goals;
+mod guards;
+mod habits;
+mod health;
+mod holidays;
+mod listing;
+mod lists;
+mod logging;
+mod metrics;
+mod openapi;
+mod options;
+mod pwa;
+mod rate_limit;
+mod request_id;
+mod schedules;
+mod schemas;
+mod subtasks;
+mod tags;
+mod task_store;
+#[cfg(test)]
+mod test_support;
+mod trash;
+mod tui;
+mod webhooks;
+mod ws;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rocket::config::LogLevel;
+use rocket::form::{
+    self, error::ErrorKind, DataField, FromForm, FromFormField, Strict, ValueField,
+};
 use rocket::response::status;
-use rocket::serde::{json::Json, Deserialize, Serialize};
-use rocket::State;
-use rocket_cors::{AllowedOrigins, CorsOptions};
-use std::env;
-use std::sync::Mutex;
+use rocket::serde::json::Json;
+use rocket::{Build, Rocket, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{OpenApi, Parameter};
+use rocket_okapi::request::OpenApiFromForm;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+
+use auth::{AuthenticatedUser, TokenKeys};
+use config::AppConfig;
+use db::init_pool;
+use error::ApiError;
+use events::{TaskEvent, TaskEvents};
+use guards::ValidatedJson;
+use listing::{Listing, Pagination};
+use mysql::{Pool, PooledConn, TxOpts};
+use openapi::query_parameter;
+use rate_limit::RateLimiter;
+use std::sync::Arc;
+use subtasks::{check_parent, Include, OnDelete, TaskDetail};
+use task_store::{MySqlTaskStore, SharedTaskStore, Tasks};
+use todo_core::clock::SharedClock;
+use todo_core::models::{Priority, Task, TaskPatch};
+use todo_core::repository::tasks::{TaskQuery, TaskSort};
+use todo_core::validation::check_patched_dates;
+use todo_core::{repository, schema, Engine};
 
-// Task struct for serialization/deserialization
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(crate = "rocket::serde")]
-struct Task {
-    id: Option<u32>,
-    description: String,
-    is_completed: bool,
+// Rocket routes
+
+// Columns GET /tasks can be sorted by
+#[derive(FromFormField, JsonSchema)]
+#[schemars(rename_all = "snake_case")]
+enum SortField {
+    Id,
+    #[field(value = "created_at")]
+    CreatedAt,
+    #[field(value = "start_date")]
+    StartDate,
+    #[field(value = "due_date")]
+    DueDate,
+    Priority,
+    Description,
+    Completed,
 }
 
-// Database connection pool wrapped in a Mutex for thread safety
-struct DbConnPool {
-    pool: Mutex<Pool>,
+impl From<SortField> for TaskSort {
+    fn from(field: SortField) -> Self {
+        match field {
+            SortField::Id => TaskSort::Id,
+            SortField::CreatedAt => TaskSort::CreatedAt,
+            SortField::StartDate => TaskSort::StartDate,
+            SortField::DueDate => TaskSort::DueDate,
+            SortField::Priority => TaskSort::Priority,
+            SortField::Description => TaskSort::Description,
+            SortField::Completed => TaskSort::Completed,
+        }
+    }
 }
 
-// Function to create a new database pool
-fn init_pool() -> Pool {
-    dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let opts = Opts::from_url(&database_url).expect("Invalid database URL");
-    Pool::new(opts).expect("Failed to create database pool")
+#[derive(FromFormField, JsonSchema)]
+#[schemars(rename_all = "snake_case")]
+enum SortOrder {
+    Asc,
+    Desc,
 }
 
-// Rocket routes
+// An RFC 3339 timestamp in a query string, e.g. 2024-06-10T17:00:00Z
+struct Timestamp(DateTime<Utc>);
 
-#[get("/tasks")]
-async fn list_tasks(db: &State<DbConnPool>) -> Json<Vec<Task>> {
-    let pool = db.pool.lock().unwrap();
-    let mut conn = pool.get_conn().unwrap();
+impl<'v> FromFormField<'v> for Timestamp {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        DateTime::parse_from_rfc3339(field.value)
+            .map(|at| Timestamp(at.with_timezone(&Utc)))
+            .map_err(|_| form::Error::validation("must be an RFC 3339 timestamp").into())
+    }
+}
+
+// A priority in a query string: low, medium or high
+struct PriorityParam(Priority);
 
-    let tasks = conn
-        .query_map(
-            "SELECT id, description, is_completed FROM tasks",
-            |(id, description, is_completed)| Task {
-                id: Some(id),
-                description,
-                is_completed,
-            },
-        )
-        .unwrap();
+impl<'v> FromFormField<'v> for PriorityParam {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Priority::parse(field.value)
+            .map(PriorityParam)
+            .ok_or_else(|| form::Error::validation("must be low, medium or high").into())
+    }
+}
 
-    Json(tasks)
+// Query parameters of GET /tasks, e.g.
+// /tasks?completed=false&list_id=3&tag=shopping&q=groceries&due_before=2024-06-10T00:00:00Z&sort=due_date&order=desc
+//
+// Fields are kept as results because a plain Option would quietly drop a
+// value that doesn't parse; `query_param` reports those as 400 instead.
+// Strict keeps a missing bool from defaulting to false.
+#[derive(FromForm)]
+struct TaskFilters<'v> {
+    completed: form::Result<'v, Strict<bool>>,
+    list_id: form::Result<'v, u32>,
+    tag: Option<String>,
+    q: Option<String>,
+    priority: form::Result<'v, PriorityParam>,
+    due_before: form::Result<'v, Timestamp>,
+    due_after: form::Result<'v, Timestamp>,
+    overdue: form::Result<'v, Strict<bool>>,
+    available: form::Result<'v, Strict<bool>>,
+    sort: form::Result<'v, SortField>,
+    order: form::Result<'v, SortOrder>,
 }
 
-#[get("/tasks/<task_id>")]
-async fn get_task(db: &State<DbConnPool>, task_id: u32) -> Option<Json<Task>> {
-    let pool = db.pool.lock().unwrap();
-    let mut conn = pool.get_conn().unwrap();
+impl<'v> OpenApiFromForm<'v> for TaskFilters<'v> {
+    fn form_multi_parameter(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<Vec<Parameter>> {
+        Ok(vec![
+            query_parameter(
+                "completed",
+                "Only completed or open tasks",
+                gen.json_schema::<bool>(),
+            ),
+            query_parameter(
+                "list_id",
+                "Only tasks in this list",
+                gen.json_schema::<u32>(),
+            ),
+            query_parameter(
+                "tag",
+                "Only tasks with this tag",
+                gen.json_schema::<String>(),
+            ),
+            query_parameter(
+                "q",
+                "Text to search descriptions for",
+                gen.json_schema::<String>(),
+            ),
+            query_parameter(
+                "priority",
+                "Only tasks of this priority",
+                gen.json_schema::<Priority>(),
+            ),
+            query_parameter(
+                "due_before",
+                "Only tasks due before this time",
+                gen.json_schema::<DateTime<Utc>>(),
+            ),
+            query_parameter(
+                "due_after",
+                "Only tasks due after this time",
+                gen.json_schema::<DateTime<Utc>>(),
+            ),
+            query_parameter(
+                "overdue",
+                "Only open tasks past their due date, or the rest",
+                gen.json_schema::<bool>(),
+            ),
+            query_parameter(
+                "available",
+                "Only tasks that have started, or those that haven't",
+                gen.json_schema::<bool>(),
+            ),
+            query_parameter(
+                "sort",
+                "Column to sort by, id by default",
+                gen.json_schema::<SortField>(),
+            ),
+            query_parameter(
+                "order",
+                "Sort direction, asc by default",
+                gen.json_schema::<SortOrder>(),
+            ),
+        ])
+    }
+}
+
+// A single query parameter, kept as a result like the fields of TaskFilters
+// so `query_param` can tell a value that doesn't parse from a missing one
+struct QueryParam<'v, T>(form::Result<'v, T>);
+
+#[rocket::async_trait]
+impl<'v, T: FromForm<'v>> FromForm<'v> for QueryParam<'v, T> {
+    type Context = <form::Result<'v, T> as FromForm<'v>>::Context;
+
+    fn init(opts: form::Options) -> Self::Context {
+        <form::Result<'v, T>>::init(opts)
+    }
+
+    fn push_value(ctxt: &mut Self::Context, field: ValueField<'v>) {
+        <form::Result<'v, T>>::push_value(ctxt, field)
+    }
 
-    let result: Option<Task> = conn
-        .exec_first(
-            "SELECT id, description, is_completed FROM tasks WHERE id = :id",
-            params! {
-                "id" => task_id,
-            },
-        )
-        .unwrap()
-        .map(|(id, description, is_completed)| Task {
-            id: Some(id),
-            description,
-            is_completed,
-        });
+    async fn push_data(ctxt: &mut Self::Context, field: DataField<'v, '_>) {
+        <form::Result<'v, T>>::push_data(ctxt, field).await
+    }
 
-    result.map(Json)
+    fn finalize(ctxt: Self::Context) -> form::Result<'v, Self> {
+        <form::Result<'v, T>>::finalize(ctxt).map(QueryParam)
+    }
 }
 
-#[post("/tasks", format = "json", data = "<task>")]
-async fn create_task(db: &State<DbConnPool>, task: Json<Task>) -> status::Created<Json<Task>> {
-    let pool = db.pool.lock().unwrap();
-    let mut conn = pool.get_conn().unwrap();
+// Documented as the optional parameter it is
+impl<T: JsonSchema> JsonSchema for QueryParam<'_, T> {
+    fn schema_name() -> String {
+        <Option<T>>::schema_name()
+    }
 
-    conn.exec_drop(
-        "INSERT INTO tasks (description, is_completed) VALUES (:description, :is_completed)",
-        params! {
-            "description" => &task.description,
-            "is_completed" => task.is_completed,
-        },
-    )
-    .unwrap();
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        <Option<T>>::json_schema(gen)
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+}
+
+// A parameter that is absent is None; one that is present must parse
+fn query_param<T>(name: &str, value: form::Result<'_, T>) -> Result<Option<T>, ApiError> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(errors) if errors.iter().all(|e| matches!(e.kind, ErrorKind::Missing)) => Ok(None),
+        Err(_) => Err(ApiError::BadRequest(format!("invalid value for {}", name))),
+    }
+}
+
+impl TaskFilters<'_> {
+    // The query these filters describe, with `overdue` and `available`
+    // judged at `now`
+    fn into_query(self, now: DateTime<Utc>) -> Result<TaskQuery, ApiError> {
+        let filters = self;
+        let sort = query_param("sort", filters.sort)?;
+        let order = query_param("order", filters.order)?;
+
+        Ok(TaskQuery {
+            completed: query_param("completed", filters.completed)?.map(Strict::into_inner),
+            list_id: query_param("list_id", filters.list_id)?,
+            tag: filters.tag.filter(|tag| !tag.trim().is_empty()),
+            search: filters.q.filter(|q| !q.trim().is_empty()),
+            priority: query_param("priority", filters.priority)?.map(|p| p.0),
+            due_before: query_param("due_before", filters.due_before)?.map(|at| at.0),
+            due_after: query_param("due_after", filters.due_after)?.map(|at| at.0),
+            overdue: query_param("overdue", filters.overdue)?.map(Strict::into_inner),
+            available: query_param("available", filters.available)?.map(Strict::into_inner),
+            sort: sort.map(TaskSort::from).unwrap_or_default(),
+            descending: matches!(order, Some(SortOrder::Desc)),
+            now,
+        })
+    }
+}
+
+#[openapi(tag = "Tasks")]
+#[get("/tasks?<filters..>")]
+async fn list_tasks(
+    tasks: Tasks<'_>,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    filters: TaskFilters<'_>,
+    pagination: Pagination,
+) -> Result<Listing<Task>, ApiError> {
+    let query = filters.into_query(clock.now())?;
+    let (limit, offset) = (pagination.per_page(), pagination.offset());
+    let (tasks, total) = tasks.search(user.id, query, limit, offset).await?;
 
-    let last_id = conn.last_insert_id() as u32;
+    Ok(Listing::page(tasks, total, pagination))
+}
+
+// One task, or with `?include=subtasks` the task with its subtasks nested
+// under it
+#[openapi(tag = "Tasks")]
+#[get("/tasks/<task_id>?<include>")]
+async fn get_task(
+    tasks: Tasks<'_>,
+    user: AuthenticatedUser,
+    task_id: u32,
+    include: QueryParam<'_, Include>,
+) -> Result<TaskDetail, ApiError> {
+    let detail = match query_param("include", include.0)? {
+        Some(Include::Subtasks) => tasks
+            .tree(user.id, task_id)
+            .await?
+            .map(Json)
+            .map(TaskDetail::Tree),
+        None => tasks
+            .find(user.id, task_id)
+            .await?
+            .map(Json)
+            .map(TaskDetail::Task),
+    };
+
+    detail.ok_or(ApiError::NotFound("task"))
+}
+
+// Task writes shared by MySqlTaskStore and the WebSocket endpoint
+
+// Insert a validated task, returning its id
+fn insert_task(
+    conn: &mut PooledConn,
+    user_id: u32,
+    task: &Task,
+    now: DateTime<Utc>,
+) -> Result<u32, ApiError> {
+    lists::check_list(conn, user_id, task.list_id)?;
+    check_parent(conn, user_id, None, task.parent_id)?;
+    Ok(repository::tasks::insert(conn, user_id, task, now)?)
+}
+
+// The task as saved by a write, and whether the write is what completed it
+fn saved(conn: &mut PooledConn, user_id: u32, before: Task) -> mysql::Result<Option<(Task, bool)>> {
+    let task = repository::tasks::find(conn, user_id, before.id.unwrap_or_default())?;
+    Ok(task.map(|task| {
+        let completed = task.is_completed && !before.is_completed;
+        (task, completed)
+    }))
+}
 
-    let new_task = Task {
-        id: Some(last_id),
-        description: task.description.clone(),
-        is_completed: task.is_completed,
+// Overwrite a task, returning it as saved if it exists
+fn replace_task(
+    conn: &mut PooledConn,
+    user_id: u32,
+    task_id: u32,
+    task: &Task,
+) -> Result<Option<(Task, bool)>, ApiError> {
+    lists::check_list(conn, user_id, task.list_id)?;
+    check_parent(conn, user_id, Some(task_id), task.parent_id)?;
+    let Some(before) = repository::tasks::find(conn, user_id, task_id)? else {
+        return Ok(None);
     };
+    repository::tasks::update(conn, user_id, task_id, task)?;
+    Ok(saved(conn, user_id, before)?)
+}
+
+// Apply a patch to a task, returning it as saved if it exists
+fn apply_patch(
+    conn: &mut PooledConn,
+    user_id: u32,
+    task_id: u32,
+    patch: &TaskPatch,
+) -> Result<Option<(Task, bool)>, ApiError> {
+    lists::check_list(conn, user_id, patch.list_id.flatten())?;
+    check_parent(conn, user_id, Some(task_id), patch.parent_id.flatten())?;
+    let Some(before) = repository::tasks::find(conn, user_id, task_id)? else {
+        return Ok(None);
+    };
+    check_patched_dates(patch, &before).map_err(ApiError::Invalid)?;
+    repository::tasks::patch(conn, user_id, task_id, patch)?;
+    Ok(saved(conn, user_id, before)?)
+}
+
+// Move a task and its subtasks to the trash, optionally moving the subtasks
+// up to the task's parent first. Returns the ids moved.
+fn trash_task(
+    conn: &mut PooledConn,
+    user_id: u32,
+    task_id: u32,
+    reparent: bool,
+    now: NaiveDateTime,
+) -> mysql::Result<Vec<u32>> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    if reparent {
+        repository::tasks::reparent_children(&mut tx, user_id, task_id)?;
+    }
+    let deleted = repository::tasks::delete(&mut tx, user_id, task_id, now)?;
+    tx.commit()?;
+    Ok(deleted)
+}
 
-    status::Created::new(format!("/tasks/{}", last_id)).body(Json(new_task))
+#[openapi(tag = "Tasks")]
+#[post("/tasks", format = "json", data = "<task>")]
+async fn create_task(
+    tasks: Tasks<'_>,
+    clock: &State<SharedClock>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    task: ValidatedJson<Task>,
+) -> Result<status::Created<Json<Task>>, ApiError> {
+    let mut new_task = task.into_inner();
+    let now = clock.now();
+    new_task.created_at = Some(now);
+    let last_id = tasks.insert(user.id, new_task.clone(), now).await?;
+    new_task.id = Some(last_id);
+    events.publish(user.id, TaskEvent::Created(new_task.clone()));
+
+    Ok(status::Created::new(format!("/tasks/{}", last_id)).body(Json(new_task)))
 }
 
+#[openapi(tag = "Tasks")]
 #[put("/tasks/<task_id>", format = "json", data = "<task>")]
-async fn update_task(db: &State<DbConnPool>, task_id: u32, task: Json<Task>) -> Option<Json<Task>> {
-    let pool = db.pool.lock().unwrap();
-    let mut conn = pool.get_conn().unwrap();
+async fn update_task(
+    tasks: Tasks<'_>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    task_id: u32,
+    task: ValidatedJson<Task>,
+) -> Result<Json<Task>, ApiError> {
+    let (updated, completed) = tasks
+        .replace(user.id, task_id, task.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound("task"))?;
+    events.publish(
+        user.id,
+        TaskEvent::Updated {
+            task: updated.clone(),
+            completed,
+        },
+    );
 
-    let result = conn.exec_drop(
-        "UPDATE tasks SET description = :description, is_completed = :is_completed WHERE id = :id",
-        params! {
-            "id" => task_id,
-            "description" => &task.description,
-            "is_completed" => task.is_completed,
+    Ok(Json(updated))
+}
+
+#[openapi(tag = "Tasks")]
+#[patch("/tasks/<task_id>", format = "json", data = "<patch>")]
+async fn patch_task(
+    tasks: Tasks<'_>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    task_id: u32,
+    patch: ValidatedJson<TaskPatch>,
+) -> Result<Json<Task>, ApiError> {
+    let (patched, completed) = tasks
+        .patch(user.id, task_id, patch.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound("task"))?;
+    events.publish(
+        user.id,
+        TaskEvent::Updated {
+            task: patched.clone(),
+            completed,
         },
     );
 
-    match result {
-        Ok(_) => Some(Json(Task {
-            id: Some(task_id),
-            description: task.description.clone(),
-            is_completed: task.is_completed,
-        })),
-        Err(_) => None,
+    Ok(Json(patched))
+}
+
+// Move a task and its subtasks to the trash, or with `?subtasks=reparent`
+// move the subtasks up to the task's parent first
+#[openapi(tag = "Tasks")]
+#[delete("/tasks/<task_id>?<subtasks>")]
+async fn delete_task(
+    tasks: Tasks<'_>,
+    clock: &State<SharedClock>,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    task_id: u32,
+    subtasks: QueryParam<'_, OnDelete>,
+) -> Result<status::NoContent, ApiError> {
+    let reparent = matches!(
+        query_param("subtasks", subtasks.0)?,
+        Some(OnDelete::Reparent)
+    );
+    let deleted = tasks.trash(user.id, task_id, reparent, clock.now()).await?;
+    for id in deleted {
+        events.publish(user.id, TaskEvent::Deleted(id));
     }
+
+    Ok(status::NoContent)
 }
 
-#[delete("/tasks/<task_id>")]
-async fn delete_task(db: &State<DbConnPool>, task_id: u32) -> status::NoContent {
-    let pool = db.pool.lock().unwrap();
+// Initialize the database
+fn init_db(pool: &Pool) {
     let mut conn = pool.get_conn().unwrap();
 
-    conn.exec_drop(
-        "DELETE FROM tasks WHERE id = :id",
-        params! {
-            "id" => task_id,
-        },
-    )
-    .unwrap();
+    // Refuse to serve against a schema this build does not understand
+    if let Err(e) = schema::init(&mut conn) {
+        tracing::error!(error = %e, "Failed to migrate the database");
+        std::process::exit(1);
+    }
+}
 
-    status::NoContent
+// The /tasks routes
+fn task_routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        list_tasks,
+        get_task,
+        create_task,
+        update_task,
+        patch_task,
+        delete_task,
+        options::all_options
+    ]
 }
 
-// Initialize the database
-fn init_db() {
-    let pool = init_pool();
-    let mut conn = pool.get_conn().unwrap();
+// JSON bodies for requests that fail before reaching a handler
+fn error_catchers() -> Vec<rocket::Catcher> {
+    catchers![
+        db::service_unavailable,
+        auth::unauthorized,
+        rate_limit::too_many_requests,
+        guards::bad_request,
+        guards::unprocessable_entity
+    ]
+}
+
+// The whole server, built from `config` rather than the environment so
+// tests can point it at their own database
+fn build_rocket(config: AppConfig) -> Rocket<Build> {
+    let db = init_pool(&config);
+    init_db(&db.pool);
+
+    // Requests are logged by logging::RequestLogger instead
+    let figment = rocket::Config::figment()
+        .merge(("address", config.address))
+        .merge(("port", config.port))
+        .merge(("log_level", LogLevel::Off));
 
-    conn.query_drop(
-        r"CREATE TABLE IF NOT EXISTS tasks (
-            id INT PRIMARY KEY AUTO_INCREMENT,
-            description TEXT NOT NULL,
-            is_completed BOOLEAN NOT NULL DEFAULT false
-        )",
+    let store: SharedTaskStore = Arc::new(MySqlTaskStore::new(db.clone()));
+    let rocket = rocket::custom(figment)
+        .manage(db)
+        .manage(store)
+        .manage(TokenKeys::new(&config.jwt_secret, config.token_ttl_secs))
+        .manage(TaskEvents::default())
+        .manage(RateLimiter::new(config.rate_limit_per_minute))
+        .manage(config.clock.clone());
+
+    let rocket = openapi::mount(
+        rocket,
+        vec![
+            task_routes(),
+            health::routes(),
+            metrics::routes(),
+            auth::routes(),
+            bulk::routes(),
+            api_keys::routes(),
+            lists::routes(),
+            subtasks::routes(),
+            tags::routes(),
+            trash::routes(),
+            events::routes(),
+            ws::routes(),
+            webhooks::routes(),
+            schedules::routes(),
+            habits::routes(),
+            goals::routes(),
+            holidays::routes(),
+            schemas::routes(),
+            export::routes(),
+            pwa::routes(),
+        ],
     )
-    .unwrap();
-}
-
-// Set up and configure CORS
-fn cors_options() -> rocket_cors::Cors {
-    let allowed_origins =
-        AllowedOrigins::some_exact(&[
-            "http://localhost:8000", 
-            "http://techsbible.com",
-        ]);
-
-    CorsOptions {
-        allowed_origins,
-        allowed_methods: vec![
-            Method::Get,
-            Method::Post,
-            Method::Put,
-            Method::Delete,
-            Method::Options,
-        ]
-        .into_iter()
-        .map(From::from)
-        .collect(),
-        allow_credentials: true,
-        ..Default::default()
-    }
-    .to_cors()
-    .expect("Failed to create CORS options")
-}
-
-#[options("/<_..>")]
-fn all_options() -> rocket::http::Status {
-    rocket::http::Status::Ok
-}
-
-#[launch]
-fn rocket() -> _ {
-    init_db();
-    let db_pool = DbConnPool {
-        pool: Mutex::new(init_pool()),
+    .register("/", error_catchers())
+    .attach(request_id::RequestIds::new(config.trusted_proxies.clone()))
+    .attach(config.cors.fairing())
+    .attach(rate_limit::RateLimitHeaders)
+    .attach(metrics::Instrumentation)
+    .attach(logging::RequestLogger)
+    .attach(chaos::fairing())
+    .attach(webhooks::dispatcher());
+
+    if config.background_jobs {
+        rocket
+            .attach(schedules::scheduler())
+            .attach(trash::purger(config.trash_retention_days))
+            .attach(goals::recorder())
+    } else {
+        rocket
+    }
+}
+
+// Give the tasks and schedules from before accounts existed to the account
+// registered under `email`. Upgraded deployments run this once by hand.
+fn adopt(email: &str) {
+    let database_url = config::database_url().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let engine = Engine::connect(&database_url).expect("Failed to connect to the database");
+    let Some(user) = engine.user(email).expect("Failed to look up the user") else {
+        eprintln!("No account is registered for {}", email);
+        std::process::exit(1);
     };
 
-    rocket::build()
-        .manage(db_pool)
-        .mount(
-            "/",
-            routes![
-                list_tasks,
-                get_task,
-                create_task,
-                update_task,
-                delete_task,
-                all_options
-            ],
-        )
-        .attach(cors_options())
+    let (tasks, schedules) = engine
+        .adopt_unowned(user.id)
+        .expect("Failed to hand over the data");
+    println!(
+        "Gave {} tasks and {} schedules to {}",
+        tasks, schedules, email
+    );
+}
+
+// `todo_web_app tui <email>` opens the terminal client for that account and
+// `todo_web_app adopt <email>` hands it the data from before accounts;
+// anything else serves the API
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(command @ ("tui" | "adopt")) = args.get(1).map(String::as_str) {
+        match (command, args.get(2)) {
+            ("tui", Some(email)) => return tui::run(email),
+            ("adopt", Some(email)) => return adopt(email),
+            _ => {
+                eprintln!("Usage: todo_web_app {} <email>", command);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let config = AppConfig::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    logging::init(&config);
+    if let Err(e) = rocket::execute(build_rocket(config).launch()) {
+        tracing::error!("Server failed: {}", e);
+        std::process::exit(1);
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rocket::config::LogLevel;
+    use rocket::http::{Header, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use rocket::serde::json::{json, Value};
+    use rocket::tokio::sync::broadcast::Receiver;
+    use std::sync::Arc;
+    use todo_core::clock::{Clock, SharedClock, SystemClock};
+
+    use crate::events::{Published, TaskEvent, TaskEvents};
+    use crate::test_support::{bearer, MemoryApp, TestApp};
+    use crate::{cors, task_routes, trash};
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn tasks_require_a_token() {
+        let app = TestApp::spawn();
+
+        let response = app.client.get("/tasks").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn created_task_can_be_fetched() {
+        let app = TestApp::spawn();
+        let token = app.register("ada@example.com");
+
+        let response = app
+            .client
+            .post("/tasks")
+            .header(bearer(&token))
+            .json(&json!({ "description": "Write tests", "is_completed": false }))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let task: Value = response.into_json().unwrap();
+
+        let response = app
+            .client
+            .get(format!("/tasks/{}", task["id"]))
+            .header(bearer(&token))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let fetched: Value = response.into_json().unwrap();
+        assert_eq!(fetched["description"], "Write tests");
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn users_only_see_their_own_tasks() {
+        let app = TestApp::spawn();
+        let ada = app.register("ada@example.com");
+        let bob = app.register("bob@example.com");
+
+        app.client
+            .post("/tasks")
+            .header(bearer(&ada))
+            .json(&json!({ "description": "Ada's task", "is_completed": false }))
+            .dispatch();
+
+        let response = app.client.get("/tasks").header(bearer(&bob)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_json::<Value>().unwrap(), json!([]));
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn tasks_become_overdue_as_the_clock_moves() {
+        let app = TestApp::spawn();
+        let token = app.register("ada@example.com");
+        let due_date = app.clock.now() + Duration::hours(1);
+
+        app.client
+            .post("/tasks")
+            .header(bearer(&token))
+            .json(&json!({
+                "description": "File taxes",
+                "is_completed": false,
+                "due_date": due_date,
+            }))
+            .dispatch();
+
+        let overdue = |app: &TestApp| {
+            app.client
+                .get("/tasks?overdue=true")
+                .header(bearer(&token))
+                .dispatch()
+                .into_json::<Value>()
+                .unwrap()
+        };
+        assert_eq!(overdue(&app), json!([]));
+
+        app.clock.advance(Duration::hours(2));
+        assert_eq!(overdue(&app)[0]["description"], "File taxes");
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn replacing_a_task_keeps_edits_when_the_due_date_stays() {
+        let app = TestApp::spawn();
+        let token = app.register("ada@example.com");
+        let due_date = app.clock.now() + Duration::days(1);
+
+        let response = app
+            .client
+            .post("/tasks")
+            .header(bearer(&token))
+            .json(&json!({
+                "description": "File taxes",
+                "is_completed": false,
+                "due_date": due_date,
+            }))
+            .dispatch();
+        let task: Value = response.into_json().unwrap();
+
+        let response = app
+            .client
+            .put(format!("/tasks/{}", task["id"]))
+            .header(bearer(&token))
+            .json(&json!({
+                "description": "File taxes early",
+                "is_completed": true,
+                "priority": "high",
+                "due_date": due_date,
+            }))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let fetched: Value = app
+            .client
+            .get(format!("/tasks/{}", task["id"]))
+            .header(bearer(&token))
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(fetched["description"], "File taxes early");
+        assert_eq!(fetched["is_completed"], true);
+        assert_eq!(fetched["priority"], "high");
+        assert_eq!(fetched["due_date"], task["due_date"]);
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn authenticated_responses_carry_the_rate_limit() {
+        let app = TestApp::spawn();
+        let token = app.register("ada@example.com");
+
+        let remaining = |app: &TestApp| {
+            let response = app.client.get("/tasks").header(bearer(&token)).dispatch();
+            assert_eq!(
+                response.headers().get_one("X-RateLimit-Limit"),
+                Some("1000")
+            );
+            assert!(response.headers().get_one("X-RateLimit-Reset").is_some());
+            response
+                .headers()
+                .get_one("X-RateLimit-Remaining")
+                .map(str::to_string)
+        };
+        assert_eq!(remaining(&app).as_deref(), Some("999"));
+        assert_eq!(remaining(&app).as_deref(), Some("998"));
+
+        let response = app.client.get("/tasks").dispatch();
+        assert!(response.headers().get_one("X-RateLimit-Limit").is_none());
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn errors_carry_the_request_id() {
+        let app = TestApp::spawn();
+
+        let response = app
+            .client
+            .get("/tasks")
+            .header(Header::new("X-Request-Id", "spoofed"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let id = response
+            .headers()
+            .get_one("X-Request-Id")
+            .map(str::to_string)
+            .expect("request id");
+        assert_ne!(id, "spoofed");
+
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["request_id"], id.as_str());
+    }
+
+    #[test]
+    #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+    fn openapi_spec_describes_the_task_routes() {
+        let app = TestApp::spawn();
+
+        let response = app.client.get("/openapi.json").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let spec: Value = response.into_json().unwrap();
+        assert!(spec["paths"]["/tasks"]["get"].is_object());
+        assert!(spec["paths"]["/tasks/{task_id}"]["patch"].is_object());
+        assert!(spec["components"]["schemas"]["Task"].is_object());
+    }
+
+    #[test]
+    fn cors_wildcards_must_stay_under_a_domain() {
+        for origin in [
+            "https://*",
+            "http://*:8080",
+            "https://*.*",
+            "https://*.com",
+            "https://*example.com",
+        ] {
+            assert!(cors::parse_origins(origin).is_err(), "{}", origin);
+        }
+
+        let origins = cors::parse_origins("https://*.example.com,http://app-*.example.com:3000");
+        assert!(matches!(origins, Ok(cors::Origins::Some { patterns, .. }) if patterns.len() == 2));
+    }
+
+    #[rocket::async_test]
+    async fn options_prefers_literal_segments_to_parameters() {
+        let figment = rocket::Config::figment().merge(("log_level", LogLevel::Off));
+        let clock: SharedClock = Arc::new(SystemClock);
+        let rocket = rocket::custom(figment)
+            .manage(clock)
+            .manage(TaskEvents::default())
+            .mount("/", task_routes().0)
+            .mount("/", trash::routes().0);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let allow =
+            |response: LocalResponse| response.headers().get_one("Allow").map(str::to_string);
+        let response = client.options("/tasks/trash").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            allow(response).as_deref(),
+            Some("GET, HEAD, DELETE, OPTIONS")
+        );
+
+        let response = client.options("/tasks/7").dispatch().await;
+        assert_eq!(
+            allow(response).as_deref(),
+            Some("GET, HEAD, PUT, PATCH, DELETE, OPTIONS")
+        );
+    }
+
+    // The tests below run the /tasks routes against MemoryTaskStore, so they
+    // need no database
+
+    #[rocket::async_test]
+    async fn listing_pages_through_a_users_own_tasks() {
+        let app = MemoryApp::spawn().await;
+        for (user, description) in [(1, "Buy milk"), (1, "Walk dog"), (2, "Bob's task")] {
+            let response = app
+                .client
+                .post("/tasks")
+                .header(app.bearer(user))
+                .json(&json!({ "description": description, "is_completed": false }))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+        }
+
+        let response = app
+            .client
+            .get("/tasks?sort=description&order=desc&per_page=1")
+            .header(app.bearer(1))
+            .dispatch()
+            .await;
+        assert_eq!(response.headers().get_one("X-Total-Count"), Some("2"));
+        let page: Value = response.into_json().await.unwrap();
+        assert_eq!(page.as_array().map(Vec::len), Some(1));
+        assert_eq!(page[0]["description"], "Walk dog");
+
+        let response = app
+            .client
+            .get("/tasks?q=bob")
+            .header(app.bearer(1))
+            .dispatch()
+            .await;
+        assert_eq!(response.into_json::<Value>().await.unwrap(), json!([]));
+    }
+
+    #[rocket::async_test]
+    async fn only_the_change_that_completes_a_task_reports_it() {
+        let app = MemoryApp::spawn().await;
+        let mut events = app
+            .client
+            .rocket()
+            .state::<TaskEvents>()
+            .expect("TaskEvents is managed")
+            .subscribe();
+
+        let task: Value = app
+            .client
+            .post("/tasks")
+            .header(app.bearer(1))
+            .json(&json!({ "description": "Ship it", "is_completed": false }))
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        let completions = |events: &mut Receiver<Published>| {
+            let mut completed = Vec::new();
+            while let Ok(Published { event, .. }) = events.try_recv() {
+                if let TaskEvent::Updated {
+                    completed: done, ..
+                } = event
+                {
+                    completed.push(done);
+                }
+            }
+            completed
+        };
+
+        for _ in 0..2 {
+            let response = app
+                .client
+                .patch(format!("/tasks/{}", task["id"]))
+                .header(app.bearer(1))
+                .json(&json!({ "is_completed": true }))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+        assert_eq!(completions(&mut events), vec![true, false]);
+    }
+
+    #[rocket::async_test]
+    async fn a_patch_cannot_start_a_task_after_it_is_due() {
+        let app = MemoryApp::spawn().await;
+        let response = app
+            .client
+            .post("/tasks")
+            .header(app.bearer(1))
+            .json(&json!({ "description": "File taxes", "is_completed": false }))
+            .dispatch()
+            .await;
+        let task: Value = response.into_json().await.unwrap();
+
+        let response = app
+            .client
+            .patch(format!("/tasks/{}", task["id"]))
+            .header(app.bearer(1))
+            .json(&json!({
+                "start_date": "2024-04-16T00:00:00Z",
+                "due_date": "2024-04-15T00:00:00Z",
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["errors"][0]["field"], "start_date");
+    }
+
+    #[rocket::async_test]
+    async fn a_patch_cannot_move_one_date_past_the_stored_other() {
+        let app = MemoryApp::spawn().await;
+        let response = app
+            .client
+            .post("/tasks")
+            .header(app.bearer(1))
+            .json(&json!({
+                "description": "File taxes",
+                "is_completed": false,
+                "due_date": "2024-04-15T00:00:00Z",
+            }))
+            .dispatch()
+            .await;
+        let task: Value = response.into_json().await.unwrap();
+
+        let response = app
+            .client
+            .patch(format!("/tasks/{}", task["id"]))
+            .header(app.bearer(1))
+            .json(&json!({ "start_date": "2024-04-16T00:00:00Z" }))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["errors"][0]["field"], "start_date");
+
+        let response = app
+            .client
+            .get(format!("/tasks/{}", task["id"]))
+            .header(app.bearer(1))
+            .dispatch()
+            .await;
+        let stored: Value = response.into_json().await.unwrap();
+        assert_eq!(stored["start_date"], Value::Null);
+        assert_eq!(stored["due_date"], "2024-04-15T00:00:00Z");
+    }
+
+    #[rocket::async_test]
+    async fn tokens_expire_by_the_servers_clock() {
+        let app = MemoryApp::spawn().await;
+
+        // Issued two hours ago by the server's clock, so expired by the
+        // system time but not by the server's
+        app.clock.advance(Duration::hours(-2));
+        let token = app.bearer(1);
+        let response = app
+            .client
+            .get("/tasks")
+            .header(token.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        app.clock.advance(Duration::hours(2));
+        let response = app.client.get("/tasks").header(token).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn csv_listings_keep_their_header_and_defuse_formulas() {
+        let app = MemoryApp::spawn().await;
+        let csv = Header::new("Accept", "text/csv");
+
+        let response = app
+            .client
+            .get("/tasks")
+            .header(app.bearer(1))
+            .header(csv.clone())
+            .dispatch()
+            .await;
+        let body = response.into_string().await.unwrap();
+        assert!(body.starts_with("id,external_id,description,"));
+        assert_eq!(body.lines().count(), 1);
+
+        app.client
+            .post("/tasks")
+            .header(app.bearer(1))
+            .json(&json!({ "description": "=HYPERLINK(\"http://x\")", "is_completed": false }))
+            .dispatch()
+            .await;
+        let response = app
+            .client
+            .get("/tasks")
+            .header(app.bearer(1))
+            .header(csv)
+            .dispatch()
+            .await;
+        let body = response.into_string().await.unwrap();
+        assert!(body.lines().nth(1).unwrap().contains("'=HYPERLINK("));
+    }
+
+    #[rocket::async_test]
+    async fn deleting_a_task_can_keep_its_subtasks() {
+        let app = MemoryApp::spawn().await;
+        let create = |body: Value| {
+            let request = app.client.post("/tasks").header(app.bearer(1)).json(&body);
+            async move { request.dispatch().await.into_json::<Value>().await.unwrap() }
+        };
+        let parent = create(json!({ "description": "Move house", "is_completed": false })).await;
+        let child = create(json!({
+            "description": "Pack books",
+            "is_completed": false,
+            "parent_id": parent["id"],
+        }))
+        .await;
+
+        let response = app
+            .client
+            .post("/tasks")
+            .header(app.bearer(2))
+            .json(&json!({
+                "description": "Not yours",
+                "is_completed": false,
+                "parent_id": parent["id"],
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = app
+            .client
+            .delete(format!("/tasks/{}?subtasks=reparent", parent["id"]))
+            .header(app.bearer(1))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        let get = |id: &Value| {
+            app.client
+                .get(format!("/tasks/{}", id))
+                .header(app.bearer(1))
+        };
+        assert_eq!(
+            get(&parent["id"]).dispatch().await.status(),
+            Status::NotFound
+        );
+        let kept: Value = get(&child["id"])
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(kept["parent_id"], Value::Null);
+    }
+}
@@ -0,0 +1,337 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::Response;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Build, Data, Orbit, Rocket, State};
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use todo_core::clock::SharedClock;
+use todo_core::models::Priority;
+use todo_core::repository;
+
+use crate::db::DbConnPool;
+use crate::events::{TaskEvent, TaskEvents};
+
+// Prometheus metrics
+//
+// The Instrumentation fairing times every request, so routes are covered
+// without doing anything themselves. Routes are labelled by handler name,
+// and requests no route matched by "unmatched". GET /metrics serves these
+// alongside the database pool's numbers in the text exposition format.
+//
+// Business numbers sit next to them: tasks created and completed through the
+// API, counted off the task event stream, and open tasks past their due date,
+// counted when scraped. Counters are labelled by list id for the first
+// MAX_LIST_LABELS lists seen, "other" after that and "none" for tasks in no
+// list, so a busy server can't grow series without bound.
+
+// Upper bounds of the latency buckets, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// A Prometheus histogram of durations
+#[derive(Default)]
+pub struct Histogram {
+    // Observations per bucket, not yet cumulative
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&le| secs <= le) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    // The series of this histogram, `labels` being e.g. `route="list_tasks"`
+    pub fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (le, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+// Write the HELP and TYPE lines of a metric
+pub fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Requests seen by the fairing, keyed by method and route
+#[derive(Default)]
+struct RequestMetrics {
+    // Per status class too, e.g. "2xx"
+    counts: Mutex<BTreeMap<(String, String, String), u64>>,
+    latency: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl RequestMetrics {
+    fn record(&self, method: &str, route: &str, status: u16, took: Duration) {
+        let class = format!("{}xx", status / 100);
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string(), class))
+            .or_default() += 1;
+        self.latency
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(took);
+    }
+
+    fn render(&self, out: &mut String) {
+        header(
+            out,
+            "http_requests_total",
+            "counter",
+            "Requests handled, by route and status class.",
+        );
+        for ((method, route, class), count) in self.counts.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, class, count
+            );
+        }
+
+        header(
+            out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time from receiving a request to sending its response.",
+        );
+        for ((method, route), histogram) in self.latency.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            histogram.render(out, "http_request_duration_seconds", &labels);
+        }
+    }
+}
+
+const MAX_LIST_LABELS: usize = 100;
+
+// Task events by kind and list label
+#[derive(Default)]
+struct BusinessMetrics {
+    counts: Mutex<BTreeMap<(&'static str, String), u64>>,
+    // Lists that have a label of their own
+    labelled: Mutex<HashSet<u32>>,
+}
+
+impl BusinessMetrics {
+    fn list_label(&self, list_id: Option<u32>) -> String {
+        let Some(list_id) = list_id else {
+            return "none".to_string();
+        };
+
+        let mut labelled = self.labelled.lock().unwrap();
+        if labelled.contains(&list_id) || labelled.len() < MAX_LIST_LABELS {
+            labelled.insert(list_id);
+            list_id.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+
+    fn record(&self, event: &TaskEvent) {
+        let (metric, list_id) = match event {
+            TaskEvent::Created(task) => ("tasks_created_total", task.list_id),
+            TaskEvent::Updated {
+                task,
+                completed: true,
+            } => ("tasks_completed_total", task.list_id),
+            _ => return,
+        };
+        let list = self.list_label(list_id);
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry((metric, list))
+            .or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let counts = self.counts.lock().unwrap();
+        for (metric, help) in [
+            (
+                "tasks_created_total",
+                "Tasks created through the API, by list.",
+            ),
+            (
+                "tasks_completed_total",
+                "Tasks marked completed through the API, by list.",
+            ),
+        ] {
+            header(out, metric, "counter", help);
+            for ((_, list), count) in counts
+                .range((metric, String::new())..)
+                .take_while(|((counted, _), _)| *counted == metric)
+            {
+                let _ = writeln!(out, "{}{{list=\"{}\"}} {}", metric, list, count);
+            }
+        }
+    }
+}
+
+// Open tasks past their due date, per priority, or None if the database
+// couldn't be asked
+async fn overdue_tasks(db: &DbConnPool, clock: &SharedClock) -> Option<Vec<(u8, u64)>> {
+    let now = clock.now().naive_utc();
+    let result = match db.get_conn("metrics").await {
+        Ok(mut conn) => rocket::tokio::task::spawn_blocking(move || {
+            repository::tasks::count_overdue(&mut conn, now)
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|counted| counted.map_err(|e| e.to_string())),
+        Err(e) => Err(e.to_string()),
+    };
+
+    result
+        .map_err(|e| {
+            tracing::error!(
+                route = "metrics",
+                error = e,
+                "Failed to count overdue tasks"
+            )
+        })
+        .ok()
+}
+
+fn render_overdue(out: &mut String, counts: &[(u8, u64)]) {
+    header(
+        out,
+        "overdue_tasks",
+        "gauge",
+        "Open tasks past their due date, by priority.",
+    );
+    for priority in [Priority::Low, Priority::Medium, Priority::High] {
+        let count = counts
+            .iter()
+            .filter(|(stored, _)| Priority::from_u8(*stored) == priority)
+            .map(|(_, count)| count)
+            .sum::<u64>();
+        let _ = writeln!(
+            out,
+            "overdue_tasks{{priority=\"{}\"}} {}",
+            priority.as_str(),
+            count
+        );
+    }
+}
+
+// When the fairing first saw the request
+struct Started(Instant);
+
+pub struct Instrumentation;
+
+#[rocket::async_trait]
+impl Fairing for Instrumentation {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket
+            .manage(RequestMetrics::default())
+            .manage(Arc::new(BusinessMetrics::default())))
+    }
+
+    // Count task events as they are published
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let (Some(business), Some(events)) = (
+            rocket.state::<Arc<BusinessMetrics>>(),
+            rocket.state::<TaskEvents>(),
+        ) else {
+            return;
+        };
+        let business = business.clone();
+        let mut receiver = events.subscribe();
+
+        rocket::tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(published) => business.record(&published.event),
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "Business metrics fell behind")
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(metrics) = req.rocket().state::<RequestMetrics>() else {
+            return;
+        };
+        let took = req.local_cache(|| Started(Instant::now())).0.elapsed();
+        let route = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or("unmatched");
+
+        metrics.record(req.method().as_str(), route, res.status().code, took);
+    }
+}
+
+#[openapi(tag = "Monitoring")]
+#[get("/metrics")]
+async fn metrics(
+    requests: &State<RequestMetrics>,
+    business: &State<Arc<BusinessMetrics>>,
+    db: &State<DbConnPool>,
+    clock: &State<SharedClock>,
+) -> (ContentType, String) {
+    let mut body = String::new();
+    requests.render(&mut body);
+    db.render_metrics(&mut body);
+    business.render(&mut body);
+    if let Some(overdue) = overdue_tasks(db, clock).await {
+        render_overdue(&mut body, &overdue);
+    }
+
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        body,
+    )
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![metrics]
+}
@@ -0,0 +1,100 @@
+use rocket::{Build, Rocket, Route};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::merge::marge_spec_list;
+use rocket_okapi::okapi::openapi3::{
+    Info, Object, OpenApi, Parameter, ParameterValue, RefOr, SchemaObject,
+};
+use rocket_okapi::settings::OpenApiSettings;
+use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
+
+use crate::listing::per_page_parameter;
+
+// OpenAPI description of the API
+//
+// Routes carry `#[openapi]` next to their Rocket attribute, and each route
+// module's `routes()` returns its routes with the spec okapi generates from
+// their signatures and the schemas of the types they take and return.
+// `mount` mounts the routes and serves the merged spec at /openapi.json,
+// browsable with Swagger UI at /docs.
+
+const DOCS_PATH: &str = "/docs";
+
+fn info() -> Info {
+    Info {
+        title: "To-do API".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        ..Info::default()
+    }
+}
+
+// An optional query parameter
+pub fn query_parameter(name: &str, description: &str, schema: SchemaObject) -> Parameter {
+    Parameter {
+        name: name.to_string(),
+        location: "query".to_string(),
+        description: Some(description.to_string()),
+        required: false,
+        deprecated: false,
+        allow_empty_value: false,
+        value: ParameterValue::Schema {
+            style: None,
+            explode: None,
+            allow_reserved: false,
+            schema,
+            example: None,
+            examples: None,
+        },
+        extensions: Object::default(),
+    }
+}
+
+// Request guards describe at most one parameter, so Pagination documents
+// `page` and its `per_page` companion is added here
+fn add_per_page(spec: &mut OpenApi, per_page: Parameter) {
+    let operations = spec.paths.values_mut().flat_map(|item| {
+        [
+            &mut item.get,
+            &mut item.post,
+            &mut item.put,
+            &mut item.patch,
+        ]
+        .into_iter()
+        .flatten()
+    });
+
+    for operation in operations {
+        let paginated = operation.parameters.iter().any(|parameter| {
+            matches!(parameter, RefOr::Object(Parameter { name, location, .. })
+                if name == "page" && location == "query")
+        });
+        if paginated {
+            operation.parameters.push(per_page.clone().into());
+        }
+    }
+}
+
+// Mount every group of routes at the root, along with the spec and its docs
+pub fn mount(mut rocket: Rocket<Build>, groups: Vec<(Vec<Route>, OpenApi)>) -> Rocket<Build> {
+    let mut specs = Vec::new();
+    for (routes, spec) in groups {
+        rocket = rocket.mount("/", routes);
+        specs.push(("/", spec));
+    }
+
+    let mut spec = marge_spec_list(&specs).expect("Failed to merge the OpenAPI specs");
+    spec.info = info();
+    let settings = OpenApiSettings::default();
+    add_per_page(
+        &mut spec,
+        per_page_parameter(&mut OpenApiGenerator::new(&settings)),
+    );
+
+    let docs = SwaggerUIConfig {
+        url: settings.json_path.clone(),
+        ..SwaggerUIConfig::default()
+    };
+
+    rocket
+        .mount("/", vec![rocket_okapi::get_openapi_route(spec, &settings)])
+        .mount(DOCS_PATH, make_swagger_ui(&docs))
+}
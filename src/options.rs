@@ -0,0 +1,101 @@
+use rocket::http::{Header, Method, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket_okapi::openapi;
+use std::path::PathBuf;
+
+// Order methods are listed in the Allow header
+const METHODS: [Method; 7] = [
+    Method::Get,
+    Method::Head,
+    Method::Post,
+    Method::Put,
+    Method::Patch,
+    Method::Delete,
+    Method::Options,
+];
+
+// How a route path such as "/tasks/<task_id>" matches a request path, if it
+// does: for each request segment, whether the route spelled it out rather
+// than taking it as a parameter
+fn path_match(pattern: &str, path: &str) -> Option<Vec<bool>> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut literal = Vec::with_capacity(segments.len());
+
+    for part in pattern.split('/').filter(|s| !s.is_empty()) {
+        if part.starts_with('<') && part.ends_with("..>") {
+            literal.resize(segments.len(), false);
+            return Some(literal);
+        }
+
+        match segments.get(literal.len()) {
+            Some(_) if part.starts_with('<') => literal.push(false),
+            Some(segment) if part == *segment => literal.push(true),
+            _ => return None,
+        }
+    }
+
+    (literal.len() == segments.len()).then_some(literal)
+}
+
+// Methods some mounted route accepts for `path`, apart from the catch-all
+// OPTIONS route itself
+//
+// As in Rocket's router, a literal segment wins over a parameter, so
+// /tasks/trash is served by the trash routes alone and not /tasks/<task_id>.
+// Of the routes matching the path, only those that spell out the earliest
+// segments count.
+fn allowed_methods(req: &Request, path: &str) -> Vec<Method> {
+    let matches: Vec<(Method, Vec<bool>)> = req
+        .rocket()
+        .routes()
+        .filter(|route| route.method != Method::Options)
+        .filter_map(|route| Some((route.method, path_match(route.uri.path(), path)?)))
+        .collect();
+    let Some(best) = matches.iter().map(|(_, literal)| literal).max() else {
+        return Vec::new();
+    };
+    let found: Vec<Method> = matches
+        .iter()
+        .filter(|(_, literal)| literal == best)
+        .map(|(method, _)| *method)
+        .collect();
+
+    METHODS
+        .into_iter()
+        .filter(|method| match method {
+            // Rocket answers HEAD for every GET route
+            Method::Head => found.contains(&Method::Get),
+            Method::Options => true,
+            method => found.contains(method),
+        })
+        .collect()
+}
+
+// Allow header for a path, worked out when responding since that is where
+// the mounted routes are reachable
+pub struct AllowedMethods(String);
+
+impl<'r> Responder<'r, 'static> for AllowedMethods {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let methods = allowed_methods(req, &self.0);
+
+        if methods.is_empty() {
+            return Err(Status::NotFound);
+        }
+
+        let allow: Vec<&str> = methods.iter().map(|method| method.as_str()).collect();
+
+        Response::build()
+            .header(Header::new("Allow", allow.join(", ")))
+            .ok()
+    }
+}
+
+// Answer OPTIONS with the methods the path really supports, or 404 when no
+// route serves it
+#[openapi(skip)]
+#[options("/<path..>")]
+pub fn all_options(path: PathBuf) -> AllowedMethods {
+    AllowedMethods(format!("/{}", path.display()))
+}
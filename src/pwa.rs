@@ -0,0 +1,93 @@
+use rocket::http::{ContentType, Header};
+use rocket::serde::json::{json, Json, Value};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{OpenApi, Responses};
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+// Assets are compiled into the binary so a deploy always serves a matching set
+const MANIFEST: &str = include_str!("../static/manifest.webmanifest");
+const SERVICE_WORKER: &str = include_str!("../static/sw.js");
+
+// Set at build time to force clients to refresh even when the assets are
+// unchanged, e.g. APP_BUILD_ID=$(git rev-parse --short HEAD) cargo build
+const BUILD_ID: Option<&str> = option_env!("APP_BUILD_ID");
+
+fn hash_hex<T: Hash + ?Sized>(value: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// Headers for assets the browser must revalidate on every load
+#[derive(Responder)]
+struct Asset {
+    body: (ContentType, &'static str),
+    cache_control: Header<'static>,
+}
+
+impl OpenApiResponderInner for Asset {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        String::responses(gen)
+    }
+}
+
+fn asset(content_type: ContentType, body: &'static str) -> Asset {
+    Asset {
+        body: (content_type, body),
+        cache_control: Header::new("Cache-Control", "no-cache"),
+    }
+}
+
+#[openapi(tag = "App")]
+#[get("/manifest.webmanifest")]
+fn manifest() -> Asset {
+    asset(ContentType::new("application", "manifest+json"), MANIFEST)
+}
+
+#[openapi(tag = "App")]
+#[get("/sw.js")]
+fn service_worker() -> Asset {
+    asset(ContentType::JavaScript, SERVICE_WORKER)
+}
+
+#[derive(Responder)]
+struct Digest {
+    body: Json<Value>,
+    cache_control: Header<'static>,
+}
+
+impl OpenApiResponderInner for Digest {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Json::<Value>::responses(gen)
+    }
+}
+
+// Digest the service worker compares against to decide when caches are stale
+#[openapi(tag = "App")]
+#[get("/assets/digest")]
+fn digest() -> Digest {
+    let assets = [
+        ("/manifest.webmanifest", hash_hex(MANIFEST)),
+        ("/sw.js", hash_hex(SERVICE_WORKER)),
+    ];
+    let version = env!("CARGO_PKG_VERSION");
+    let digest = hash_hex(&(version, BUILD_ID, &assets));
+
+    Digest {
+        body: Json(json!({
+            "version": version,
+            "build": BUILD_ID,
+            "digest": digest,
+            "assets": assets.iter().cloned().collect::<BTreeMap<_, _>>(),
+        })),
+        cache_control: Header::new("Cache-Control", "no-store"),
+    }
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![manifest, service_worker, digest]
+}
@@ -0,0 +1,156 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::Request;
+use rocket::response::Response;
+use rocket::serde::json::{json, Json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use todo_core::clock::SharedClock;
+
+use crate::request_id;
+
+// Per-user request limits
+//
+// Each signed-in user may make `limit` requests per fixed one-minute window;
+// the rest of the window is answered with 429. Every authenticated response
+// carries the user's quota so clients can slow down before running out:
+//
+// - X-RateLimit-Limit: requests allowed per window
+// - X-RateLimit-Remaining: requests left in the current window
+// - X-RateLimit-Reset: when the window ends, in seconds since the epoch
+//
+// Counts are kept in memory, so each server instance limits on its own.
+
+const WINDOW_SECS: i64 = 60;
+
+pub const LIMIT_HEADER: &str = "X-RateLimit-Limit";
+pub const REMAINING_HEADER: &str = "X-RateLimit-Remaining";
+pub const RESET_HEADER: &str = "X-RateLimit-Reset";
+
+// Requests counted per user in the current window
+struct Window {
+    start: i64,
+    counts: HashMap<u32, u32>,
+}
+
+pub struct RateLimiter {
+    limit: u32,
+    window: Mutex<Window>,
+}
+
+// A user's standing after a request was counted
+#[derive(Clone, Copy)]
+struct Quota {
+    limit: u32,
+    remaining: u32,
+    reset: i64,
+    // Seconds until the window ends
+    retry_after: i64,
+    exceeded: bool,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        RateLimiter {
+            limit,
+            window: Mutex::new(Window {
+                start: 0,
+                counts: HashMap::new(),
+            }),
+        }
+    }
+
+    // Count one request by `user_id` at `now` (seconds since the epoch)
+    fn hit(&self, user_id: u32, now: i64) -> Quota {
+        let start = now - now.rem_euclid(WINDOW_SECS);
+        let mut window = self.window.lock().unwrap();
+        // Users who stopped making requests are forgotten with their window
+        if window.start != start {
+            window.start = start;
+            window.counts.clear();
+        }
+
+        let count = window.counts.entry(user_id).or_insert(0);
+        *count = count.saturating_add(1);
+
+        Quota {
+            limit: self.limit,
+            remaining: self.limit.saturating_sub(*count),
+            reset: start + WINDOW_SECS,
+            retry_after: start + WINDOW_SECS - now,
+            exceeded: *count > self.limit,
+        }
+    }
+}
+
+// The quota of the request's user, once the request has been counted
+struct Counted(Option<Quota>);
+
+// Count the request against `user_id`'s quota, returning whether it may go
+// ahead. A request is counted once however many guards authenticate it.
+pub fn admit(req: &Request<'_>, user_id: u32) -> bool {
+    let counted = req.local_cache(|| {
+        let limiter = req
+            .rocket()
+            .state::<RateLimiter>()
+            .expect("RateLimiter must be managed");
+        let clock = req
+            .rocket()
+            .state::<SharedClock>()
+            .expect("SharedClock must be managed");
+        Counted(Some(limiter.hit(user_id, clock.now().timestamp())))
+    });
+
+    counted.0.is_none_or(|quota| !quota.exceeded)
+}
+
+fn quota(req: &Request<'_>) -> Option<Quota> {
+    req.local_cache(|| Counted(None)).0
+}
+
+// Adds the quota headers to the responses of authenticated requests
+pub struct RateLimitHeaders;
+
+#[rocket::async_trait]
+impl Fairing for RateLimitHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limit headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(quota) = quota(req) else {
+            return;
+        };
+
+        res.set_header(Header::new(LIMIT_HEADER, quota.limit.to_string()));
+        res.set_header(Header::new(REMAINING_HEADER, quota.remaining.to_string()));
+        res.set_header(Header::new(RESET_HEADER, quota.reset.to_string()));
+    }
+}
+
+// 429 body with a Retry-After of the rest of the window
+#[derive(Responder)]
+#[response(status = 429, content_type = "json")]
+pub struct TooManyRequests {
+    body: Json<Value>,
+    retry_after: Header<'static>,
+}
+
+#[catch(429)]
+pub fn too_many_requests(req: &Request<'_>) -> TooManyRequests {
+    let retry_after = quota(req).map_or(WINDOW_SECS, |quota| quota.retry_after);
+
+    TooManyRequests {
+        body: Json(json!({
+            "status": 429,
+            "code": "rate_limited",
+            "message": "Too many requests, slow down",
+            "request_id": request_id::of(req),
+        })),
+        retry_after: Header::new("Retry-After", retry_after.to_string()),
+    }
+}
@@ -0,0 +1,99 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Response;
+use rocket::Data;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use std::fmt;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+// Request ids
+//
+// Every request gets an id, sent back in X-Request-Id and included in error
+// bodies and the request log, so a client's bug report can be matched with
+// the server's logs. A proxy listed in TRUSTED_PROXIES may pass its own id in
+// X-Request-Id; anyone else's is ignored and a fresh UUID used instead.
+
+pub const HEADER: &str = "X-Request-Id";
+
+// Longest incoming id that is taken as is
+const MAX_LEN: usize = 128;
+
+// The current request's id. Handlers can take it as a guard.
+#[derive(Clone)]
+pub struct RequestId(String);
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// The current request's id, made up on the spot if the fairing didn't run
+pub fn of<'r>(req: &'r Request<'_>) -> &'r str {
+    &req.local_cache(|| RequestId(Uuid::new_v4().to_string())).0
+}
+
+fn acceptable(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+pub struct RequestIds {
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl RequestIds {
+    pub fn new(trusted_proxies: Vec<IpAddr>) -> Self {
+        RequestIds { trusted_proxies }
+    }
+
+    fn trusts(&self, req: &Request<'_>) -> bool {
+        req.remote()
+            .is_some_and(|peer| self.trusted_proxies.contains(&peer.ip()))
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ids",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let incoming = req
+            .headers()
+            .get_one(HEADER)
+            .filter(|id| acceptable(id) && self.trusts(req))
+            .map(str::to_string);
+
+        req.local_cache(|| RequestId(incoming.unwrap_or_else(|| Uuid::new_v4().to_string())));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        res.set_header(Header::new(HEADER, of(req).to_string()));
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId(of(req).to_string()))
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for RequestId {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
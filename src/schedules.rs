@@ -0,0 +1,390 @@
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use mysql::{PooledConn, TxOpts};
+use rocket::fairing::AdHoc;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+use std::time::Duration;
+use todo_core::clock::SharedClock;
+use todo_core::models::{Schedule, ScheduleException, ScheduleRun};
+use todo_core::scheduling::validate;
+use todo_core::validation::{FieldError, Validate};
+use todo_core::{calendar, repository, scheduling};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::{DbConn, DbConnPool};
+use crate::error::ApiError;
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+
+// How often the scheduler looks for schedules that are due
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+// Schedule routes
+
+#[openapi(tag = "Schedules")]
+#[get("/schedules")]
+async fn list_schedules(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<Listing<Schedule>, ApiError> {
+    let schedules = conn
+        .run(move |c| repository::schedules::list(c, user.id))
+        .await?;
+
+    Ok(Listing::all(schedules))
+}
+
+#[openapi(tag = "Schedules")]
+#[get("/schedules/<schedule_id>")]
+async fn get_schedule(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+) -> Result<Json<Schedule>, ApiError> {
+    conn.run(move |c| repository::schedules::find(c, user.id, schedule_id))
+        .await?
+        .map(Json)
+        .ok_or(ApiError::NotFound("schedule"))
+}
+
+#[openapi(tag = "Schedules")]
+#[post("/schedules", format = "json", data = "<schedule>")]
+async fn create_schedule(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    schedule: ValidatedJson<Schedule>,
+) -> Result<status::Created<Json<Schedule>>, ApiError> {
+    let calendar = conn.run(move |c| calendar::load(c, user.id)).await?;
+    let next_run_at =
+        validate(&schedule, &calendar, &[], clock.now()).map_err(ApiError::BadRequest)?;
+
+    let mut new_schedule = schedule.into_inner();
+    let row = new_schedule.clone();
+    let last_id = conn
+        .run(move |c| repository::schedules::insert(c, user.id, &row, next_run_at))
+        .await?;
+    new_schedule.id = Some(last_id);
+    new_schedule.next_run_at = Some(next_run_at.and_utc());
+
+    Ok(status::Created::new(format!("/schedules/{}", last_id)).body(Json(new_schedule)))
+}
+
+#[openapi(tag = "Schedules")]
+#[put("/schedules/<schedule_id>", format = "json", data = "<schedule>")]
+async fn update_schedule(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+    schedule: ValidatedJson<Schedule>,
+) -> Result<Json<Schedule>, ApiError> {
+    let (calendar, exceptions) = conn
+        .run(move |c| {
+            let exceptions = repository::schedules::exceptions(c, schedule_id)?;
+            Ok::<_, mysql::Error>((calendar::load(c, user.id)?, exceptions))
+        })
+        .await?;
+    let next_run_at =
+        validate(&schedule, &calendar, &exceptions, clock.now()).map_err(ApiError::BadRequest)?;
+
+    let mut schedule = schedule.into_inner();
+    let row = schedule.clone();
+    let found = conn
+        .run(move |c| repository::schedules::update(c, user.id, schedule_id, &row, next_run_at))
+        .await?;
+    if !found {
+        return Err(ApiError::NotFound("schedule"));
+    }
+
+    schedule.id = Some(schedule_id);
+    schedule.next_run_at = Some(next_run_at.and_utc());
+    Ok(Json(schedule))
+}
+
+#[openapi(tag = "Schedules")]
+#[delete("/schedules/<schedule_id>")]
+async fn delete_schedule(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::schedules::delete(c, user.id, schedule_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+// Occurrence routes
+//
+// A single run of a schedule can be skipped or moved without touching its
+// cron. Occurrences are named by their date in the schedule's timezone, e.g.
+// POST /schedules/3/occurrences/2024-12-25/skip; on days the cron runs more
+// than once, that is the first run of the day.
+
+// Body of POST /schedules/<id>/occurrences/<date>/move
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct Move {
+    to: DateTime<Utc>,
+}
+
+// Nothing to check without the clock; the handler makes sure `to` is ahead
+impl Validate for Move {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        Ok(())
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| ApiError::BadRequest("dates must look like 2024-12-25".to_string()))
+}
+
+// The occurrence of a user's schedule on `date`, which must be still to come
+fn find_occurrence(
+    conn: &mut PooledConn,
+    user_id: u32,
+    schedule_id: u32,
+    date: NaiveDate,
+    now: DateTime<Utc>,
+) -> Result<(Schedule, DateTime<Utc>), ApiError> {
+    let schedule = repository::schedules::find(conn, user_id, schedule_id)?
+        .ok_or(ApiError::NotFound("schedule"))?;
+    let recurrence_id = scheduling::occurrence_on(&schedule, date)
+        .map_err(ApiError::BadRequest)?
+        .ok_or(ApiError::NotFound("occurrence"))?;
+    if recurrence_id <= now {
+        return Err(ApiError::BadRequest(
+            "that occurrence has already happened".to_string(),
+        ));
+    }
+
+    Ok((schedule, recurrence_id))
+}
+
+// Record a change to one occurrence and move the schedule's next run to
+// match
+fn change_occurrence(
+    conn: &mut PooledConn,
+    user_id: u32,
+    schedule_id: u32,
+    date: NaiveDate,
+    moved_to: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<ScheduleException, ApiError> {
+    let (schedule, recurrence_id) = find_occurrence(conn, user_id, schedule_id, date, now)?;
+    let exception = ScheduleException {
+        recurrence_id,
+        moved_to,
+    };
+
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    repository::schedules::set_exception(&mut tx, schedule_id, &exception)?;
+    let exceptions = repository::schedules::exceptions(&mut tx, schedule_id)?;
+    let calendar = calendar::load(&mut tx, user_id)?;
+    let next_run_at = validate(&schedule, &calendar, &exceptions, now).map_err(|_| {
+        ApiError::BadRequest("that would leave the schedule with no runs".to_string())
+    })?;
+    repository::schedules::set_next_run(&mut tx, schedule_id, next_run_at)?;
+    tx.commit()?;
+
+    Ok(exception)
+}
+
+#[openapi(tag = "Schedules")]
+#[get("/schedules/<schedule_id>/exceptions")]
+async fn list_exceptions(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+) -> Result<Listing<ScheduleException>, ApiError> {
+    let exceptions = conn
+        .run(move |c| -> Result<_, ApiError> {
+            repository::schedules::find(c, user.id, schedule_id)?
+                .ok_or(ApiError::NotFound("schedule"))?;
+            Ok(repository::schedules::exceptions(c, schedule_id)?)
+        })
+        .await?;
+
+    Ok(Listing::all(exceptions))
+}
+
+#[openapi(tag = "Schedules")]
+#[post("/schedules/<schedule_id>/occurrences/<date>/skip")]
+async fn skip_occurrence(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+    date: &str,
+) -> Result<Json<ScheduleException>, ApiError> {
+    let date = parse_date(date)?;
+    let now = clock.now();
+    conn.run(move |c| change_occurrence(c, user.id, schedule_id, date, None, now))
+        .await
+        .map(Json)
+}
+
+#[openapi(tag = "Schedules")]
+#[post(
+    "/schedules/<schedule_id>/occurrences/<date>/move",
+    format = "json",
+    data = "<target>"
+)]
+async fn move_occurrence(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+    date: &str,
+    target: ValidatedJson<Move>,
+) -> Result<Json<ScheduleException>, ApiError> {
+    let date = parse_date(date)?;
+    let now = clock.now();
+    let to = target.into_inner().to;
+    if to <= now {
+        return Err(ApiError::BadRequest(
+            "occurrences can only be moved into the future".to_string(),
+        ));
+    }
+
+    conn.run(move |c| change_occurrence(c, user.id, schedule_id, date, Some(to), now))
+        .await
+        .map(Json)
+}
+
+// Days of history GET /schedules/<id>/occurrences covers by default, and
+// the most any history covers
+const DEFAULT_HISTORY_DAYS: u32 = 30;
+const MAX_HISTORY_DAYS: u32 = 366;
+
+// A `range` like 90d, `default` days when there is none
+pub fn parse_range(range: Option<&str>, default: u32) -> Result<u32, ApiError> {
+    let Some(range) = range else {
+        return Ok(default);
+    };
+
+    range
+        .strip_suffix('d')
+        .and_then(|days| days.parse().ok())
+        .filter(|days| (1..=MAX_HISTORY_DAYS).contains(days))
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "range must be a number of days from 1d to {}d",
+                MAX_HISTORY_DAYS
+            ))
+        })
+}
+
+// How each run of a schedule in the last `range` days turned out, oldest
+// first, e.g. GET /schedules/3/occurrences?range=90d
+#[openapi(tag = "Schedules")]
+#[get("/schedules/<schedule_id>/occurrences?<range>")]
+async fn list_runs(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+    range: Option<&str>,
+) -> Result<Listing<ScheduleRun>, ApiError> {
+    let days = parse_range(range, DEFAULT_HISTORY_DAYS)?;
+    let now = clock.now();
+    let since = now - ChronoDuration::days(days.into());
+
+    let runs = conn
+        .run(move |c| -> Result<_, ApiError> {
+            let schedule = repository::schedules::find(c, user.id, schedule_id)?
+                .ok_or(ApiError::NotFound("schedule"))?;
+            Ok(scheduling::history(c, &schedule, since, now)?)
+        })
+        .await?;
+
+    Ok(Listing::all(runs))
+}
+
+// Undo a skip or move, putting the occurrence back where the cron has it
+#[openapi(tag = "Schedules")]
+#[delete("/schedules/<schedule_id>/occurrences/<date>")]
+async fn restore_occurrence(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    schedule_id: u32,
+    date: &str,
+) -> Result<status::NoContent, ApiError> {
+    let date = parse_date(date)?;
+    let now = clock.now();
+    conn.run(move |c| -> Result<_, ApiError> {
+        let (schedule, recurrence_id) = find_occurrence(c, user.id, schedule_id, date, now)?;
+
+        let mut tx = c.start_transaction(TxOpts::default())?;
+        if !repository::schedules::delete_exception(&mut tx, schedule_id, recurrence_id)? {
+            return Err(ApiError::NotFound("change to that occurrence"));
+        }
+        let exceptions = repository::schedules::exceptions(&mut tx, schedule_id)?;
+        let calendar = calendar::load(&mut tx, user.id)?;
+        let next_run_at =
+            validate(&schedule, &calendar, &exceptions, now).map_err(ApiError::BadRequest)?;
+        repository::schedules::set_next_run(&mut tx, schedule_id, next_run_at)?;
+        Ok(tx.commit()?)
+    })
+    .await?;
+
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        list_schedules,
+        get_schedule,
+        create_schedule,
+        update_schedule,
+        delete_schedule,
+        list_exceptions,
+        list_runs,
+        skip_occurrence,
+        move_occurrence,
+        restore_occurrence
+    ]
+}
+
+// Background scheduler, started once Rocket is listening
+pub fn scheduler() -> AdHoc {
+    AdHoc::on_liftoff("Schedule runner", |rocket| {
+        Box::pin(async move {
+            let pool = rocket
+                .state::<DbConnPool>()
+                .expect("DbConnPool must be managed")
+                .pool
+                .clone();
+            let clock = rocket
+                .state::<SharedClock>()
+                .expect("SharedClock must be managed")
+                .clone();
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(SCHEDULER_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let pool = pool.clone();
+                    let now = clock.now();
+                    let result = rocket::tokio::task::spawn_blocking(move || {
+                        scheduling::run_due(&pool, now)
+                    })
+                    .await;
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => tracing::error!(error = %e, "Scheduler run failed"),
+                        Err(e) => tracing::error!(error = %e, "Scheduler run panicked"),
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,26 @@
+use rocket::http::ContentType;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::schema_for;
+
+use todo_core::models::Task;
+
+// JSON Schemas of the API's payloads, generated from the models it
+// serializes, so clients and webhook consumers can validate what they get
+// without keeping their own copy in step
+
+fn schema_json() -> ContentType {
+    ContentType::new("application", "schema+json")
+}
+
+// A task as the /tasks routes take and return it
+#[openapi(tag = "Schemas")]
+#[get("/schemas/task.json")]
+fn task_schema() -> (ContentType, String) {
+    let schema = serde_json::to_string_pretty(&schema_for!(Task)).expect("schemas serialize");
+    (schema_json(), schema)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![task_schema]
+}
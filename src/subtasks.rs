@@ -0,0 +1,174 @@
+use mysql::prelude::Queryable;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{OpenApi, Responses};
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::produce_any_responses;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+
+use todo_core::models::Task;
+use todo_core::repository;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::ApiError;
+use crate::listing::Listing;
+
+// Deepest a task may be nested, counting top-level tasks as depth 1. This
+// keeps deletes within MySQL's limit on chained ON DELETE CASCADE actions.
+const MAX_DEPTH: usize = 10;
+
+// `?include=` values for GET /tasks/<id>
+#[derive(FromFormField, JsonSchema)]
+#[schemars(rename_all = "snake_case")]
+pub enum Include {
+    Subtasks,
+}
+
+// `?subtasks=` values for DELETE /tasks/<id>: delete the subtasks along with
+// the task (the default), or move them up to the task's parent
+#[derive(FromFormField, JsonSchema)]
+#[schemars(rename_all = "snake_case")]
+pub enum OnDelete {
+    Delete,
+    Reparent,
+}
+
+// A task with all of its subtasks, nested
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct TaskTree {
+    #[serde(flatten)]
+    task: Task,
+    subtasks: Vec<TaskTree>,
+}
+
+impl TaskTree {
+    pub fn load<Q: Queryable>(conn: &mut Q, user_id: u32, task: Task) -> mysql::Result<Self> {
+        TaskTree::build(task, &mut |id| {
+            repository::tasks::children(conn, user_id, id)
+        })
+    }
+
+    // The tree under `task`, asking `children` for the direct subtasks of
+    // each task in it
+    pub fn build<E>(
+        task: Task,
+        children: &mut impl FnMut(u32) -> Result<Vec<Task>, E>,
+    ) -> Result<Self, E> {
+        let direct = match task.id {
+            Some(id) => children(id)?,
+            None => Vec::new(),
+        };
+        let subtasks = direct
+            .into_iter()
+            .map(|child| TaskTree::build(child, children))
+            .collect::<Result<_, E>>()?;
+
+        Ok(TaskTree { task, subtasks })
+    }
+
+    // Levels in the tree, 1 for a task without subtasks
+    fn height(&self) -> usize {
+        1 + self
+            .subtasks
+            .iter()
+            .map(TaskTree::height)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Responder)]
+pub enum TaskDetail {
+    Task(Json<Task>),
+    Tree(Json<TaskTree>),
+}
+
+impl OpenApiResponderInner for TaskDetail {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        produce_any_responses(
+            Json::<Task>::responses(gen)?,
+            Json::<TaskTree>::responses(gen)?,
+        )
+    }
+}
+
+// Subtasks may only be nested under the user's own tasks, never under
+// themselves or their own subtasks, and no deeper than MAX_DEPTH.
+// `task_id` is the task being moved, if it exists already.
+pub fn check_parent<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    task_id: Option<u32>,
+    parent_id: Option<u32>,
+) -> Result<(), ApiError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+
+    // Levels from the top down to and including the new parent
+    let mut depth = 0;
+    let mut ancestor = Some(parent_id);
+    while let Some(id) = ancestor {
+        if Some(id) == task_id {
+            return Err(ApiError::BadRequest(
+                "a task cannot be a subtask of itself or of its own subtasks".to_string(),
+            ));
+        }
+        match repository::tasks::parent_id(conn, user_id, id)? {
+            Some(parent) => ancestor = parent,
+            None => {
+                return Err(ApiError::BadRequest(format!(
+                    "task {} does not exist",
+                    parent_id
+                )))
+            }
+        }
+        depth += 1;
+    }
+
+    let height = match task_id {
+        Some(id) => match repository::tasks::find(conn, user_id, id)? {
+            Some(task) => TaskTree::load(conn, user_id, task)?.height(),
+            None => 1,
+        },
+        None => 1,
+    };
+    if depth + height > MAX_DEPTH {
+        return Err(ApiError::BadRequest(format!(
+            "subtasks can be nested at most {} levels deep",
+            MAX_DEPTH
+        )));
+    }
+
+    Ok(())
+}
+
+// Subtask routes
+
+// The direct subtasks of a task
+#[openapi(tag = "Tasks")]
+#[get("/tasks/<task_id>/subtasks")]
+async fn list_subtasks(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    task_id: u32,
+) -> Result<Listing<Task>, ApiError> {
+    let subtasks = conn
+        .run(move |c| -> Result<_, ApiError> {
+            if repository::tasks::parent_id(c, user.id, task_id)?.is_none() {
+                return Err(ApiError::NotFound("task"));
+            }
+            Ok(repository::tasks::children(c, user.id, task_id)?)
+        })
+        .await?;
+
+    Ok(Listing::all(subtasks))
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![list_subtasks]
+}
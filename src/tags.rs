@@ -0,0 +1,71 @@
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+
+use todo_core::models::Tag;
+use todo_core::repository;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConn;
+use crate::error::{is_duplicate, ApiError};
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+
+// Tag routes
+//
+// Tags are also created on the fly when a task names one the user doesn't
+// have yet, so POST /tags is only needed to set one up ahead of time.
+
+#[openapi(tag = "Tags")]
+#[get("/tags")]
+async fn list_tags(mut conn: DbConn, user: AuthenticatedUser) -> Result<Listing<Tag>, ApiError> {
+    let tags = conn
+        .run(move |c| repository::tags::list(c, user.id))
+        .await?;
+
+    Ok(Listing::all(tags))
+}
+
+#[openapi(tag = "Tags")]
+#[post("/tags", format = "json", data = "<tag>")]
+async fn create_tag(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    tag: ValidatedJson<Tag>,
+) -> Result<status::Created<Json<Tag>>, ApiError> {
+    let mut new_tag = tag.into_inner();
+    new_tag.name = new_tag.name.trim().to_string();
+    let row = new_tag.clone();
+    let id = conn
+        .run(move |c| repository::tags::insert(c, user.id, &row))
+        .await
+        .map_err(|e| {
+            if is_duplicate(&e) {
+                ApiError::Conflict(format!("tag {} already exists", new_tag.name))
+            } else {
+                ApiError::Database(e)
+            }
+        })?;
+    new_tag.id = Some(id);
+
+    Ok(status::Created::new(format!("/tags/{}", id)).body(Json(new_tag)))
+}
+
+// Deleting a tag takes it off every task
+#[openapi(tag = "Tags")]
+#[delete("/tags/<tag_id>")]
+async fn delete_tag(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    tag_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::tags::delete(c, user.id, tag_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![list_tags, create_tag, delete_tag]
+}
@@ -0,0 +1,220 @@
+use chrono::{DateTime, Utc};
+use mysql::PooledConn;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use todo_core::models::{Task, TaskPatch};
+use todo_core::repository;
+use todo_core::repository::tasks::TaskQuery;
+
+use crate::chaos;
+use crate::db::DbConnPool;
+use crate::error::{add_error_response, ApiError};
+use crate::subtasks::TaskTree;
+use crate::{apply_patch, insert_task, replace_task, trash_task};
+
+// Storage behind the /tasks routes
+//
+// The server keeps tasks in MySQL through MySqlTaskStore. The routes only see
+// the SharedTaskStore managed by Rocket, so tests can run them against an
+// in-memory store instead of a database.
+#[rocket::async_trait]
+pub trait TaskStore: Send + Sync {
+    // One page of the user's tasks matching `query`, and how many match
+    async fn search(
+        &self,
+        user_id: u32,
+        query: TaskQuery,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<Task>, u64), ApiError>;
+
+    async fn find(&self, user_id: u32, task_id: u32) -> Result<Option<Task>, ApiError>;
+
+    // A task with its subtasks nested under it
+    async fn tree(&self, user_id: u32, task_id: u32) -> Result<Option<TaskTree>, ApiError>;
+
+    // Insert a validated task, returning its id
+    async fn insert(&self, user_id: u32, task: Task, now: DateTime<Utc>) -> Result<u32, ApiError>;
+
+    // Overwrite a task, returning it as saved and whether this completed it
+    async fn replace(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        task: Task,
+    ) -> Result<Option<(Task, bool)>, ApiError>;
+
+    // Apply a patch, returning the task as saved and whether this completed it
+    async fn patch(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        patch: TaskPatch,
+    ) -> Result<Option<(Task, bool)>, ApiError>;
+
+    // Move a task and its subtasks to the trash, optionally moving the
+    // subtasks up to the task's parent first. Returns the ids moved.
+    async fn trash(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        reparent: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u32>, ApiError>;
+}
+
+pub type SharedTaskStore = Arc<dyn TaskStore>;
+
+// Tasks in MySQL. Each call checks out its own connection under the name of
+// the route it serves, so per-route acquire timeouts and metrics still apply.
+pub struct MySqlTaskStore {
+    db: DbConnPool,
+}
+
+impl MySqlTaskStore {
+    pub fn new(db: DbConnPool) -> Self {
+        MySqlTaskStore { db }
+    }
+
+    async fn run<F, R>(&self, route: &str, f: F) -> Result<R, ApiError>
+    where
+        F: FnOnce(&mut PooledConn) -> Result<R, ApiError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut conn = self
+            .db
+            .checkout(route)
+            .await
+            .map_err(ApiError::Unavailable)?;
+        conn.run(f).await
+    }
+}
+
+#[rocket::async_trait]
+impl TaskStore for MySqlTaskStore {
+    async fn search(
+        &self,
+        user_id: u32,
+        query: TaskQuery,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<Task>, u64), ApiError> {
+        self.run("list_tasks", move |c| {
+            let tasks = repository::tasks::search(c, user_id, &query, limit, offset)?;
+            Ok((tasks, repository::tasks::count(c, user_id, &query)?))
+        })
+        .await
+    }
+
+    async fn find(&self, user_id: u32, task_id: u32) -> Result<Option<Task>, ApiError> {
+        self.run("get_task", move |c| {
+            Ok(repository::tasks::find(c, user_id, task_id)?)
+        })
+        .await
+    }
+
+    async fn tree(&self, user_id: u32, task_id: u32) -> Result<Option<TaskTree>, ApiError> {
+        self.run("get_task", move |c| {
+            let Some(task) = repository::tasks::find(c, user_id, task_id)? else {
+                return Ok(None);
+            };
+            Ok(Some(TaskTree::load(c, user_id, task)?))
+        })
+        .await
+    }
+
+    async fn insert(&self, user_id: u32, task: Task, now: DateTime<Utc>) -> Result<u32, ApiError> {
+        self.run("create_task", move |c| insert_task(c, user_id, &task, now))
+            .await
+    }
+
+    async fn replace(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        task: Task,
+    ) -> Result<Option<(Task, bool)>, ApiError> {
+        self.run("update_task", move |c| {
+            replace_task(c, user_id, task_id, &task)
+        })
+        .await
+    }
+
+    async fn patch(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        patch: TaskPatch,
+    ) -> Result<Option<(Task, bool)>, ApiError> {
+        self.run("patch_task", move |c| {
+            apply_patch(c, user_id, task_id, &patch)
+        })
+        .await
+    }
+
+    async fn trash(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        reparent: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u32>, ApiError> {
+        self.run("delete_task", move |c| {
+            Ok(trash_task(c, user_id, task_id, reparent, now.naive_utc())?)
+        })
+        .await
+    }
+}
+
+// The managed task store, for handlers
+//
+// Fails the request with 503 when chaos mode fails its database, as the
+// DbConn guard does.
+pub struct Tasks<'r>(&'r SharedTaskStore);
+
+impl Deref for Tasks<'_> {
+    type Target = dyn TaskStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Tasks<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let store = req
+            .rocket()
+            .state::<SharedTaskStore>()
+            .expect("SharedTaskStore must be managed");
+
+        if chaos::fails_database(req) {
+            return Outcome::Error((Status::ServiceUnavailable, ()));
+        }
+        Outcome::Success(Tasks(store))
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for Tasks<'r> {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+
+    fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_error_response(gen, &mut responses, 503)?;
+        Ok(responses)
+    }
+}
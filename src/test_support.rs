@@ -0,0 +1,503 @@
+// Support for black-box HTTP tests
+//
+// `TestApp::spawn` builds the server against a throwaway database created on
+// the MySQL server in TEST_DATABASE_URL, e.g. `mysql://root:pw@localhost:3306`,
+// and drops the database again once the app goes out of scope. Each app gets
+// its own database, so tests can run in parallel. Tests that use it are
+// ignored by default, so `cargo test` reports them as ignored rather than
+// passed without a server; run them with `cargo test -- --ignored`, where
+// `spawn` panics if TEST_DATABASE_URL is unset:
+//
+//     #[test]
+//     #[ignore = "needs MySQL in TEST_DATABASE_URL"]
+//     fn ... {
+//         let app = TestApp::spawn();
+//         let token = app.register("ada@example.com");
+//         let response = app.client.get("/tasks").header(bearer(&token)).dispatch();
+//     }
+//
+// `MemoryApp` serves just the /tasks routes from a MemoryTaskStore, so task
+// handling can be tested without any database:
+//
+//     let app = MemoryApp::spawn().await;
+//     let response = app.client.get("/tasks").header(app.bearer(1)).dispatch().await;
+
+use chrono::{DateTime, Utc};
+use mysql::prelude::*;
+use mysql::Pool;
+use rocket::config::LogLevel;
+use rocket::http::{Header, Status};
+use rocket::local::{asynchronous, blocking::Client};
+use rocket::serde::json::{json, Value};
+use std::cmp::Ordering as SortOrdering;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use todo_core::clock::{Clock, FixedClock, SharedClock};
+use todo_core::models::{Task, TaskPatch};
+use todo_core::repository::tasks::{TaskQuery, TaskSort};
+use todo_core::validation::check_patched_dates;
+
+use crate::auth::TokenKeys;
+use crate::config::AppConfig;
+use crate::cors::CorsSettings;
+use crate::db::AcquireTimeouts;
+use crate::error::ApiError;
+use crate::events::TaskEvents;
+use crate::logging::{self, LogFormat};
+use crate::rate_limit::RateLimiter;
+use crate::subtasks::TaskTree;
+use crate::task_store::{SharedTaskStore, TaskStore};
+use crate::{build_rocket, error_catchers, task_routes};
+
+const TEST_PASSWORD: &str = "correct horse battery";
+
+static NEXT_DATABASE: AtomicU32 = AtomicU32::new(0);
+
+// `mysql://user:pw@host:port/db?opts` without the `/db?opts`
+fn server_root(url: &str) -> &str {
+    let authority = url.find("://").map_or(0, |i| i + 3);
+    let end = url[authority..]
+        .find(['/', '?'])
+        .map_or(url.len(), |i| authority + i);
+    &url[..end]
+}
+
+// A database that only lives as long as the test using it
+struct EphemeralDb {
+    admin: Pool,
+    name: String,
+    url: String,
+}
+
+impl EphemeralDb {
+    fn create(server_url: &str) -> Self {
+        let admin = Pool::new(server_url).expect("Failed to connect to TEST_DATABASE_URL");
+        let name = format!(
+            "todo_test_{}_{}",
+            std::process::id(),
+            NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+        );
+        admin
+            .get_conn()
+            .and_then(|mut conn| conn.query_drop(format!("CREATE DATABASE {}", name)))
+            .expect("Failed to create the test database");
+
+        let url = format!("{}/{}", server_root(server_url), name);
+        EphemeralDb { admin, name, url }
+    }
+}
+
+impl Drop for EphemeralDb {
+    fn drop(&mut self) {
+        let dropped = self
+            .admin
+            .get_conn()
+            .and_then(|mut conn| conn.query_drop(format!("DROP DATABASE IF EXISTS {}", self.name)));
+        if let Err(e) = dropped {
+            eprintln!("Failed to drop test database {}: {}", self.name, e);
+        }
+    }
+}
+
+// The server running against its own empty database. Its clock starts at
+// the real time and only moves when a test moves it.
+pub struct TestApp {
+    pub client: Client,
+    pub clock: Arc<FixedClock>,
+    // Declared after the client so the server's connections close first
+    _db: EphemeralDb,
+}
+
+impl TestApp {
+    pub fn spawn() -> Self {
+        let server_url = env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL must point at a MySQL server to run this test");
+        let db = EphemeralDb::create(&server_url);
+        let clock = Arc::new(FixedClock::new(Utc::now()));
+
+        let config = AppConfig {
+            database_url: db.url.clone(),
+            db_pool_size: None,
+            query_timeout: Duration::from_secs(30),
+            acquire_timeouts: AcquireTimeouts {
+                default: Duration::from_secs(5),
+                per_route: HashMap::new(),
+            },
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            jwt_secret: "test secret".to_string(),
+            token_ttl_secs: 60 * 60,
+            trash_retention_days: 30,
+            rate_limit_per_minute: 1000,
+            cors: CorsSettings::default(),
+            trusted_proxies: Vec::new(),
+            background_jobs: false,
+            clock: clock.clone(),
+            log_level: logging::DEFAULT_LEVEL.to_string(),
+            log_format: LogFormat::Text,
+        };
+        let client = Client::tracked(build_rocket(config)).expect("Failed to build the server");
+
+        TestApp {
+            client,
+            clock,
+            _db: db,
+        }
+    }
+
+    // Register an account and return its access token
+    pub fn register(&self, email: &str) -> String {
+        let response = self
+            .client
+            .post("/auth/register")
+            .json(&json!({ "email": email, "password": TEST_PASSWORD }))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+
+        let session: Value = response.into_json().expect("session body");
+        session["token"].as_str().expect("token").to_string()
+    }
+}
+
+// Authorization header carrying `token`
+pub fn bearer(token: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+// Tasks kept in memory, each with the id of the user who owns it. Lists
+// aren't kept, so tasks can't be filed under one.
+#[derive(Default)]
+pub struct MemoryTaskStore {
+    tasks: Mutex<BTreeMap<u32, (u32, Task)>>,
+}
+
+// Whether `task` is one `query` asks for, judged as the SQL in
+// repository::tasks would
+fn matches(query: &TaskQuery, task: &Task) -> bool {
+    let open = !task.is_completed;
+    let due_passed = task.due_date.is_some_and(|due| due < query.now);
+    let started = task.start_date.is_none_or(|start| start <= query.now);
+
+    query
+        .completed
+        .is_none_or(|completed| task.is_completed == completed)
+        && query
+            .list_id
+            .is_none_or(|list_id| task.list_id == Some(list_id))
+        && query
+            .tag
+            .as_ref()
+            .is_none_or(|tag| task.tags.iter().any(|name| name == tag.trim()))
+        && query.search.as_ref().is_none_or(|search| {
+            task.description
+                .to_lowercase()
+                .contains(&search.to_lowercase())
+        })
+        && query
+            .priority
+            .is_none_or(|priority| task.priority == priority)
+        && query
+            .due_before
+            .is_none_or(|before| task.due_date.is_some_and(|due| due < before))
+        && query
+            .due_after
+            .is_none_or(|after| task.due_date.is_some_and(|due| due > after))
+        && query
+            .overdue
+            .is_none_or(|overdue| (open && due_passed) == overdue)
+        && query
+            .available
+            .is_none_or(|available| (open && started) == available)
+}
+
+// NULLs first, ties broken by id, as MySQL orders them
+fn compare(sort: TaskSort, a: &Task, b: &Task) -> SortOrdering {
+    let by_field = match sort {
+        TaskSort::Id => SortOrdering::Equal,
+        TaskSort::CreatedAt => a.created_at.cmp(&b.created_at),
+        TaskSort::StartDate => a.start_date.cmp(&b.start_date),
+        TaskSort::DueDate => a.due_date.cmp(&b.due_date),
+        TaskSort::Priority => a.priority.cmp(&b.priority),
+        TaskSort::Description => a
+            .description
+            .to_lowercase()
+            .cmp(&b.description.to_lowercase()),
+        TaskSort::Completed => a.is_completed.cmp(&b.is_completed),
+    };
+    by_field.then(a.id.cmp(&b.id))
+}
+
+impl MemoryTaskStore {
+    // The user's tasks outside the trash, by id
+    fn live(tasks: &BTreeMap<u32, (u32, Task)>, user_id: u32) -> BTreeMap<u32, Task> {
+        tasks
+            .iter()
+            .filter(|(_, (owner, task))| *owner == user_id && task.deleted_at.is_none())
+            .map(|(id, (_, task))| (*id, task.clone()))
+            .collect()
+    }
+
+    // The checks insert_task and friends make against the database, short of
+    // the nesting depth
+    fn check(
+        live: &BTreeMap<u32, Task>,
+        task_id: Option<u32>,
+        list_id: Option<u32>,
+        parent_id: Option<u32>,
+    ) -> Result<(), ApiError> {
+        if let Some(list_id) = list_id {
+            return Err(ApiError::BadRequest(format!(
+                "list {} does not exist",
+                list_id
+            )));
+        }
+
+        let mut ancestor = parent_id;
+        while let Some(id) = ancestor {
+            if Some(id) == task_id {
+                return Err(ApiError::BadRequest(
+                    "a task cannot be a subtask of itself or of its own subtasks".to_string(),
+                ));
+            }
+            match live.get(&id) {
+                Some(task) => ancestor = task.parent_id,
+                None => {
+                    return Err(ApiError::BadRequest(format!(
+                        "task {} does not exist",
+                        parent_id.unwrap_or_default()
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Store `task` over an existing one, returning it as saved and whether
+    // this completed it
+    fn save(&self, user_id: u32, before: Task, mut task: Task) -> (Task, bool) {
+        task.id = before.id;
+        task.external_id = task.external_id.or(before.external_id);
+        task.created_at = before.created_at;
+        task.deleted_at = None;
+
+        let completed = task.is_completed && !before.is_completed;
+        let id = task.id.unwrap_or_default();
+        self.tasks
+            .lock()
+            .unwrap()
+            .insert(id, (user_id, task.clone()));
+        (task, completed)
+    }
+}
+
+#[rocket::async_trait]
+impl TaskStore for MemoryTaskStore {
+    async fn search(
+        &self,
+        user_id: u32,
+        query: TaskQuery,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<Task>, u64), ApiError> {
+        let live = MemoryTaskStore::live(&self.tasks.lock().unwrap(), user_id);
+        let mut tasks: Vec<Task> = live
+            .into_values()
+            .filter(|task| matches(&query, task))
+            .collect();
+        tasks.sort_by(|a, b| {
+            let ordering = compare(query.sort, a, b);
+            if query.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        let total = tasks.len() as u64;
+        let page = tasks
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok((page, total))
+    }
+
+    async fn find(&self, user_id: u32, task_id: u32) -> Result<Option<Task>, ApiError> {
+        let mut live = MemoryTaskStore::live(&self.tasks.lock().unwrap(), user_id);
+        Ok(live.remove(&task_id))
+    }
+
+    async fn tree(&self, user_id: u32, task_id: u32) -> Result<Option<TaskTree>, ApiError> {
+        let live = MemoryTaskStore::live(&self.tasks.lock().unwrap(), user_id);
+        let Some(task) = live.get(&task_id).cloned() else {
+            return Ok(None);
+        };
+
+        let tree = TaskTree::build(task, &mut |id| {
+            Ok::<_, ApiError>(
+                live.values()
+                    .filter(|child| child.parent_id == Some(id))
+                    .cloned()
+                    .collect(),
+            )
+        })?;
+        Ok(Some(tree))
+    }
+
+    async fn insert(
+        &self,
+        user_id: u32,
+        mut task: Task,
+        now: DateTime<Utc>,
+    ) -> Result<u32, ApiError> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let live = MemoryTaskStore::live(&tasks, user_id);
+        MemoryTaskStore::check(&live, None, task.list_id, task.parent_id)?;
+
+        let id = tasks.keys().next_back().map_or(1, |last| last + 1);
+        task.id = Some(id);
+        task.created_at = task.created_at.or(Some(now));
+        tasks.insert(id, (user_id, task));
+        Ok(id)
+    }
+
+    async fn replace(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        task: Task,
+    ) -> Result<Option<(Task, bool)>, ApiError> {
+        let live = MemoryTaskStore::live(&self.tasks.lock().unwrap(), user_id);
+        MemoryTaskStore::check(&live, Some(task_id), task.list_id, task.parent_id)?;
+        let Some(before) = live.get(&task_id).cloned() else {
+            return Ok(None);
+        };
+
+        Ok(Some(self.save(user_id, before, task)))
+    }
+
+    async fn patch(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        patch: TaskPatch,
+    ) -> Result<Option<(Task, bool)>, ApiError> {
+        let live = MemoryTaskStore::live(&self.tasks.lock().unwrap(), user_id);
+        MemoryTaskStore::check(
+            &live,
+            Some(task_id),
+            patch.list_id.flatten(),
+            patch.parent_id.flatten(),
+        )?;
+        let Some(before) = live.get(&task_id).cloned() else {
+            return Ok(None);
+        };
+        check_patched_dates(&patch, &before).map_err(ApiError::Invalid)?;
+
+        let mut task = before.clone();
+        task.external_id = patch.external_id.or(task.external_id);
+        task.description = patch.description.unwrap_or(task.description);
+        task.is_completed = patch.is_completed.unwrap_or(task.is_completed);
+        task.list_id = patch.list_id.unwrap_or(task.list_id);
+        task.parent_id = patch.parent_id.unwrap_or(task.parent_id);
+        task.tags = patch.tags.unwrap_or(task.tags);
+        task.priority = patch.priority.unwrap_or(task.priority);
+        task.start_date = patch.start_date.unwrap_or(task.start_date);
+        task.due_date = patch.due_date.unwrap_or(task.due_date);
+        Ok(Some(self.save(user_id, before, task)))
+    }
+
+    async fn trash(
+        &self,
+        user_id: u32,
+        task_id: u32,
+        reparent: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u32>, ApiError> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let Some(parent_id) = MemoryTaskStore::live(&tasks, user_id)
+            .get(&task_id)
+            .map(|task| task.parent_id)
+        else {
+            return Ok(Vec::new());
+        };
+
+        if reparent {
+            for (owner, child) in tasks.values_mut() {
+                if *owner == user_id && child.parent_id == Some(task_id) {
+                    child.parent_id = parent_id;
+                }
+            }
+        }
+
+        // The task and every live task below it
+        let live = MemoryTaskStore::live(&tasks, user_id);
+        let mut deleted = vec![task_id];
+        let mut next = 0;
+        while let Some(&parent) = deleted.get(next) {
+            deleted.extend(
+                live.values()
+                    .filter(|child| child.parent_id == Some(parent))
+                    .filter_map(|child| child.id),
+            );
+            next += 1;
+        }
+
+        for id in &deleted {
+            if let Some((_, task)) = tasks.get_mut(id) {
+                task.deleted_at = Some(now);
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+// The /tasks routes alone, served from a MemoryTaskStore. The clock starts at
+// the real time and only moves when a test moves it.
+pub struct MemoryApp {
+    pub client: asynchronous::Client,
+    pub clock: Arc<FixedClock>,
+    keys: TokenKeys,
+}
+
+impl MemoryApp {
+    pub async fn spawn() -> Self {
+        let clock = Arc::new(FixedClock::new(Utc::now()));
+        let shared_clock: SharedClock = clock.clone();
+        let store: SharedTaskStore = Arc::new(MemoryTaskStore::default());
+        let figment = rocket::Config::figment().merge(("log_level", LogLevel::Off));
+
+        let rocket = rocket::custom(figment)
+            .manage(store)
+            .manage(TokenKeys::new("test secret", 60 * 60))
+            .manage(TaskEvents::default())
+            .manage(RateLimiter::new(1000))
+            .manage(shared_clock)
+            .mount("/", task_routes().0)
+            .register("/", error_catchers());
+        let client = asynchronous::Client::tracked(rocket)
+            .await
+            .expect("Failed to build the server");
+
+        MemoryApp {
+            client,
+            clock,
+            keys: TokenKeys::new("test secret", 60 * 60),
+        }
+    }
+
+    // Authorization header for the user with id `user_id`; users need not
+    // exist anywhere for the /tasks routes
+    pub fn bearer(&self, user_id: u32) -> Header<'static> {
+        let token = self
+            .keys
+            .sign(user_id, self.clock.now())
+            .expect("token signs");
+        bearer(&token)
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::Duration as ChronoDuration;
+use rocket::fairing::AdHoc;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use std::time::Duration;
+
+use todo_core::clock::SharedClock;
+use todo_core::models::Task;
+use todo_core::repository;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::{DbConn, DbConnPool};
+use crate::error::ApiError;
+use crate::events::{TaskEvent, TaskEvents};
+use crate::listing::{Listing, Pagination};
+
+// How often the purger looks for tasks past the retention window
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Trash routes
+//
+// DELETE /tasks/<id> moves a task to the trash. From there it can be
+// restored until it is purged, either by emptying the trash or once it has
+// been there longer than the retention window.
+
+#[openapi(tag = "Trash")]
+#[get("/tasks/trash")]
+async fn list_trash(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    pagination: Pagination,
+) -> Result<Listing<Task>, ApiError> {
+    let (limit, offset) = (pagination.per_page(), pagination.offset());
+    let (tasks, total) = conn
+        .run(move |c| {
+            let tasks = repository::tasks::trash(c, user.id, limit, offset)?;
+            Ok::<_, mysql::Error>((tasks, repository::tasks::count_trash(c, user.id)?))
+        })
+        .await?;
+
+    Ok(Listing::page(tasks, total, pagination))
+}
+
+#[openapi(tag = "Trash")]
+#[post("/tasks/<task_id>/restore")]
+async fn restore_task(
+    mut conn: DbConn,
+    events: &State<TaskEvents>,
+    user: AuthenticatedUser,
+    task_id: u32,
+) -> Result<Json<Task>, ApiError> {
+    let restored = conn
+        .run(move |c| {
+            if !repository::tasks::restore(c, user.id, task_id)? {
+                return Ok(None);
+            }
+            repository::tasks::find(c, user.id, task_id)
+        })
+        .await?
+        .ok_or(ApiError::NotFound("task in the trash"))?;
+    events.publish(user.id, TaskEvent::Created(restored.clone()));
+
+    Ok(Json(restored))
+}
+
+#[openapi(tag = "Trash")]
+#[delete("/tasks/trash")]
+async fn empty_trash(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::tasks::empty_trash(c, user.id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![list_trash, restore_task, empty_trash]
+}
+
+// Background job permanently deleting tasks that have been in the trash for
+// longer than `retention` days
+pub fn purger(retention: i64) -> AdHoc {
+    AdHoc::on_liftoff("Trash purger", move |rocket| {
+        Box::pin(async move {
+            let pool = rocket
+                .state::<DbConnPool>()
+                .expect("DbConnPool must be managed")
+                .pool
+                .clone();
+            let clock = rocket
+                .state::<SharedClock>()
+                .expect("SharedClock must be managed")
+                .clone();
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(PURGE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let pool = pool.clone();
+                    let cutoff = (clock.now() - ChronoDuration::days(retention)).naive_utc();
+                    let result = rocket::tokio::task::spawn_blocking(move || {
+                        repository::tasks::purge_trashed_before(&mut pool.get_conn()?, cutoff)
+                    })
+                    .await;
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => tracing::error!(error = %e, "Trash purge failed"),
+                        Err(e) => tracing::error!(error = %e, "Trash purge panicked"),
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,204 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use todo_core::models::Task;
+use todo_core::Engine;
+
+use crate::config;
+
+const HELP: &str = "j/k move  space toggle  a add  d delete  r refresh  q quit";
+
+enum Mode {
+    Browse,
+    Adding(String),
+}
+
+struct App {
+    engine: Engine,
+    user_id: u32,
+    tasks: Vec<Task>,
+    selected: ListState,
+    mode: Mode,
+    status: String,
+}
+
+impl App {
+    fn refresh(&mut self) {
+        match self.engine.tasks(self.user_id) {
+            Ok(tasks) => {
+                self.tasks = tasks;
+                let last = self.tasks.len().checked_sub(1);
+                let selected = self
+                    .selected
+                    .selected()
+                    .map(|i| last.map_or(0, |l| i.min(l)));
+                self.selected.select(selected.or(last.map(|_| 0)));
+            }
+            Err(e) => self.status = format!("Failed to load tasks: {}", e),
+        }
+    }
+
+    fn current(&self) -> Option<&Task> {
+        self.selected.selected().and_then(|i| self.tasks.get(i))
+    }
+
+    fn toggle(&mut self) {
+        let Some(task) = self.current() else { return };
+        let (id, is_completed) = (task.id.unwrap(), task.is_completed);
+
+        if let Err(e) = self.engine.set_completed(self.user_id, id, !is_completed) {
+            self.status = format!("Failed to update task: {}", e);
+        }
+        self.refresh();
+    }
+
+    fn delete(&mut self) {
+        let Some(task) = self.current() else { return };
+        let id = task.id.unwrap();
+
+        match self.engine.delete_task(self.user_id, id) {
+            Ok(()) => self.status = format!("Deleted task {}", id),
+            Err(e) => self.status = format!("Failed to delete task: {}", e),
+        }
+        self.refresh();
+    }
+
+    fn add(&mut self, description: &str) {
+        match self.engine.add_task(self.user_id, description) {
+            Ok(task) => {
+                self.status = format!("Added task {}", task.id.unwrap());
+                self.refresh();
+                self.selected.select_last();
+            }
+            Err(e) => self.status = format!("Failed to add task: {}", e),
+        }
+    }
+
+    // Handle one key press; returns false when the app should exit
+    fn on_key(&mut self, code: KeyCode) -> bool {
+        match &mut self.mode {
+            Mode::Browse => match code {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Char('j') | KeyCode::Down => self.selected.select_next(),
+                KeyCode::Char('k') | KeyCode::Up => self.selected.select_previous(),
+                KeyCode::Char(' ') | KeyCode::Enter => self.toggle(),
+                KeyCode::Char('d') => self.delete(),
+                KeyCode::Char('r') => self.refresh(),
+                KeyCode::Char('a') => self.mode = Mode::Adding(String::new()),
+                _ => {}
+            },
+            Mode::Adding(input) => match code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Enter => {
+                    let description = input.trim().to_string();
+                    self.mode = Mode::Browse;
+                    if !description.is_empty() {
+                        self.add(&description);
+                    }
+                }
+                _ => {}
+            },
+        }
+
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let items: Vec<ListItem> = self
+            .tasks
+            .iter()
+            .map(|task| {
+                let mark = if task.is_completed { "[x]" } else { "[ ]" };
+                ListItem::new(format!("{} {}", mark, task.description))
+            })
+            .collect();
+        let open = self.tasks.iter().filter(|t| !t.is_completed).count();
+
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" Tasks ({} open) ", open)))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.selected);
+
+        let input = match &self.mode {
+            Mode::Adding(input) => Paragraph::new(input.as_str())
+                .block(Block::bordered().title(" New task (enter to save, esc to cancel) ")),
+            Mode::Browse => Paragraph::new("").block(Block::bordered()),
+        };
+        frame.render_widget(input, input_area);
+
+        let status = if self.status.is_empty() {
+            HELP
+        } else {
+            &self.status
+        };
+        frame.render_widget(Line::from(status), status_area);
+    }
+}
+
+fn run_app(terminal: &mut DefaultTerminal, mut app: App) -> io::Result<()> {
+    app.refresh();
+
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            app.status.clear();
+            if !app.on_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Terminal client working directly against the database through todo-core,
+// showing the tasks of the account registered under `email`
+pub fn run(email: &str) {
+    let database_url = config::database_url().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let engine = Engine::connect(&database_url).expect("Failed to connect to the database");
+    let user = match engine.user(email).expect("Failed to look up the user") {
+        Some(user) => user,
+        None => {
+            eprintln!("No account is registered for {}", email);
+            std::process::exit(1);
+        }
+    };
+
+    let app = App {
+        engine,
+        user_id: user.id,
+        tasks: Vec::new(),
+        selected: ListState::default(),
+        mode: Mode::Browse,
+        status: String::new(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, app);
+    ratatui::restore();
+
+    if let Err(e) = result {
+        eprintln!("Terminal error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,464 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rocket::fairing::AdHoc;
+use rocket::response::status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::Serialize;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use schemars::JsonSchema;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use todo_core::clock::SharedClock;
+use todo_core::models::{PayloadVersion, Task, Webhook};
+use todo_core::repository;
+use todo_core::validation::FieldError;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::{DbConn, DbConnPool};
+use crate::error::ApiError;
+use crate::events::{TaskEvent, TaskEvents};
+use crate::guards::ValidatedJson;
+use crate::listing::Listing;
+
+// Deliveries are POSTed as JSON with these headers:
+//
+// - X-Todo-Event: task.created, task.completed or task.deleted
+// - X-Todo-Delivery: random id, the same across retries of one delivery
+// - X-Todo-Signature: sha256=<hex HMAC-SHA256 of the body, keyed by the
+//   hook's secret>
+// - X-Webhook-Payload-Version: the version the body is shaped by, the one
+//   the hook is pinned to or else the latest
+//
+// Each payload version spells out the task fields it sends, so a change to
+// the task JSON reaches hooks only once it is given a new version.
+//
+// A delivery that fails with a network error, a 429 or a 5xx is retried with
+// exponential backoff; other responses are final. Redirects are not
+// followed.
+//
+// Hooks must point at public addresses. A URL whose host is, or resolves to,
+// a loopback, private or link-local address is refused when the hook is
+// created or changed, so deliveries can't be aimed at services on the
+// server's own network. Deliveries check again as they connect, so a host
+// whose DNS later turns private is still refused.
+const SIGNATURE_HEADER: &str = "X-Todo-Signature";
+const EVENT_HEADER: &str = "X-Todo-Event";
+const DELIVERY_HEADER: &str = "X-Todo-Delivery";
+const PAYLOAD_VERSION_HEADER: &str = "X-Webhook-Payload-Version";
+
+const SECRET_BYTES: usize = 32;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// A newly created hook. This is the only response that includes the secret.
+#[derive(Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", digest)
+}
+
+// Webhook routes
+
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks")]
+async fn list_webhooks(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+) -> Result<Listing<Webhook>, ApiError> {
+    let webhooks = conn
+        .run(move |c| repository::webhooks::list(c, user.id))
+        .await?;
+
+    Ok(Listing::all(webhooks))
+}
+
+// Whether deliveries may go to `ip`
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // 0.0.0.0/8, the carrier-grade NAT range 100.64.0.0/10, benchmarking
+        // 198.18.0.0/15 and reserved 240.0.0.0/4, which takes in broadcast
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64)
+        || (a == 198 && (b & 0xfe) == 18)
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // NAT64 64:ff9b::/96 reaches the IPv4 address in its last 32 bits
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., a, b, c, d] = ip.octets();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    let first = segments[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local fc00::/7, link-local fe80::/10 and documentation
+        // 2001:db8::/32
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && segments[1] == 0xdb8))
+}
+
+// The address in a URL's host, when it is written as one. IPv6 hosts come
+// bracketed, as in the URL.
+fn literal_host(host: &str) -> Option<IpAddr> {
+    host.trim_matches(['[', ']']).parse().ok()
+}
+
+// Resolves delivery hosts to their public addresses only, so a hook can't be
+// turned on the server's network by changing its DNS after it was checked
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = rocket::tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            let addresses: reqwest::dns::Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+// Refuse hook URLs that lead into the server's own network
+async fn check_public(url: &str) -> Result<(), ApiError> {
+    let refuse = |message: &str| ApiError::Invalid(vec![FieldError::new("url", message)]);
+
+    let url = reqwest::Url::parse(url.trim()).map_err(|_| refuse("must be a valid URL"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let Some(host) = url.host_str() else {
+        return Err(refuse("must have a host"));
+    };
+    let addresses: Vec<IpAddr> = match literal_host(host) {
+        Some(ip) => vec![ip],
+        None => rocket::tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| refuse("must have a host that resolves"))?
+            .map(|address| address.ip())
+            .collect(),
+    };
+
+    if addresses.is_empty() || !addresses.into_iter().all(is_public) {
+        return Err(refuse("must not point at a private or loopback address"));
+    }
+    Ok(())
+}
+
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks", format = "json", data = "<webhook>")]
+async fn create_webhook(
+    mut conn: DbConn,
+    clock: &State<SharedClock>,
+    user: AuthenticatedUser,
+    webhook: ValidatedJson<Webhook>,
+) -> Result<status::Created<Json<CreatedWebhook>>, ApiError> {
+    let mut webhook = webhook.into_inner();
+    check_public(&webhook.url).await?;
+    webhook.created_at = Some(clock.now());
+    let secret = random_hex(SECRET_BYTES);
+
+    let row_secret = secret.clone();
+    let webhook = conn
+        .run(move |c| repository::webhooks::insert(c, user.id, &webhook, &row_secret))
+        .await?;
+    let location = format!("/webhooks/{}", webhook.id.unwrap_or_default());
+
+    Ok(status::Created::new(location).body(Json(CreatedWebhook { webhook, secret })))
+}
+
+#[openapi(tag = "Webhooks")]
+#[put("/webhooks/<webhook_id>", format = "json", data = "<webhook>")]
+async fn update_webhook(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    webhook_id: u32,
+    webhook: ValidatedJson<Webhook>,
+) -> Result<Json<Webhook>, ApiError> {
+    let webhook = webhook.into_inner();
+    check_public(&webhook.url).await?;
+    conn.run(move |c| -> Result<_, ApiError> {
+        if !repository::webhooks::update(c, user.id, webhook_id, &webhook)? {
+            return Err(ApiError::NotFound("webhook"));
+        }
+        repository::webhooks::find(c, user.id, webhook_id)?.ok_or(ApiError::NotFound("webhook"))
+    })
+    .await
+    .map(Json)
+}
+
+#[openapi(tag = "Webhooks")]
+#[delete("/webhooks/<webhook_id>")]
+async fn delete_webhook(
+    mut conn: DbConn,
+    user: AuthenticatedUser,
+    webhook_id: u32,
+) -> Result<status::NoContent, ApiError> {
+    conn.run(move |c| repository::webhooks::delete(c, user.id, webhook_id))
+        .await?;
+
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        list_webhooks,
+        create_webhook,
+        update_webhook,
+        delete_webhook
+    ]
+}
+
+// Delivery
+
+// The hook event for a task change, if hooks hear about it
+fn hook_event(event: &TaskEvent) -> Option<&'static str> {
+    match event {
+        TaskEvent::Created(_) => Some("task.created"),
+        TaskEvent::Updated {
+            completed: true, ..
+        } => Some("task.completed"),
+        TaskEvent::Updated { .. } => None,
+        TaskEvent::Deleted(_) => Some("task.deleted"),
+    }
+}
+
+// A task as payload `version` describes it
+fn task_payload(task: &Task, version: PayloadVersion) -> Value {
+    match version {
+        PayloadVersion::V1 => json!({
+            "id": task.id,
+            "external_id": task.external_id,
+            "description": task.description,
+            "is_completed": task.is_completed,
+            "list_id": task.list_id,
+            "parent_id": task.parent_id,
+            "tags": task.tags,
+            "priority": task.priority,
+            "start_date": task.start_date,
+            "due_date": task.due_date,
+            "created_at": task.created_at,
+            "deleted_at": task.deleted_at,
+        }),
+    }
+}
+
+// The body sent for a task change in payload `version`
+fn payload(event: &TaskEvent, name: &str, occurred_at: Value, version: PayloadVersion) -> String {
+    let mut payload = json!({
+        "event": name,
+        "occurred_at": occurred_at,
+    });
+    match event {
+        TaskEvent::Created(task) | TaskEvent::Updated { task, .. } => {
+            payload["task"] = task_payload(task, version)
+        }
+        TaskEvent::Deleted(id) => payload["task_id"] = json!(id),
+    }
+    payload.to_string()
+}
+
+// POST one payload to one hook, retrying transient failures
+async fn deliver(
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+    event: &str,
+    version: PayloadVersion,
+    body: String,
+) {
+    let delivery = random_hex(16);
+    // Named hosts are checked by PublicResolver as each attempt connects;
+    // addresses written into the URL never reach it
+    let literal = reqwest::Url::parse(&url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().and_then(literal_host));
+    if literal.is_some_and(|ip| !is_public(ip)) {
+        tracing::error!(
+            url,
+            event,
+            delivery,
+            "Refusing webhook to a private address"
+        );
+        return;
+    }
+    let signature = sign(&secret, body.as_bytes());
+    let mut delay = FIRST_RETRY_DELAY;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event)
+            .header(DELIVERY_HEADER, &delivery)
+            .header(SIGNATURE_HEADER, &signature)
+            .header(PAYLOAD_VERSION_HEADER, version.number())
+            .body(body.clone())
+            .send()
+            .await;
+
+        let retry = match result {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => {
+                let status = response.status();
+                tracing::warn!(
+                    url,
+                    event,
+                    attempt,
+                    status = status.as_u16(),
+                    "Webhook refused delivery"
+                );
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                tracing::warn!(url, event, attempt, error = %e, "Webhook delivery failed");
+                true
+            }
+        };
+        if !retry || attempt == MAX_ATTEMPTS {
+            break;
+        }
+
+        rocket::tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+
+    tracing::error!(url, event, delivery, "Giving up on webhook");
+}
+
+// Background job POSTing task changes to the owner's hooks. Each delivery
+// runs on its own task, so neither the request that made the change nor
+// other deliveries wait on a slow hook.
+pub fn dispatcher() -> AdHoc {
+    AdHoc::on_liftoff("Webhook dispatcher", |rocket| {
+        Box::pin(async move {
+            let pool = rocket
+                .state::<DbConnPool>()
+                .expect("DbConnPool must be managed")
+                .pool
+                .clone();
+            let clock = rocket
+                .state::<SharedClock>()
+                .expect("SharedClock must be managed")
+                .clone();
+            let mut receiver = rocket
+                .state::<TaskEvents>()
+                .expect("TaskEvents must be managed")
+                .subscribe();
+            let client = reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .redirect(reqwest::redirect::Policy::none())
+                .dns_resolver(Arc::new(PublicResolver))
+                // A proxy would resolve hook hosts itself, past PublicResolver
+                .no_proxy()
+                .build()
+                .expect("Failed to build the webhook HTTP client");
+
+            rocket::tokio::spawn(async move {
+                loop {
+                    let published = match receiver.recv().await {
+                        Ok(published) => published,
+                        Err(RecvError::Lagged(missed)) => {
+                            tracing::warn!(missed, "Webhook dispatcher fell behind");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    let Some(event) = hook_event(&published.event) else {
+                        continue;
+                    };
+
+                    let pool = pool.clone();
+                    let user_id = published.user_id;
+                    let targets = rocket::tokio::task::spawn_blocking(move || {
+                        repository::webhooks::targets(&mut pool.get_conn()?, user_id)
+                    })
+                    .await;
+                    let targets = match targets {
+                        Ok(Ok(targets)) => targets,
+                        Ok(Err(e)) => {
+                            tracing::error!(user_id, error = %e, "Failed to load webhooks");
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::error!(user_id, error = %e, "Loading webhooks panicked");
+                            continue;
+                        }
+                    };
+                    if targets.is_empty() {
+                        continue;
+                    }
+
+                    // One body per version the hooks are sent
+                    let occurred_at = json!(clock.now());
+                    let mut bodies = BTreeMap::new();
+                    for (url, secret, pinned) in targets {
+                        let version = Webhook::payload_version_for(pinned);
+                        let body = bodies
+                            .entry(version)
+                            .or_insert_with(|| {
+                                payload(&published.event, event, occurred_at.clone(), version)
+                            })
+                            .clone();
+                        let client = client.clone();
+                        rocket::tokio::spawn(async move {
+                            deliver(client, url, secret, event, version, body).await
+                        });
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,318 @@
+use mysql::PooledConn;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::serde::json::{json, serde_json, Value};
+use rocket::serde::Deserialize;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Shutdown, State};
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::{openapi, openapi_get_routes_spec};
+use rocket_ws::{Channel, Message, WebSocket};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use todo_core::clock::SharedClock;
+use todo_core::models::{Task, TaskPatch};
+use todo_core::validation::{FieldError, Validate};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::DbConnPool;
+use crate::error::ApiError;
+use crate::events::{Published, TaskEvent, TaskEvents};
+use crate::{apply_patch, insert_task, lists, replace_task, trash_task};
+
+// WebSocket sync
+//
+// GET /ws upgrades to a socket that carries JSON text messages both ways.
+// Clients send commands, each answered with `{"type": "ok", ...}` or
+// `{"type": "error", "error": {...}}`; a `ref` on a command is echoed in
+// its reply:
+//
+//     {"type": "subscribe", "list_id": 3}          (null for tasks in no list)
+//     {"type": "unsubscribe", "list_id": 3}
+//     {"type": "create", "ref": 1, "task": {...}}   -> {"type": "ok", "ref": 1, "task": {...}}
+//     {"type": "update", "id": 5, "task": {...}}    -> {"type": "ok", "task": {...}}
+//     {"type": "patch", "id": 5, "patch": {...}}    -> {"type": "ok", "task": {...}}
+//     {"type": "delete", "id": 5, "reparent": true} -> {"type": "ok", "deleted": [5, 6]}
+//
+// Changes to tasks in subscribed lists, whether made over HTTP or another of
+// the user's sockets, arrive as `created`, `updated` and `deleted` messages
+// shaped like the SSE events on /tasks/events. A task moved out of a
+// subscribed list shows up only in its new one. Deletions go to every socket
+// with a subscription, since a deleted task no longer has a list to match.
+// A socket that falls behind gets `{"type": "resync"}` and should reload.
+
+static NEXT_SOCKET: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+enum Command {
+    Subscribe {
+        list_id: Option<u32>,
+    },
+    Unsubscribe {
+        list_id: Option<u32>,
+    },
+    Create {
+        task: Task,
+    },
+    Update {
+        id: u32,
+        task: Task,
+    },
+    Patch {
+        id: u32,
+        patch: TaskPatch,
+    },
+    Delete {
+        id: u32,
+        #[serde(default)]
+        reparent: bool,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ClientMessage {
+    #[serde(rename = "ref", default)]
+    reference: Option<Value>,
+    #[serde(flatten)]
+    command: Command,
+}
+
+// Why a command was refused
+enum Failure {
+    Invalid(Vec<FieldError>),
+    Api(ApiError),
+}
+
+impl From<ApiError> for Failure {
+    fn from(e: ApiError) -> Self {
+        Failure::Api(e)
+    }
+}
+
+impl Failure {
+    // The same body the HTTP API reports the error with
+    fn body(&self) -> Value {
+        match self {
+            Failure::Invalid(errors) => json!({
+                "status": 422,
+                "code": "invalid_body",
+                "message": "Unprocessable Entity",
+                "errors": errors,
+            }),
+            Failure::Api(e) => {
+                match e {
+                    ApiError::Database(e) => {
+                        tracing::error!(route = "ws", error = %e, "Database error")
+                    }
+                    ApiError::Internal(e) => {
+                        tracing::error!(route = "ws", error = %e, "Internal error")
+                    }
+                    _ => {}
+                }
+                e.body()
+            }
+        }
+    }
+}
+
+fn validated<T: Validate>(value: T) -> Result<T, Failure> {
+    value.validate().map_err(Failure::Invalid)?;
+    Ok(value)
+}
+
+// One open socket
+struct Session<'r> {
+    id: u64,
+    user_id: u32,
+    db: &'r DbConnPool,
+    clock: &'r SharedClock,
+    events: &'r TaskEvents,
+    // Subscribed list ids; None stands for tasks in no list
+    lists: HashSet<Option<u32>>,
+}
+
+impl Session<'_> {
+    // Run `f` with a pooled connection on the blocking thread pool
+    async fn run<F, R>(&self, f: F) -> Result<R, ApiError>
+    where
+        F: FnOnce(&mut PooledConn) -> Result<R, ApiError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut conn = self.db.get_conn("sync").await?;
+        rocket::tokio::task::spawn_blocking(move || f(&mut conn))
+            .await
+            .map_err(|e| ApiError::Internal(format!("Socket command panicked: {}", e)))?
+    }
+
+    fn publish(&self, event: TaskEvent) {
+        self.events.publish_from(self.user_id, self.id, event);
+    }
+
+    // Whether a published change should be sent down this socket
+    fn wants(&self, published: &Published) -> bool {
+        if published.user_id != self.user_id || published.origin == Some(self.id) {
+            return false;
+        }
+
+        match &published.event {
+            TaskEvent::Created(task) | TaskEvent::Updated { task, .. } => {
+                self.lists.contains(&task.list_id)
+            }
+            TaskEvent::Deleted(_) => !self.lists.is_empty(),
+        }
+    }
+
+    // Carry out a command, returning the fields of its reply
+    async fn execute(&mut self, command: Command) -> Result<Value, Failure> {
+        let user_id = self.user_id;
+
+        match command {
+            Command::Subscribe { list_id } => {
+                self.run(move |c| lists::check_list(c, user_id, list_id))
+                    .await?;
+                self.lists.insert(list_id);
+                Ok(json!({}))
+            }
+            Command::Unsubscribe { list_id } => {
+                self.lists.remove(&list_id);
+                Ok(json!({}))
+            }
+            Command::Create { task } => {
+                let mut task = validated(task)?;
+                let now = self.clock.now();
+                task.created_at = Some(now);
+                let row = task.clone();
+                task.id = Some(
+                    self.run(move |c| insert_task(c, user_id, &row, now))
+                        .await?,
+                );
+                self.publish(TaskEvent::Created(task.clone()));
+                Ok(json!({ "task": task }))
+            }
+            Command::Update { id, task } => {
+                let task = validated(task)?;
+                let (task, completed) = self
+                    .run(move |c| replace_task(c, user_id, id, &task))
+                    .await?
+                    .ok_or(ApiError::NotFound("task"))?;
+                self.publish(TaskEvent::Updated {
+                    task: task.clone(),
+                    completed,
+                });
+                Ok(json!({ "task": task }))
+            }
+            Command::Patch { id, patch } => {
+                let patch = validated(patch)?;
+                let (task, completed) = self
+                    .run(move |c| apply_patch(c, user_id, id, &patch))
+                    .await?
+                    .ok_or(ApiError::NotFound("task"))?;
+                self.publish(TaskEvent::Updated {
+                    task: task.clone(),
+                    completed,
+                });
+                Ok(json!({ "task": task }))
+            }
+            Command::Delete { id, reparent } => {
+                let now = self.clock.now().naive_utc();
+                let deleted = self
+                    .run(move |c| Ok(trash_task(c, user_id, id, reparent, now)?))
+                    .await?;
+                for id in &deleted {
+                    self.publish(TaskEvent::Deleted(*id));
+                }
+                Ok(json!({ "deleted": deleted }))
+            }
+        }
+    }
+
+    // The reply to one text message
+    async fn reply(&mut self, text: &str) -> Value {
+        let message: ClientMessage = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                let failure = Failure::Invalid(vec![FieldError::new("body", e.to_string())]);
+                return json!({ "type": "error", "error": failure.body() });
+            }
+        };
+
+        match self.execute(message.command).await {
+            Ok(mut reply) => {
+                reply["type"] = json!("ok");
+                reply["ref"] = json!(message.reference);
+                reply
+            }
+            Err(failure) => json!({
+                "type": "error",
+                "ref": message.reference,
+                "error": failure.body(),
+            }),
+        }
+    }
+}
+
+fn text(value: Value) -> Message {
+    Message::Text(value.to_string())
+}
+
+// Left out of the OpenAPI spec, which can't describe a socket protocol
+#[openapi(skip)]
+#[get("/ws")]
+fn sync<'r>(
+    ws: WebSocket,
+    db: &'r State<DbConnPool>,
+    clock: &'r State<SharedClock>,
+    events: &'r State<TaskEvents>,
+    user: AuthenticatedUser,
+    mut shutdown: Shutdown,
+) -> Channel<'r> {
+    let mut session = Session {
+        id: NEXT_SOCKET.fetch_add(1, Ordering::Relaxed),
+        user_id: user.id,
+        db,
+        clock,
+        events,
+        lists: HashSet::new(),
+    };
+    let mut receiver = events.subscribe();
+
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            loop {
+                select! {
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Text(message))) => {
+                            let reply = session.reply(&message).await;
+                            stream.send(text(reply)).await?;
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        // Pings are answered by the library; binary frames
+                        // aren't part of the protocol
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => return Err(e),
+                    },
+                    published = receiver.recv() => match published {
+                        Ok(published) if session.wants(&published) => {
+                            stream.send(text(published.event.to_json())).await?;
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(_)) => {
+                            stream.send(text(json!({ "type": "resync" }))).await?;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+
+            Ok(())
+        })
+    })
+}
+
+pub fn routes() -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![sync]
+}
@@ -0,0 +1,75 @@
+// Service worker for the to-do PWA.
+//
+// The app shell is cached under a name derived from /assets/digest, so a
+// deploy that changes the digest installs a fresh cache and drops the old
+// one. API reads are network-first and fall back to the last cached response
+// while offline.
+
+const SHELL = ["/", "/manifest.webmanifest"];
+const API_CACHE = "todo-api";
+
+async function currentDigest() {
+  const response = await fetch("/assets/digest", { cache: "no-store" });
+  const { digest } = await response.json();
+  return digest;
+}
+
+self.addEventListener("install", (event) => {
+  event.waitUntil(
+    (async () => {
+      const cache = await caches.open(`todo-shell-${await currentDigest()}`);
+      await cache.addAll(SHELL);
+      await self.skipWaiting();
+    })()
+  );
+});
+
+self.addEventListener("activate", (event) => {
+  event.waitUntil(
+    (async () => {
+      const keep = `todo-shell-${await currentDigest()}`;
+      for (const name of await caches.keys()) {
+        // Cached API data may predate the deploy, so it goes too
+        if (name !== keep) {
+          await caches.delete(name);
+        }
+      }
+      await self.clients.claim();
+    })()
+  );
+});
+
+self.addEventListener("fetch", (event) => {
+  const request = event.request;
+  const url = new URL(request.url);
+
+  if (request.method !== "GET" || url.origin !== self.location.origin) {
+    return;
+  }
+
+  if (url.pathname.startsWith("/tasks") || url.pathname.startsWith("/schedules")) {
+    event.respondWith(
+      (async () => {
+        const cache = await caches.open(API_CACHE);
+        try {
+          const response = await fetch(request);
+          if (response.ok) {
+            await cache.put(request, response.clone());
+          }
+          return response;
+        } catch (error) {
+          const cached = await cache.match(request);
+          if (cached) {
+            return cached;
+          }
+          throw error;
+        }
+      })()
+    );
+    return;
+  }
+
+  event.respondWith(
+    caches.match(request).then((cached) => cached || fetch(request))
+  );
+});
@@ -0,0 +1,10 @@
+-- URLs notified when a user's tasks change. The secret signs each delivery
+-- and is stored as is, since signing needs it.
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INT PRIMARY KEY AUTO_INCREMENT,
+    user_id INT NOT NULL,
+    url VARCHAR(2048) NOT NULL,
+    secret CHAR(64) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_webhooks_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
@@ -0,0 +1,12 @@
+-- Changes to single occurrences of a schedule, after RFC 5545. An occurrence
+-- is identified by its RECURRENCE-ID, the time the schedule's cron gives it
+-- (in UTC). A row without moved_to skips it, like an EXDATE; one with
+-- moved_to runs it at that time instead.
+CREATE TABLE IF NOT EXISTS schedule_exceptions (
+    schedule_id INT NOT NULL,
+    recurrence_id DATETIME NOT NULL,
+    moved_to DATETIME NULL,
+    PRIMARY KEY (schedule_id, recurrence_id),
+    CONSTRAINT fk_schedule_exceptions_schedule
+        FOREIGN KEY (schedule_id) REFERENCES schedules (id) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Every occurrence of a schedule the scheduler got to, with the task it
+-- created, or no task when the occurrence was skipped. task_id has no
+-- foreign key so a run outlives its task being purged from the trash.
+CREATE TABLE IF NOT EXISTS schedule_runs (
+    schedule_id INT NOT NULL,
+    ran_at DATETIME NOT NULL,
+    task_id INT NULL,
+    PRIMARY KEY (schedule_id, ran_at),
+    CONSTRAINT fk_schedule_runs_schedule
+        FOREIGN KEY (schedule_id) REFERENCES schedules (id) ON DELETE CASCADE
+);
@@ -0,0 +1,22 @@
+-- Habits: something to do `times` times per day, week or month, judged in
+-- the habit's timezone. Each check-in is a row of its own, so a habit done
+-- twice in a day counts twice.
+CREATE TABLE IF NOT EXISTS habits (
+    id INT PRIMARY KEY AUTO_INCREMENT,
+    user_id INT NOT NULL,
+    description TEXT NOT NULL,
+    times INT NOT NULL,
+    period VARCHAR(16) NOT NULL,
+    timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_habits_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS habit_checkins (
+    id INT PRIMARY KEY AUTO_INCREMENT,
+    habit_id INT NOT NULL,
+    done_at DATETIME NOT NULL,
+    INDEX idx_habit_checkins_done_at (habit_id, done_at),
+    CONSTRAINT fk_habit_checkins_habit
+        FOREIGN KEY (habit_id) REFERENCES habits (id) ON DELETE CASCADE
+);
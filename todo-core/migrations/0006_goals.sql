@@ -0,0 +1,39 @@
+-- Goals and what counts towards them: single tasks, or every task filed
+-- under a list. goal_snapshots keeps one progress reading per goal per day
+-- (UTC), overwritten through the day, which GET /goals/<id>/progress turns
+-- into a time series.
+CREATE TABLE IF NOT EXISTS goals (
+    id INT PRIMARY KEY AUTO_INCREMENT,
+    user_id INT NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    description TEXT NULL,
+    due_date DATETIME NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_goals_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS goal_tasks (
+    goal_id INT NOT NULL,
+    task_id INT NOT NULL,
+    PRIMARY KEY (goal_id, task_id),
+    CONSTRAINT fk_goal_tasks_goal FOREIGN KEY (goal_id) REFERENCES goals (id) ON DELETE CASCADE,
+    CONSTRAINT fk_goal_tasks_task FOREIGN KEY (task_id) REFERENCES tasks (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS goal_lists (
+    goal_id INT NOT NULL,
+    list_id INT NOT NULL,
+    PRIMARY KEY (goal_id, list_id),
+    CONSTRAINT fk_goal_lists_goal FOREIGN KEY (goal_id) REFERENCES goals (id) ON DELETE CASCADE,
+    CONSTRAINT fk_goal_lists_list FOREIGN KEY (list_id) REFERENCES lists (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS goal_snapshots (
+    goal_id INT NOT NULL,
+    day DATE NOT NULL,
+    total INT NOT NULL,
+    completed INT NOT NULL,
+    PRIMARY KEY (goal_id, day),
+    CONSTRAINT fk_goal_snapshots_goal
+        FOREIGN KEY (goal_id) REFERENCES goals (id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- The payload version a hook is pinned to, or NULL to follow the latest
+ALTER TABLE webhooks ADD COLUMN payload_version INT NULL;
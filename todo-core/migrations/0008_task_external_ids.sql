@@ -0,0 +1,3 @@
+-- Every task gets an external id so an export can be matched up again on
+-- import. New tasks are given one when inserted; this fills in the rest.
+UPDATE tasks SET external_id = CONCAT('task-', id) WHERE external_id IS NULL;
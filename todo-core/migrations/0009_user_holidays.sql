@@ -0,0 +1,11 @@
+-- Holidays belong to the user who added them. Each existing user keeps a
+-- copy of the calendar that used to be shared.
+ALTER TABLE holidays ADD COLUMN user_id INT NULL;
+ALTER TABLE holidays DROP INDEX `date`;
+ALTER TABLE holidays ADD UNIQUE KEY uq_holidays_user_date (user_id, date);
+INSERT INTO holidays (user_id, date, name)
+    SELECT users.id, shared.date, shared.name
+    FROM users CROSS JOIN (SELECT date, name FROM holidays WHERE user_id IS NULL) AS shared;
+DELETE FROM holidays WHERE user_id IS NULL;
+ALTER TABLE holidays MODIFY user_id INT NOT NULL;
+ALTER TABLE holidays ADD CONSTRAINT fk_holidays_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
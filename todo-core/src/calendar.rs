@@ -0,0 +1,16 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use mysql::prelude::*;
+use mysql::*;
+use std::collections::HashSet;
+
+use crate::repository;
+
+// A user's holiday calendar as a set of dates, for business-day checks
+pub fn load<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<HashSet<NaiveDate>> {
+    repository::holidays::dates(conn, user_id).map(|dates| dates.into_iter().collect())
+}
+
+// Weekends and calendar holidays are not business days
+pub fn is_business_day(date: NaiveDate, calendar: &HashSet<NaiveDate>) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !calendar.contains(&date)
+}
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use mysql::prelude::Queryable;
+use mysql::*;
+
+use crate::models::{Goal, GoalProgress, GoalProgressPoint};
+use crate::repository;
+
+// Work out a goal's progress from its linked tasks and keep it as today's
+// reading
+pub fn current<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    goal_id: u32,
+    now: DateTime<Utc>,
+) -> Result<GoalProgress> {
+    let (total, completed) = repository::goals::count_tasks(conn, user_id, goal_id)?;
+    repository::goals::record_snapshot(conn, goal_id, now.date_naive(), total, completed)?;
+
+    Ok(GoalProgress::new(total, completed))
+}
+
+// `goal` with its progress filled in
+pub fn with_progress<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    mut goal: Goal,
+    now: DateTime<Utc>,
+) -> Result<Goal> {
+    goal.progress = Some(current(conn, user_id, goal.id.unwrap_or_default(), now)?);
+    Ok(goal)
+}
+
+// A goal's daily readings since `since`, oldest first and ending with today.
+// Days nobody looked at the goal and the recorder didn't run are missing.
+pub fn history<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    goal_id: u32,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<Vec<GoalProgressPoint>> {
+    current(conn, user_id, goal_id, now)?;
+
+    Ok(
+        repository::goals::snapshots_since(conn, goal_id, since.date_naive())?
+            .into_iter()
+            .map(|(date, total, completed)| GoalProgressPoint {
+                date,
+                progress: GoalProgress::new(total, completed),
+            })
+            .collect(),
+    )
+}
+
+// Take today's reading of every goal, so the history has a point for each
+// day even when nobody looks
+pub fn record_all(pool: &Pool, now: DateTime<Utc>) -> Result<()> {
+    let mut conn = pool.get_conn()?;
+    for (goal_id, user_id) in repository::goals::all_ids(&mut conn)? {
+        current(&mut conn, user_id, goal_id, now)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::GoalProgress;
+
+    #[test]
+    fn progress_is_the_share_completed() {
+        let progress = GoalProgress::new(3, 1);
+        assert_eq!((progress.total, progress.completed), (3, 1));
+        assert_eq!(progress.percent, 33.3);
+        assert_eq!(GoalProgress::new(3, 2).percent, 66.7);
+        assert_eq!(GoalProgress::new(4, 4).percent, 100.0);
+    }
+
+    #[test]
+    fn a_goal_with_no_tasks_is_at_zero() {
+        assert_eq!(GoalProgress::new(0, 0).percent, 0.0);
+    }
+}
@@ -0,0 +1,236 @@
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use mysql::prelude::Queryable;
+use mysql::*;
+use std::collections::BTreeMap;
+
+use crate::models::{Habit, HabitPeriod, HabitProgress};
+use crate::repository;
+use crate::scheduling::parse_timezone;
+
+// Finished periods that adherence is worked out over
+const ADHERENCE_PERIODS: usize = 12;
+
+// First day of the period `date` falls in
+pub fn period_start(date: NaiveDate, period: HabitPeriod) -> NaiveDate {
+    match period {
+        HabitPeriod::Day => date,
+        HabitPeriod::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+        HabitPeriod::Month => date.with_day(1).unwrap_or(date),
+    }
+}
+
+fn next_period(start: NaiveDate, period: HabitPeriod) -> NaiveDate {
+    match period {
+        HabitPeriod::Day => start + Days::new(1),
+        HabitPeriod::Week => start + Days::new(7),
+        HabitPeriod::Month => start + Months::new(1),
+    }
+}
+
+// Work out how a habit is going from when it was done. Periods run from the
+// one the habit was created in up to the one `now` falls in, which is still
+// open; check-ins after `now` are left out.
+pub fn progress(habit: Habit, check_ins: &[DateTime<Utc>], now: DateTime<Utc>) -> HabitProgress {
+    let tz = parse_timezone(&habit.timezone).unwrap_or(Tz::UTC);
+    let period = habit.period;
+    let start_of = |at: DateTime<Utc>| period_start(at.with_timezone(&tz).date_naive(), period);
+
+    let current = start_of(now);
+    let mut counts: BTreeMap<NaiveDate, u32> = BTreeMap::new();
+    for &at in check_ins.iter().filter(|&&at| at <= now) {
+        *counts.entry(start_of(at)).or_default() += 1;
+    }
+
+    // Older check-ins than the habit, e.g. imported ones, still count
+    let first = habit
+        .created_at
+        .map(start_of)
+        .into_iter()
+        .chain(counts.keys().next().copied())
+        .min()
+        .unwrap_or(current)
+        .min(current);
+    let mut done = Vec::new();
+    let mut start = first;
+    while start <= current {
+        done.push(counts.get(&start).copied().unwrap_or(0));
+        start = next_period(start, period);
+    }
+
+    let times = habit.times.max(1);
+    let met: Vec<bool> = done.iter().map(|&count| count >= times).collect();
+    let (done_this_period, finished) = done.split_last().map_or((0, &[][..]), |(d, f)| (*d, f));
+
+    let recent = &finished[finished.len().saturating_sub(ADHERENCE_PERIODS)..];
+    let adherence = (!recent.is_empty()).then(|| {
+        let achieved: u32 = recent.iter().map(|&count| count.min(times)).sum();
+        achieved as f64 / (times as f64 * recent.len() as f64)
+    });
+
+    let runs = met.split(|&met| !met).map(<[bool]>::len);
+    let longest_streak = runs.max().unwrap_or(0) as u32;
+    // An unmet current period doesn't break the streak before it yet
+    let closed = if met.last() == Some(&true) {
+        &met[..]
+    } else {
+        &met[..finished.len()]
+    };
+    let current_streak = closed.iter().rev().take_while(|&&met| met).count() as u32;
+
+    HabitProgress {
+        habit,
+        period_start: current,
+        done_this_period,
+        adherence,
+        current_streak,
+        longest_streak,
+    }
+}
+
+// Load a habit's check-ins and work out its progress
+pub fn load_progress<Q: Queryable>(
+    conn: &mut Q,
+    habit: Habit,
+    now: DateTime<Utc>,
+) -> Result<HabitProgress> {
+    let check_ins: Vec<DateTime<Utc>> =
+        repository::habits::check_ins(conn, habit.id.unwrap_or_default())?
+            .into_iter()
+            .map(|at| at.and_utc())
+            .collect();
+
+    Ok(progress(habit, &check_ins, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(at: &str) -> DateTime<Utc> {
+        at.parse().expect("valid RFC 3339 time")
+    }
+
+    fn date(at: &str) -> NaiveDate {
+        at.parse().expect("valid date")
+    }
+
+    fn habit(times: u32, period: HabitPeriod, timezone: &str, created_at: &str) -> Habit {
+        Habit {
+            id: Some(1),
+            description: "Stretch".to_string(),
+            times,
+            period,
+            timezone: timezone.to_string(),
+            created_at: Some(utc(created_at)),
+        }
+    }
+
+    #[test]
+    fn periods_start_on_monday_and_the_first_of_the_month() {
+        let sunday = date("2024-03-31");
+        assert_eq!(period_start(sunday, HabitPeriod::Day), sunday);
+        assert_eq!(period_start(sunday, HabitPeriod::Week), date("2024-03-25"));
+        assert_eq!(period_start(sunday, HabitPeriod::Month), date("2024-03-01"));
+    }
+
+    #[test]
+    fn months_follow_on_from_month_ends() {
+        assert_eq!(
+            next_period(date("2024-01-01"), HabitPeriod::Month),
+            date("2024-02-01")
+        );
+        assert_eq!(
+            next_period(date("2024-12-30"), HabitPeriod::Week),
+            date("2025-01-06")
+        );
+    }
+
+    #[test]
+    fn an_unmet_current_period_keeps_the_streak() {
+        let habit = habit(1, HabitPeriod::Day, "UTC", "2024-06-01T08:00:00Z");
+        let check_ins = [
+            utc("2024-06-01T08:00:00Z"),
+            utc("2024-06-03T08:00:00Z"),
+            utc("2024-06-04T08:00:00Z"),
+            utc("2024-06-05T08:00:00Z"),
+        ];
+        let progress = progress(habit, &check_ins, utc("2024-06-06T12:00:00Z"));
+
+        assert_eq!(progress.period_start, date("2024-06-06"));
+        assert_eq!(progress.done_this_period, 0);
+        assert_eq!(progress.current_streak, 3);
+        assert_eq!(progress.longest_streak, 3);
+        // 4 of the 5 finished days
+        assert_eq!(progress.adherence, Some(0.8));
+    }
+
+    #[test]
+    fn meeting_the_current_period_extends_the_streak() {
+        let habit = habit(2, HabitPeriod::Week, "UTC", "2024-06-03T08:00:00Z");
+        let check_ins = [
+            utc("2024-06-03T08:00:00Z"),
+            utc("2024-06-05T08:00:00Z"),
+            utc("2024-06-10T08:00:00Z"),
+            utc("2024-06-11T08:00:00Z"),
+            // After now, so left out
+            utc("2024-06-20T08:00:00Z"),
+        ];
+        let progress = progress(habit, &check_ins, utc("2024-06-12T12:00:00Z"));
+
+        assert_eq!(progress.done_this_period, 2);
+        assert_eq!(progress.current_streak, 2);
+        assert_eq!(progress.adherence, Some(1.0));
+    }
+
+    #[test]
+    fn check_ins_count_in_the_habits_timezone() {
+        // 23:30 on 31 January in New York is already February in UTC
+        let habit = habit(
+            1,
+            HabitPeriod::Month,
+            "America/New_York",
+            "2024-01-10T15:00:00Z",
+        );
+        let check_ins = [utc("2024-02-01T04:30:00Z")];
+        let progress = progress(habit, &check_ins, utc("2024-02-15T12:00:00Z"));
+
+        assert_eq!(progress.period_start, date("2024-02-01"));
+        assert_eq!(progress.done_this_period, 0);
+        assert_eq!(progress.current_streak, 1);
+        assert_eq!(progress.adherence, Some(1.0));
+    }
+
+    #[test]
+    fn weeks_span_dst_changes() {
+        // The clocks go forward on Sunday 31 March; check-ins either side of
+        // it fall in the same week
+        let habit = habit(
+            2,
+            HabitPeriod::Week,
+            "Europe/Berlin",
+            "2024-03-25T08:00:00Z",
+        );
+        let check_ins = [utc("2024-03-30T22:30:00Z"), utc("2024-03-31T21:30:00Z")];
+        let progress = progress(habit, &check_ins, utc("2024-03-31T21:45:00Z"));
+
+        assert_eq!(progress.period_start, date("2024-03-25"));
+        assert_eq!(progress.done_this_period, 2);
+        assert_eq!(progress.adherence, None);
+    }
+
+    #[test]
+    fn a_gap_ends_the_streak() {
+        let habit = habit(1, HabitPeriod::Day, "UTC", "2024-06-01T08:00:00Z");
+        let check_ins = [
+            utc("2024-06-01T08:00:00Z"),
+            utc("2024-06-02T08:00:00Z"),
+            utc("2024-06-03T08:00:00Z"),
+            utc("2024-06-05T08:00:00Z"),
+        ];
+        let progress = progress(habit, &check_ins, utc("2024-06-07T12:00:00Z"));
+
+        assert_eq!(progress.current_streak, 0);
+        assert_eq!(progress.longest_streak, 3);
+    }
+}
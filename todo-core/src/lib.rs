@@ -0,0 +1,133 @@
+// Task engine shared by the web server and anything that wants to embed it
+// without going through HTTP: models, the SQL repository, and the services
+// built on top of them (holiday calendar, cron schedules, habit and goal
+// progress).
+//
+// `Engine` is the convenient entry point; the modules stay public for callers
+// that manage their own connections or transactions.
+
+pub mod calendar;
+pub mod clock;
+pub mod goals;
+pub mod habits;
+pub mod models;
+pub mod repository;
+pub mod scheduling;
+pub mod schema;
+pub mod validation;
+
+use mysql::*;
+
+use std::sync::Arc;
+
+use clock::{SharedClock, SystemClock};
+use models::{Task, TaskPatch, User};
+
+// Connection options the repository relies on
+//
+// Matched rather than changed rows are reported, so an UPDATE that leaves a
+// row as it was still counts as finding it.
+pub fn opts_from_url(database_url: &str) -> Result<OptsBuilder> {
+    let opts = Opts::from_url(database_url)?;
+
+    Ok(OptsBuilder::from_opts(opts)
+        .additional_capabilities(consts::CapabilityFlags::CLIENT_FOUND_ROWS))
+}
+
+// Handle on a task database. Tasks belong to users, so task methods take the
+// id of the user acting on them.
+#[derive(Clone)]
+pub struct Engine {
+    pool: Pool,
+    clock: SharedClock,
+}
+
+impl Engine {
+    // Connect to DATABASE_URL-style `mysql://` URL and migrate the schema
+    pub fn connect(database_url: &str) -> std::result::Result<Self, schema::MigrationError> {
+        let engine = Engine::from_pool(Pool::new(opts_from_url(database_url)?)?);
+        schema::init(&mut engine.pool.get_conn()?)?;
+        Ok(engine)
+    }
+
+    // Wrap an existing pool, e.g. one shared with the web server
+    pub fn from_pool(pool: Pool) -> Self {
+        Engine {
+            pool,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Use `clock` instead of the system time, e.g. a frozen one in tests
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Engine { clock, ..self }
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    // The account registered under `email`
+    pub fn user(&self, email: &str) -> Result<Option<User>> {
+        repository::users::find_by_email(&mut self.pool.get_conn()?, email)
+    }
+
+    // Give the tasks and schedules created before accounts existed to
+    // `user_id`, returning how many tasks and schedules it got
+    pub fn adopt_unowned(&self, user_id: u32) -> Result<(u64, u64)> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        let claimed = repository::users::claim_unowned(&mut tx, user_id)?;
+        tx.commit()?;
+        Ok(claimed)
+    }
+
+    pub fn tasks(&self, user_id: u32) -> Result<Vec<Task>> {
+        repository::tasks::list(&mut self.pool.get_conn()?, user_id)
+    }
+
+    pub fn task(&self, user_id: u32, id: u32) -> Result<Option<Task>> {
+        repository::tasks::find(&mut self.pool.get_conn()?, user_id, id)
+    }
+
+    pub fn add_task(&self, user_id: u32, description: &str) -> Result<Task> {
+        let now = self.clock.now();
+        let mut task = Task::new(description);
+        task.created_at = Some(now);
+        task.id = Some(repository::tasks::insert(
+            &mut self.pool.get_conn()?,
+            user_id,
+            &task,
+            now,
+        )?);
+
+        Ok(task)
+    }
+
+    // Mark a task done or not done, returning it if it exists
+    pub fn set_completed(&self, user_id: u32, id: u32, is_completed: bool) -> Result<Option<Task>> {
+        let mut conn = self.pool.get_conn()?;
+
+        let patch = TaskPatch {
+            is_completed: Some(is_completed),
+            ..TaskPatch::default()
+        };
+        if !repository::tasks::patch(&mut conn, user_id, id, &patch)? {
+            return Ok(None);
+        }
+
+        repository::tasks::find(&mut conn, user_id, id)
+    }
+
+    pub fn delete_task(&self, user_id: u32, id: u32) -> Result<()> {
+        let now = self.clock.now().naive_utc();
+        repository::tasks::delete(&mut self.pool.get_conn()?, user_id, id, now)?;
+        Ok(())
+    }
+
+    // Create tasks for schedules that are due; embedders without their own
+    // scheduler loop can call this periodically
+    pub fn run_due_schedules(&self) -> Result<()> {
+        scheduling::run_due(&self.pool, self.clock.now())
+    }
+}
@@ -0,0 +1,440 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Task struct for serialization/deserialization
+//
+// `external_id` is a caller-chosen key that stays the same across export and
+// import, so re-importing an edited export updates tasks instead of copying them.
+// `start_date`, `due_date` and `created_at` are RFC 3339 timestamps, reported
+// in UTC; `created_at` is set on insert. A task with a future `start_date`
+// isn't actionable yet. `list_id` is the list the task is filed
+// under, if any; `tags` are names of the user's tags, created as needed.
+// `parent_id` makes the task a subtask of another of the user's tasks.
+// `deleted_at` is set while the task is in the trash and can't be written.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Task {
+    pub id: Option<u32>,
+    #[serde(default)]
+    pub external_id: Option<String>,
+    pub description: String,
+    pub is_completed: bool,
+    #[serde(default)]
+    pub list_id: Option<u32>,
+    #[serde(default)]
+    pub parent_id: Option<u32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub start_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub due_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_deserializing)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Task {
+    // A new, open task
+    pub fn new(description: impl Into<String>) -> Self {
+        Task {
+            id: None,
+            external_id: None,
+            description: description.into(),
+            is_completed: false,
+            list_id: None,
+            parent_id: None,
+            tags: Vec::new(),
+            priority: Priority::default(),
+            start_date: None,
+            due_date: None,
+            created_at: None,
+            deleted_at: None,
+        }
+    }
+}
+
+// How urgent a task is, stored as a TINYINT so that higher sorts later
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "low" => Some(Priority::Low),
+            "medium" => Some(Priority::Medium),
+            "high" => Some(Priority::High),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Priority::Low => 1,
+            Priority::Medium => 2,
+            Priority::High => 3,
+        }
+    }
+
+    // Unknown values read back from the database count as medium
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Priority::Low,
+            3 => Priority::High,
+            _ => Priority::Medium,
+        }
+    }
+}
+
+// Fields to change on an existing task; anything left out keeps its value.
+// `"start_date": null` and `"due_date": null` clear those dates, and
+// `"list_id": null` and `"parent_id": null` take the task out of its list or
+// up to the top level, which is why those are double Options.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TaskPatch {
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub is_completed: Option<bool>,
+    #[serde(default, deserialize_with = "present")]
+    pub list_id: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "present")]
+    pub parent_id: Option<Option<u32>>,
+    // Replaces all of the task's tags
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default, deserialize_with = "present")]
+    pub start_date: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub due_date: Option<Option<DateTime<Utc>>>,
+}
+
+// Distinguishes a field sent as null (Some(None)) from one left out (None)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// What to do with a scheduled run that falls on a weekend or holiday
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HolidayPolicy {
+    #[default]
+    Run,
+    Skip,
+    NextBusinessDay,
+}
+
+impl HolidayPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HolidayPolicy::Run => "run",
+            HolidayPolicy::Skip => "skip",
+            HolidayPolicy::NextBusinessDay => "next_business_day",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "skip" => HolidayPolicy::Skip,
+            "next_business_day" => HolidayPolicy::NextBusinessDay,
+            _ => HolidayPolicy::Run,
+        }
+    }
+}
+
+// Schedule struct for serialization/deserialization
+//
+// `cron` accepts the usual five fields ("0 9 * * Mon") or the six/seven field
+// form with seconds and year. It is evaluated in `timezone` (an IANA name), and
+// `next_run_at` is always reported in UTC. `on_holiday` decides whether runs on
+// weekends and holiday-calendar dates happen, are skipped, or move forward.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Schedule {
+    pub id: Option<u32>,
+    // Owner, filled in from the database and never part of a payload
+    #[serde(skip)]
+    pub user_id: Option<u32>,
+    pub description: String,
+    pub cron: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub on_holiday: HolidayPolicy,
+    #[serde(default)]
+    pub next_run_at: Option<DateTime<Utc>>,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+// A change to one occurrence of a schedule. `recurrence_id` is when the cron
+// would run it, and `moved_to` when it runs instead; without `moved_to` the
+// occurrence is skipped.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduleException {
+    pub recurrence_id: DateTime<Utc>,
+    pub moved_to: Option<DateTime<Utc>>,
+}
+
+// How one run of a schedule turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    // Its task was completed
+    Done,
+    // Its task was left open past the day of the run, or deleted unfinished
+    Missed,
+    // Its task is open and the day isn't over
+    Open,
+    // No task was created, e.g. for a holiday or a skipped occurrence
+    Skipped,
+}
+
+// One run of a schedule, dated in the schedule's timezone
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduleRun {
+    pub date: NaiveDate,
+    pub ran_at: DateTime<Utc>,
+    pub task_id: Option<u32>,
+    pub status: RunStatus,
+}
+
+// The stretch of time a habit's target applies to. Weeks start on Monday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HabitPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl HabitPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HabitPeriod::Day => "day",
+            HabitPeriod::Week => "week",
+            HabitPeriod::Month => "month",
+        }
+    }
+
+    // Unknown values read back from the database count as weekly
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "day" => HabitPeriod::Day,
+            "month" => HabitPeriod::Month,
+            _ => HabitPeriod::Week,
+        }
+    }
+}
+
+// Something to do `times` times per `period`, e.g. 3 times a week. Periods
+// are judged in `timezone`, an IANA name.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Habit {
+    pub id: Option<u32>,
+    pub description: String,
+    pub times: u32,
+    pub period: HabitPeriod,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// How a habit is going. `adherence` is the share of the target met over the
+// last finished periods, absent until one has finished; a streak counts
+// periods in a row that met the target, the current one included once met.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct HabitProgress {
+    #[serde(flatten)]
+    pub habit: Habit,
+    // Start of the current period, in the habit's timezone
+    pub period_start: NaiveDate,
+    pub done_this_period: u32,
+    pub adherence: Option<f64>,
+    pub current_streak: u32,
+    pub longest_streak: u32,
+}
+
+// An objective, e.g. for a quarter, that tasks count towards. Linking a list
+// counts every task filed under it, and linking replaces the goal's links on
+// each write. `progress` is worked out from the linked tasks on every read.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Goal {
+    pub id: Option<u32>,
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub due_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub task_ids: Vec<u32>,
+    #[serde(default)]
+    pub list_ids: Vec<u32>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_deserializing)]
+    pub progress: Option<GoalProgress>,
+}
+
+// Linked tasks, trashed ones aside, and how many of them are completed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct GoalProgress {
+    pub total: u32,
+    pub completed: u32,
+    // Share completed, 0 to 100; 0 while nothing is linked
+    pub percent: f64,
+}
+
+impl GoalProgress {
+    pub fn new(total: u32, completed: u32) -> Self {
+        let percent = if total == 0 {
+            0.0
+        } else {
+            (completed as f64 * 1000.0 / total as f64).round() / 10.0
+        };
+        GoalProgress {
+            total,
+            completed,
+            percent,
+        }
+    }
+}
+
+// A goal's progress as it stood at the end of a day (UTC), or now for today
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct GoalProgressPoint {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub progress: GoalProgress,
+}
+
+// A registered account. The password hash stays in the repository.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct User {
+    pub id: u32,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A long-lived credential for scripts and integrations. Only a hash of the
+// key is stored; the key itself is shown once, when it is created. Keys
+// without `expires_at` never expire.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKey {
+    pub id: Option<u32>,
+    pub label: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// A URL that is sent a signed POST when one of the user's tasks is created,
+// completed or deleted. The signing secret is shown once, when the hook is
+// created.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Webhook {
+    pub id: Option<u32>,
+    pub url: String,
+    // Payload version deliveries use; unpinned hooks get the latest
+    #[serde(default)]
+    pub payload_version: Option<u32>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// Payload versions hooks can be sent, oldest first. A version stays listed
+// for a while after a newer one ships, so subscribers have time to move;
+// hooks still pinned to one that has been dropped get the oldest left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PayloadVersion {
+    V1,
+}
+
+impl PayloadVersion {
+    pub const ALL: &'static [PayloadVersion] = &[PayloadVersion::V1];
+    pub const OLDEST: PayloadVersion = PayloadVersion::V1;
+    pub const LATEST: PayloadVersion = PayloadVersion::V1;
+
+    // The number hooks pin and deliveries are labelled with
+    pub fn number(self) -> u32 {
+        match self {
+            PayloadVersion::V1 => 1,
+        }
+    }
+
+    pub fn from_number(number: u32) -> Option<Self> {
+        PayloadVersion::ALL
+            .iter()
+            .copied()
+            .find(|version| version.number() == number)
+    }
+}
+
+impl Webhook {
+    // The version deliveries to a hook pinned to `pinned` are sent in
+    pub fn payload_version_for(pinned: Option<u32>) -> PayloadVersion {
+        let Some(pinned) = pinned else {
+            return PayloadVersion::LATEST;
+        };
+        PayloadVersion::ALL
+            .iter()
+            .copied()
+            .rev()
+            .find(|version| version.number() <= pinned)
+            .unwrap_or(PayloadVersion::OLDEST)
+    }
+}
+
+// A named list that tasks can be filed under
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TaskList {
+    pub id: Option<u32>,
+    pub name: String,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// A label a user can put on any number of tasks; names are unique per user
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Tag {
+    pub id: Option<u32>,
+    pub name: String,
+}
+
+// Holiday struct for serialization/deserialization
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Holiday {
+    pub id: Option<u32>,
+    pub date: NaiveDate,
+    pub name: String,
+}
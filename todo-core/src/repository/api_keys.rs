@@ -0,0 +1,82 @@
+use chrono::{NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::ApiKey;
+
+type ApiKeyRow = (u32, String, Option<NaiveDateTime>, NaiveDateTime);
+
+fn from_row((id, label, expires_at, created_at): ApiKeyRow) -> ApiKey {
+    ApiKey {
+        id: Some(id),
+        label,
+        expires_at: expires_at.map(|at| at.and_utc()),
+        created_at: Some(created_at.and_utc()),
+    }
+}
+
+// A user's keys, expired ones included
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<ApiKey>> {
+    conn.exec_map(
+        "SELECT id, label, expires_at, created_at FROM api_keys
+         WHERE user_id = :user_id ORDER BY id",
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )
+}
+
+// The user an unexpired key with this hash belongs to
+pub fn find_user<Q: Queryable>(
+    conn: &mut Q,
+    key_hash: &str,
+    now: NaiveDateTime,
+) -> Result<Option<u32>> {
+    conn.exec_first(
+        "SELECT user_id FROM api_keys
+         WHERE key_hash = :key_hash AND (expires_at IS NULL OR expires_at > :now)",
+        params! {
+            "key_hash" => key_hash,
+            "now" => now,
+        },
+    )
+}
+
+// Store a key by its hash, returning the key as saved
+pub fn insert<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    api_key: &ApiKey,
+    key_hash: &str,
+) -> Result<ApiKey> {
+    let created_at = api_key.created_at.unwrap_or_else(Utc::now);
+    let result = conn.exec_iter(
+        "INSERT INTO api_keys (user_id, label, key_hash, expires_at, created_at)
+         VALUES (:user_id, :label, :key_hash, :expires_at, :created_at)",
+        params! {
+            "user_id" => user_id,
+            "label" => &api_key.label,
+            "key_hash" => key_hash,
+            "expires_at" => api_key.expires_at.map(|at| at.naive_utc()),
+            "created_at" => created_at.naive_utc(),
+        },
+    )?;
+
+    Ok(ApiKey {
+        id: Some(result.last_insert_id().unwrap_or_default() as u32),
+        label: api_key.label.clone(),
+        expires_at: api_key.expires_at,
+        created_at: Some(created_at),
+    })
+}
+
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM api_keys WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
@@ -0,0 +1,226 @@
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::Goal;
+
+const COLUMNS: &str = "id, title, description, due_date, created_at";
+
+type GoalRow = (
+    u32,
+    String,
+    Option<String>,
+    Option<NaiveDateTime>,
+    NaiveDateTime,
+);
+
+fn from_row((id, title, description, due_date, created_at): GoalRow) -> Goal {
+    Goal {
+        id: Some(id),
+        title,
+        description,
+        due_date: due_date.map(|due_date| due_date.and_utc()),
+        task_ids: Vec::new(),
+        list_ids: Vec::new(),
+        created_at: Some(created_at.and_utc()),
+        progress: None,
+    }
+}
+
+// Fill in the links of goals read by `from_row`
+fn with_links<Q: Queryable>(conn: &mut Q, mut goals: Vec<Goal>) -> Result<Vec<Goal>> {
+    for goal in &mut goals {
+        let goal_id = goal.id.unwrap_or_default();
+        goal.task_ids = conn.exec(
+            "SELECT task_id FROM goal_tasks WHERE goal_id = :goal_id ORDER BY task_id",
+            params! {
+                "goal_id" => goal_id,
+            },
+        )?;
+        goal.list_ids = conn.exec(
+            "SELECT list_id FROM goal_lists WHERE goal_id = :goal_id ORDER BY list_id",
+            params! {
+                "goal_id" => goal_id,
+            },
+        )?;
+    }
+    Ok(goals)
+}
+
+// A user's goals
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Goal>> {
+    let goals = conn.exec_map(
+        format!(
+            "SELECT {} FROM goals WHERE user_id = :user_id ORDER BY id",
+            COLUMNS
+        ),
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )?;
+    with_links(conn, goals)
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<Goal>> {
+    let goal = conn
+        .exec_first(
+            format!(
+                "SELECT {} FROM goals WHERE id = :id AND user_id = :user_id",
+                COLUMNS
+            ),
+            params! {
+                "id" => id,
+                "user_id" => user_id,
+            },
+        )?
+        .map(from_row);
+    Ok(with_links(conn, goal.into_iter().collect())?.pop())
+}
+
+// Replace the tasks and lists linked to a goal
+fn set_links<Q: Queryable>(conn: &mut Q, goal_id: u32, goal: &Goal) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM goal_tasks WHERE goal_id = :goal_id",
+        params! {
+            "goal_id" => goal_id,
+        },
+    )?;
+    conn.exec_batch(
+        "INSERT IGNORE INTO goal_tasks (goal_id, task_id) VALUES (:goal_id, :task_id)",
+        goal.task_ids.iter().map(|&task_id| {
+            params! {
+                "goal_id" => goal_id,
+                "task_id" => task_id,
+            }
+        }),
+    )?;
+
+    conn.exec_drop(
+        "DELETE FROM goal_lists WHERE goal_id = :goal_id",
+        params! {
+            "goal_id" => goal_id,
+        },
+    )?;
+    conn.exec_batch(
+        "INSERT IGNORE INTO goal_lists (goal_id, list_id) VALUES (:goal_id, :list_id)",
+        goal.list_ids.iter().map(|&list_id| {
+            params! {
+                "goal_id" => goal_id,
+                "list_id" => list_id,
+            }
+        }),
+    )
+}
+
+// Insert a goal with its links and return its new id
+pub fn insert<Q: Queryable>(conn: &mut Q, user_id: u32, goal: &Goal) -> Result<u32> {
+    let result = conn.exec_iter(
+        "INSERT INTO goals (user_id, title, description, due_date, created_at)
+         VALUES (:user_id, :title, :description, :due_date, :created_at)",
+        params! {
+            "user_id" => user_id,
+            "title" => &goal.title,
+            "description" => &goal.description,
+            "due_date" => goal.due_date.map(|due_date| due_date.naive_utc()),
+            "created_at" => goal.created_at.unwrap_or_else(Utc::now).naive_utc(),
+        },
+    )?;
+    let id = result.last_insert_id().unwrap_or_default() as u32;
+    drop(result);
+
+    set_links(conn, id, goal)?;
+    Ok(id)
+}
+
+// Overwrite a goal and its links, returning whether it exists
+pub fn update<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32, goal: &Goal) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE goals SET title = :title, description = :description, due_date = :due_date
+         WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "title" => &goal.title,
+            "description" => &goal.description,
+            "due_date" => goal.due_date.map(|due_date| due_date.naive_utc()),
+        },
+    )?;
+    let found = result.affected_rows() > 0;
+    drop(result);
+
+    if found {
+        set_links(conn, id, goal)?;
+    }
+    Ok(found)
+}
+
+// Delete a goal; the tasks and lists linked to it stay
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM goals WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
+
+// Every goal as (id, owner), for recording progress in the background
+pub fn all_ids<Q: Queryable>(conn: &mut Q) -> Result<Vec<(u32, u32)>> {
+    conn.exec("SELECT id, user_id FROM goals", ())
+}
+
+// Linked tasks not in the trash, and how many of them are completed. A task
+// both linked itself and filed under a linked list counts once.
+pub fn count_tasks<Q: Queryable>(conn: &mut Q, user_id: u32, goal_id: u32) -> Result<(u32, u32)> {
+    let counts: Option<(u32, u32)> = conn.exec_first(
+        "SELECT COUNT(*), CAST(COALESCE(SUM(is_completed), 0) AS UNSIGNED) FROM tasks
+         WHERE user_id = :user_id AND deleted_at IS NULL
+         AND (id IN (SELECT task_id FROM goal_tasks WHERE goal_id = :goal_id)
+              OR list_id IN (SELECT list_id FROM goal_lists WHERE goal_id = :goal_id))",
+        params! {
+            "user_id" => user_id,
+            "goal_id" => goal_id,
+        },
+    )?;
+
+    Ok(counts.unwrap_or_default())
+}
+
+// Record a goal's progress for `day`, replacing any earlier reading that day
+pub fn record_snapshot<Q: Queryable>(
+    conn: &mut Q,
+    goal_id: u32,
+    day: NaiveDate,
+    total: u32,
+    completed: u32,
+) -> Result<()> {
+    conn.exec_drop(
+        "INSERT INTO goal_snapshots (goal_id, day, total, completed)
+         VALUES (:goal_id, :day, :total, :completed)
+         ON DUPLICATE KEY UPDATE total = VALUES(total), completed = VALUES(completed)",
+        params! {
+            "goal_id" => goal_id,
+            "day" => day,
+            "total" => total,
+            "completed" => completed,
+        },
+    )
+}
+
+// Readings on or after `since`, oldest first, as (day, total, completed)
+pub fn snapshots_since<Q: Queryable>(
+    conn: &mut Q,
+    goal_id: u32,
+    since: NaiveDate,
+) -> Result<Vec<(NaiveDate, u32, u32)>> {
+    conn.exec(
+        "SELECT day, total, completed FROM goal_snapshots
+         WHERE goal_id = :goal_id AND day >= :since ORDER BY day",
+        params! {
+            "goal_id" => goal_id,
+            "since" => since,
+        },
+    )
+}
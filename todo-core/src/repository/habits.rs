@@ -0,0 +1,130 @@
+use chrono::{NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::{Habit, HabitPeriod};
+
+const COLUMNS: &str = "id, description, times, period, timezone, created_at";
+
+type HabitRow = (u32, String, u32, String, String, NaiveDateTime);
+
+fn from_row((id, description, times, period, timezone, created_at): HabitRow) -> Habit {
+    Habit {
+        id: Some(id),
+        description,
+        times,
+        period: HabitPeriod::parse(&period),
+        timezone,
+        created_at: Some(created_at.and_utc()),
+    }
+}
+
+// A user's habits
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Habit>> {
+    conn.exec_map(
+        format!(
+            "SELECT {} FROM habits WHERE user_id = :user_id ORDER BY id",
+            COLUMNS
+        ),
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<Habit>> {
+    conn.exec_first(
+        format!(
+            "SELECT {} FROM habits WHERE id = :id AND user_id = :user_id",
+            COLUMNS
+        ),
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+    .map(|row| row.map(from_row))
+}
+
+// Insert a habit and return its new id
+pub fn insert<Q: Queryable>(conn: &mut Q, user_id: u32, habit: &Habit) -> Result<u32> {
+    let result = conn.exec_iter(
+        "INSERT INTO habits (user_id, description, times, period, timezone, created_at)
+         VALUES (:user_id, :description, :times, :period, :timezone, :created_at)",
+        params! {
+            "user_id" => user_id,
+            "description" => &habit.description,
+            "times" => habit.times,
+            "period" => habit.period.as_str(),
+            "timezone" => &habit.timezone,
+            "created_at" => habit.created_at.unwrap_or_else(Utc::now).naive_utc(),
+        },
+    )?;
+
+    Ok(result.last_insert_id().unwrap_or_default() as u32)
+}
+
+// Overwrite a habit's target, returning whether it exists. Check-ins are
+// kept, so past periods are judged by the new target.
+pub fn update<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32, habit: &Habit) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE habits SET description = :description, times = :times,
+         period = :period, timezone = :timezone
+         WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "description" => &habit.description,
+            "times" => habit.times,
+            "period" => habit.period.as_str(),
+            "timezone" => &habit.timezone,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// Delete a habit along with its check-ins
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM habits WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
+
+pub fn check_in<Q: Queryable>(conn: &mut Q, habit_id: u32, done_at: NaiveDateTime) -> Result<()> {
+    conn.exec_drop(
+        "INSERT INTO habit_checkins (habit_id, done_at) VALUES (:habit_id, :done_at)",
+        params! {
+            "habit_id" => habit_id,
+            "done_at" => done_at,
+        },
+    )
+}
+
+// Take back the latest check-in, returning whether there was one
+pub fn undo_check_in<Q: Queryable>(conn: &mut Q, habit_id: u32) -> Result<bool> {
+    let result = conn.exec_iter(
+        "DELETE FROM habit_checkins WHERE habit_id = :habit_id
+         ORDER BY done_at DESC, id DESC LIMIT 1",
+        params! {
+            "habit_id" => habit_id,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// When a habit was done, oldest first
+pub fn check_ins<Q: Queryable>(conn: &mut Q, habit_id: u32) -> Result<Vec<NaiveDateTime>> {
+    conn.exec(
+        "SELECT done_at FROM habit_checkins WHERE habit_id = :habit_id ORDER BY done_at",
+        params! {
+            "habit_id" => habit_id,
+        },
+    )
+}
@@ -0,0 +1,63 @@
+use chrono::NaiveDate;
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::Holiday;
+
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Holiday>> {
+    conn.exec_map(
+        "SELECT id, date, name FROM holidays WHERE user_id = :user_id ORDER BY date",
+        params! {
+            "user_id" => user_id,
+        },
+        |(id, date, name)| Holiday {
+            id: Some(id),
+            date,
+            name,
+        },
+    )
+}
+
+pub fn dates<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<NaiveDate>> {
+    conn.exec_map(
+        "SELECT date FROM holidays WHERE user_id = :user_id",
+        params! {
+            "user_id" => user_id,
+        },
+        |date: NaiveDate| date,
+    )
+}
+
+// Insert a holiday, renaming the user's existing one on the same date;
+// returns its id
+pub fn upsert<Q: Queryable>(conn: &mut Q, user_id: u32, holiday: &Holiday) -> Result<u32> {
+    conn.exec_drop(
+        "INSERT INTO holidays (user_id, date, name) VALUES (:user_id, :date, :name)
+         ON DUPLICATE KEY UPDATE name = VALUES(name)",
+        params! {
+            "user_id" => user_id,
+            "date" => holiday.date,
+            "name" => &holiday.name,
+        },
+    )?;
+
+    let id: Option<u32> = conn.exec_first(
+        "SELECT id FROM holidays WHERE user_id = :user_id AND date = :date",
+        params! {
+            "user_id" => user_id,
+            "date" => holiday.date,
+        },
+    )?;
+
+    Ok(id.unwrap_or_default())
+}
+
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM holidays WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
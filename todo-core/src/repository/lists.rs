@@ -0,0 +1,76 @@
+use chrono::{NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::TaskList;
+
+type ListRow = (u32, String, NaiveDateTime);
+
+fn from_row((id, name, created_at): ListRow) -> TaskList {
+    TaskList {
+        id: Some(id),
+        name,
+        created_at: Some(created_at.and_utc()),
+    }
+}
+
+// All of a user's lists
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<TaskList>> {
+    conn.exec_map(
+        "SELECT id, name, created_at FROM lists WHERE user_id = :user_id ORDER BY id",
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<TaskList>> {
+    conn.exec_first(
+        "SELECT id, name, created_at FROM lists WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+    .map(|row| row.map(from_row))
+}
+
+// Insert a list and return its new id
+pub fn insert<Q: Queryable>(conn: &mut Q, user_id: u32, list: &TaskList) -> Result<u32> {
+    let result = conn.exec_iter(
+        "INSERT INTO lists (user_id, name, created_at) VALUES (:user_id, :name, :created_at)",
+        params! {
+            "user_id" => user_id,
+            "name" => &list.name,
+            "created_at" => list.created_at.unwrap_or_else(Utc::now).naive_utc(),
+        },
+    )?;
+
+    Ok(result.last_insert_id().unwrap_or_default() as u32)
+}
+
+// Rename a list, returning whether it exists
+pub fn update<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32, list: &TaskList) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE lists SET name = :name WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "name" => &list.name,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// Delete a list along with the tasks filed under it
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM lists WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
@@ -0,0 +1,18 @@
+// All SQL lives in this module, one submodule per table.
+//
+// Every statement goes through the binary protocol (`exec*`), so the mysql
+// crate prepares it once per connection and reuses it from the connection's
+// statement cache (`stmt_cache_size` in DATABASE_URL, 32 by default). The
+// functions are generic over `Queryable` so they work on pooled connections
+// and inside transactions alike.
+
+pub mod api_keys;
+pub mod goals;
+pub mod habits;
+pub mod holidays;
+pub mod lists;
+pub mod schedules;
+pub mod tags;
+pub mod tasks;
+pub mod users;
+pub mod webhooks;
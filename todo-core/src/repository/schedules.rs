@@ -0,0 +1,262 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::{HolidayPolicy, Schedule, ScheduleException};
+
+const COLUMNS: &str = "id, user_id, description, cron, timezone, on_holiday, next_run_at";
+
+type ScheduleRow = (
+    u32,
+    Option<u32>,
+    String,
+    String,
+    String,
+    String,
+    NaiveDateTime,
+);
+
+fn from_row(
+    (id, user_id, description, cron, timezone, on_holiday, next_run_at): ScheduleRow,
+) -> Schedule {
+    Schedule {
+        id: Some(id),
+        user_id,
+        description,
+        cron,
+        timezone,
+        on_holiday: HolidayPolicy::parse(&on_holiday),
+        next_run_at: Some(next_run_at.and_utc()),
+    }
+}
+
+// A user's schedules
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Schedule>> {
+    conn.exec_map(
+        format!(
+            "SELECT {} FROM schedules WHERE user_id = :user_id ORDER BY id",
+            COLUMNS
+        ),
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<Schedule>> {
+    conn.exec_first(
+        format!(
+            "SELECT {} FROM schedules WHERE id = :id AND user_id = :user_id",
+            COLUMNS
+        ),
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+    .map(|row| row.map(from_row))
+}
+
+// Schedules of every user whose next run is at or before `now`
+pub fn list_due<Q: Queryable>(conn: &mut Q, now: NaiveDateTime) -> Result<Vec<Schedule>> {
+    conn.exec_map(
+        format!(
+            "SELECT {} FROM schedules WHERE next_run_at <= :now",
+            COLUMNS
+        ),
+        params! {
+            "now" => now,
+        },
+        from_row,
+    )
+}
+
+// Insert a schedule and return its new id
+pub fn insert<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    schedule: &Schedule,
+    next_run_at: NaiveDateTime,
+) -> Result<u32> {
+    let result = conn.exec_iter(
+        "INSERT INTO schedules (user_id, description, cron, timezone, on_holiday, next_run_at)
+         VALUES (:user_id, :description, :cron, :timezone, :on_holiday, :next_run_at)",
+        params! {
+            "user_id" => user_id,
+            "description" => &schedule.description,
+            "cron" => &schedule.cron,
+            "timezone" => &schedule.timezone,
+            "on_holiday" => schedule.on_holiday.as_str(),
+            "next_run_at" => next_run_at,
+        },
+    )?;
+
+    Ok(result.last_insert_id().unwrap_or_default() as u32)
+}
+
+// Overwrite a schedule, returning whether it exists
+pub fn update<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    id: u32,
+    schedule: &Schedule,
+    next_run_at: NaiveDateTime,
+) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE schedules SET description = :description, cron = :cron,
+         timezone = :timezone, on_holiday = :on_holiday, next_run_at = :next_run_at
+         WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "description" => &schedule.description,
+            "cron" => &schedule.cron,
+            "timezone" => &schedule.timezone,
+            "on_holiday" => schedule.on_holiday.as_str(),
+            "next_run_at" => next_run_at,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+pub fn set_next_run<Q: Queryable>(conn: &mut Q, id: u32, next_run_at: NaiveDateTime) -> Result<()> {
+    conn.exec_drop(
+        "UPDATE schedules SET next_run_at = :next_run_at WHERE id = :id",
+        params! {
+            "id" => id,
+            "next_run_at" => next_run_at,
+        },
+    )
+}
+
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM schedules WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
+
+// Drop a schedule that has no runs left, whoever owns it
+pub fn delete_finished<Q: Queryable>(conn: &mut Q, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM schedules WHERE id = :id",
+        params! {
+            "id" => id,
+        },
+    )
+}
+
+// Skipped and moved occurrences of a schedule, in the order the cron gives
+// them
+pub fn exceptions<Q: Queryable>(conn: &mut Q, schedule_id: u32) -> Result<Vec<ScheduleException>> {
+    conn.exec_map(
+        "SELECT recurrence_id, moved_to FROM schedule_exceptions
+         WHERE schedule_id = :schedule_id ORDER BY recurrence_id",
+        params! {
+            "schedule_id" => schedule_id,
+        },
+        |(recurrence_id, moved_to): (NaiveDateTime, Option<NaiveDateTime>)| ScheduleException {
+            recurrence_id: recurrence_id.and_utc(),
+            moved_to: moved_to.map(|moved_to| moved_to.and_utc()),
+        },
+    )
+}
+
+// Skip an occurrence, or with `moved_to` move it, replacing any earlier
+// change to it
+pub fn set_exception<Q: Queryable>(
+    conn: &mut Q,
+    schedule_id: u32,
+    exception: &ScheduleException,
+) -> Result<()> {
+    conn.exec_drop(
+        "INSERT INTO schedule_exceptions (schedule_id, recurrence_id, moved_to)
+         VALUES (:schedule_id, :recurrence_id, :moved_to)
+         ON DUPLICATE KEY UPDATE moved_to = VALUES(moved_to)",
+        params! {
+            "schedule_id" => schedule_id,
+            "recurrence_id" => exception.recurrence_id.naive_utc(),
+            "moved_to" => exception.moved_to.map(|moved_to| moved_to.naive_utc()),
+        },
+    )
+}
+
+// Put an occurrence back as the cron gives it, returning whether it had
+// been changed
+pub fn delete_exception<Q: Queryable>(
+    conn: &mut Q,
+    schedule_id: u32,
+    recurrence_id: DateTime<Utc>,
+) -> Result<bool> {
+    let result = conn.exec_iter(
+        "DELETE FROM schedule_exceptions
+         WHERE schedule_id = :schedule_id AND recurrence_id = :recurrence_id",
+        params! {
+            "schedule_id" => schedule_id,
+            "recurrence_id" => recurrence_id.naive_utc(),
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// Forget changes to occurrences that are over, whether skipped or moved
+pub fn delete_past_exceptions<Q: Queryable>(
+    conn: &mut Q,
+    schedule_id: u32,
+    now: NaiveDateTime,
+) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM schedule_exceptions
+         WHERE schedule_id = :schedule_id AND recurrence_id <= :now
+           AND (moved_to IS NULL OR moved_to <= :now)",
+        params! {
+            "schedule_id" => schedule_id,
+            "now" => now,
+        },
+    )
+}
+
+// Note that the scheduler got to an occurrence, creating `task_id` or
+// nothing if it was skipped
+pub fn record_run<Q: Queryable>(
+    conn: &mut Q,
+    schedule_id: u32,
+    ran_at: NaiveDateTime,
+    task_id: Option<u32>,
+) -> Result<()> {
+    conn.exec_drop(
+        "INSERT INTO schedule_runs (schedule_id, ran_at, task_id)
+         VALUES (:schedule_id, :ran_at, :task_id)
+         ON DUPLICATE KEY UPDATE task_id = VALUES(task_id)",
+        params! {
+            "schedule_id" => schedule_id,
+            "ran_at" => ran_at,
+            "task_id" => task_id,
+        },
+    )
+}
+
+// Runs of a schedule since `since`, oldest first: when each ran, its task
+// and whether the task is known to have been completed
+pub fn runs_since<Q: Queryable>(
+    conn: &mut Q,
+    schedule_id: u32,
+    since: NaiveDateTime,
+) -> Result<Vec<(NaiveDateTime, Option<u32>, bool)>> {
+    conn.exec(
+        "SELECT schedule_runs.ran_at, schedule_runs.task_id, COALESCE(tasks.is_completed, false)
+         FROM schedule_runs LEFT JOIN tasks ON tasks.id = schedule_runs.task_id
+         WHERE schedule_runs.schedule_id = :schedule_id AND schedule_runs.ran_at >= :since
+         ORDER BY schedule_runs.ran_at",
+        params! {
+            "schedule_id" => schedule_id,
+            "since" => since,
+        },
+    )
+}
@@ -0,0 +1,113 @@
+use mysql::prelude::*;
+use mysql::*;
+use std::collections::HashMap;
+
+use crate::models::Tag;
+
+// Task ids per tag lookup, well under the limit on placeholders in a statement
+const TASKS_PER_QUERY: usize = 1000;
+
+// All of a user's tags
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Tag>> {
+    conn.exec_map(
+        "SELECT id, name FROM tags WHERE user_id = :user_id ORDER BY name",
+        params! {
+            "user_id" => user_id,
+        },
+        |(id, name)| Tag { id: Some(id), name },
+    )
+}
+
+// Insert a tag and return its new id
+pub fn insert<Q: Queryable>(conn: &mut Q, user_id: u32, tag: &Tag) -> Result<u32> {
+    let result = conn.exec_iter(
+        "INSERT INTO tags (user_id, name) VALUES (:user_id, :name)",
+        params! {
+            "user_id" => user_id,
+            "name" => tag.name.trim(),
+        },
+    )?;
+
+    Ok(result.last_insert_id().unwrap_or_default() as u32)
+}
+
+// Delete a tag, taking it off every task
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM tags WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
+
+// Tag names of each of `task_ids`, sorted by name
+pub fn for_tasks<Q: Queryable>(
+    conn: &mut Q,
+    task_ids: &[u32],
+) -> Result<HashMap<u32, Vec<String>>> {
+    let mut tags: HashMap<u32, Vec<String>> = HashMap::new();
+
+    for chunk in task_ids.chunks(TASKS_PER_QUERY) {
+        let placeholders: Vec<String> = (0..chunk.len()).map(|i| format!(":id{}", i)).collect();
+        let params: Vec<(String, Value)> = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (format!("id{}", i), Value::from(id)))
+            .collect();
+
+        conn.exec_map(
+            format!(
+                "SELECT task_tags.task_id, tags.name FROM task_tags
+                 JOIN tags ON tags.id = task_tags.tag_id
+                 WHERE task_tags.task_id IN ({})
+                 ORDER BY tags.name",
+                placeholders.join(", ")
+            ),
+            Params::from(params),
+            |(task_id, name): (u32, String)| tags.entry(task_id).or_default().push(name),
+        )?;
+    }
+
+    Ok(tags)
+}
+
+// Replace a task's tags with `names`, creating any the user doesn't have yet
+pub fn set_for_task<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    task_id: u32,
+    names: &[String],
+) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM task_tags WHERE task_id = :task_id",
+        params! {
+            "task_id" => task_id,
+        },
+    )?;
+
+    for name in names {
+        let name = name.trim();
+        conn.exec_drop(
+            "INSERT INTO tags (user_id, name) VALUES (:user_id, :name)
+             ON DUPLICATE KEY UPDATE name = name",
+            params! {
+                "user_id" => user_id,
+                "name" => name,
+            },
+        )?;
+        conn.exec_drop(
+            "INSERT INTO task_tags (task_id, tag_id)
+             SELECT :task_id, id FROM tags WHERE user_id = :user_id AND name = :name
+             ON DUPLICATE KEY UPDATE task_id = task_id",
+            params! {
+                "task_id" => task_id,
+                "user_id" => user_id,
+                "name" => name,
+            },
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,715 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::{Priority, Task, TaskPatch};
+use crate::repository::tags;
+
+const COLUMNS: &str = "id, external_id, description, is_completed, list_id, parent_id, \
+                       priority, start_date, due_date, created_at, deleted_at";
+
+type TaskRow = (
+    u32,
+    Option<String>,
+    String,
+    bool,
+    Option<u32>,
+    Option<u32>,
+    u8,
+    Option<NaiveDateTime>,
+    Option<NaiveDateTime>,
+    NaiveDateTime,
+    Option<NaiveDateTime>,
+);
+
+fn from_row(
+    (
+        id,
+        external_id,
+        description,
+        is_completed,
+        list_id,
+        parent_id,
+        priority,
+        start_date,
+        due_date,
+        created_at,
+        deleted_at,
+    ): TaskRow,
+) -> Task {
+    Task {
+        id: Some(id),
+        external_id,
+        description,
+        is_completed,
+        list_id,
+        parent_id,
+        tags: Vec::new(),
+        priority: Priority::from_u8(priority),
+        start_date: start_date.map(|start_date| start_date.and_utc()),
+        due_date: due_date.map(|due_date| due_date.and_utc()),
+        created_at: Some(created_at.and_utc()),
+        deleted_at: deleted_at.map(|deleted_at| deleted_at.and_utc()),
+    }
+}
+
+// Fill in the tags of tasks read by `from_row`
+fn with_tags<Q: Queryable>(conn: &mut Q, mut tasks: Vec<Task>) -> Result<Vec<Task>> {
+    let ids: Vec<u32> = tasks.iter().filter_map(|task| task.id).collect();
+    let mut tags = tags::for_tasks(conn, &ids)?;
+
+    for task in &mut tasks {
+        if let Some(names) = task.id.and_then(|id| tags.remove(&id)) {
+            task.tags = names;
+        }
+    }
+
+    Ok(tasks)
+}
+
+fn one_with_tags<Q: Queryable>(conn: &mut Q, task: Option<Task>) -> Result<Option<Task>> {
+    Ok(with_tags(conn, task.into_iter().collect())?.pop())
+}
+
+// Columns the task list can be ordered by
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TaskSort {
+    #[default]
+    Id,
+    CreatedAt,
+    StartDate,
+    DueDate,
+    Priority,
+    Description,
+    Completed,
+}
+
+impl TaskSort {
+    fn column(self) -> &'static str {
+        match self {
+            TaskSort::Id => "id",
+            TaskSort::CreatedAt => "created_at",
+            TaskSort::StartDate => "start_date",
+            TaskSort::DueDate => "due_date",
+            TaskSort::Priority => "priority",
+            TaskSort::Description => "description",
+            TaskSort::Completed => "is_completed",
+        }
+    }
+}
+
+// Which tasks to list and in what order
+#[derive(Debug, Clone, Default)]
+pub struct TaskQuery {
+    pub completed: Option<bool>,
+    pub list_id: Option<u32>,
+    // Name of a tag the tasks must have
+    pub tag: Option<String>,
+    // Case-insensitive substring of the description
+    pub search: Option<String>,
+    pub priority: Option<Priority>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub due_after: Option<DateTime<Utc>>,
+    // Open tasks whose due date has passed, or with false everything else
+    pub overdue: Option<bool>,
+    // Open tasks whose start date, if any, has arrived, or with false
+    // everything else
+    pub available: Option<bool>,
+    pub sort: TaskSort,
+    pub descending: bool,
+    // The moment `overdue` and `available` are judged at
+    pub now: DateTime<Utc>,
+}
+
+impl TaskQuery {
+    // WHERE clause for one user's tasks outside the trash and its bound
+    // parameters
+    fn filter(&self, user_id: u32) -> (String, Vec<(String, Value)>) {
+        let mut conditions = vec!["user_id = :user_id", "deleted_at IS NULL"];
+        let mut params = vec![("user_id".to_string(), Value::from(user_id))];
+
+        if let Some(completed) = self.completed {
+            conditions.push("is_completed = :completed");
+            params.push(("completed".to_string(), Value::from(completed)));
+        }
+        if let Some(list_id) = self.list_id {
+            conditions.push("list_id = :list_id");
+            params.push(("list_id".to_string(), Value::from(list_id)));
+        }
+        if let Some(tag) = &self.tag {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM task_tags JOIN tags ON tags.id = task_tags.tag_id
+                 WHERE task_tags.task_id = tasks.id AND tags.name = :tag)",
+            );
+            params.push(("tag".to_string(), Value::from(tag.trim())));
+        }
+        if let Some(search) = &self.search {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            conditions.push("LOWER(description) LIKE LOWER(:search)");
+            params.push(("search".to_string(), Value::from(format!("%{}%", escaped))));
+        }
+        if let Some(priority) = self.priority {
+            conditions.push("priority = :priority");
+            params.push(("priority".to_string(), Value::from(priority.as_u8())));
+        }
+        if let Some(due_before) = self.due_before {
+            conditions.push("due_date < :due_before");
+            params.push((
+                "due_before".to_string(),
+                Value::from(due_before.naive_utc()),
+            ));
+        }
+        if let Some(due_after) = self.due_after {
+            conditions.push("due_date > :due_after");
+            params.push(("due_after".to_string(), Value::from(due_after.naive_utc())));
+        }
+        if let Some(overdue) = self.overdue {
+            conditions.push(if overdue {
+                "(is_completed = false AND due_date < :now)"
+            } else {
+                "NOT (is_completed = false AND due_date IS NOT NULL AND due_date < :now)"
+            });
+        }
+        if let Some(available) = self.available {
+            conditions.push(if available {
+                "(is_completed = false AND (start_date IS NULL OR start_date <= :now))"
+            } else {
+                "NOT (is_completed = false AND (start_date IS NULL OR start_date <= :now))"
+            });
+        }
+        if self.overdue.is_some() || self.available.is_some() {
+            params.push(("now".to_string(), Value::from(self.now.naive_utc())));
+        }
+
+        (format!("WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+// All of a user's tasks outside the trash
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Task>> {
+    let tasks = conn.exec_map(
+        format!(
+            "SELECT {} FROM tasks WHERE user_id = :user_id AND deleted_at IS NULL ORDER BY id",
+            COLUMNS
+        ),
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )?;
+
+    with_tags(conn, tasks)
+}
+
+// One page of the tasks matching `query`. Ties are broken by id so pages
+// don't overlap.
+pub fn search<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    query: &TaskQuery,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<Task>> {
+    let (filter, mut params) = query.filter(user_id);
+    let order = if query.descending { "DESC" } else { "ASC" };
+    params.push(("limit".to_string(), Value::from(limit)));
+    params.push(("offset".to_string(), Value::from(offset)));
+
+    let tasks = conn.exec_map(
+        format!(
+            "SELECT {} FROM tasks {} ORDER BY {} {}, id {} LIMIT :limit OFFSET :offset",
+            COLUMNS,
+            filter,
+            query.sort.column(),
+            order,
+            order
+        ),
+        Params::from(params),
+        from_row,
+    )?;
+
+    with_tags(conn, tasks)
+}
+
+// Number of tasks matching `query`
+pub fn count<Q: Queryable>(conn: &mut Q, user_id: u32, query: &TaskQuery) -> Result<u64> {
+    let (filter, params) = query.filter(user_id);
+
+    conn.exec_first(
+        format!("SELECT COUNT(*) FROM tasks {}", filter),
+        Params::from(params),
+    )
+    .map(Option::unwrap_or_default)
+}
+
+// Open tasks past their due date across all users, per priority
+pub fn count_overdue<Q: Queryable>(conn: &mut Q, now: NaiveDateTime) -> Result<Vec<(u8, u64)>> {
+    conn.exec(
+        "SELECT priority, COUNT(*) FROM tasks
+         WHERE deleted_at IS NULL AND is_completed = false AND due_date < :now
+         GROUP BY priority ORDER BY priority",
+        params! {
+            "now" => now,
+        },
+    )
+}
+
+// Ids of every task matching `query`, ignoring its order
+pub fn matching_ids<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    query: &TaskQuery,
+) -> Result<Vec<u32>> {
+    let (filter, params) = query.filter(user_id);
+
+    conn.exec(
+        format!("SELECT id FROM tasks {} ORDER BY id", filter),
+        Params::from(params),
+    )
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<Task>> {
+    let task = conn
+        .exec_first(
+            format!(
+                "SELECT {} FROM tasks WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL",
+                COLUMNS
+            ),
+            params! {
+                "id" => id,
+                "user_id" => user_id,
+            },
+        )
+        .map(|row| row.map(from_row))?;
+
+    one_with_tags(conn, task)
+}
+
+pub fn find_by_external_id<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    external_id: &str,
+) -> Result<Option<Task>> {
+    let task = conn
+        .exec_first(
+            format!(
+                "SELECT {} FROM tasks
+                 WHERE external_id = :external_id AND user_id = :user_id AND deleted_at IS NULL",
+                COLUMNS
+            ),
+            params! {
+                "external_id" => external_id,
+                "user_id" => user_id,
+            },
+        )
+        .map(|row| row.map(from_row))?;
+
+    one_with_tags(conn, task)
+}
+
+// The direct subtasks of a task, oldest first
+pub fn children<Q: Queryable>(conn: &mut Q, user_id: u32, parent_id: u32) -> Result<Vec<Task>> {
+    let tasks = conn.exec_map(
+        format!(
+            "SELECT {} FROM tasks
+             WHERE parent_id = :parent_id AND user_id = :user_id AND deleted_at IS NULL
+             ORDER BY id",
+            COLUMNS
+        ),
+        params! {
+            "parent_id" => parent_id,
+            "user_id" => user_id,
+        },
+        from_row,
+    )?;
+
+    with_tags(conn, tasks)
+}
+
+// The parent of a task: None if the task doesn't exist or is in the trash,
+// Some(None) for a top-level task
+pub fn parent_id<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<Option<u32>>> {
+    conn.exec_first(
+        "SELECT parent_id FROM tasks WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
+
+// Move a task's subtasks up to its own parent, e.g. before deleting it
+pub fn reparent_children<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    let Some(parent_id) = parent_id(conn, user_id, id)? else {
+        return Ok(());
+    };
+
+    conn.exec_drop(
+        "UPDATE tasks SET parent_id = :parent_id WHERE parent_id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "parent_id" => parent_id,
+        },
+    )
+}
+
+// Insert a task and return its new id. Tasks without a creation time, e.g.
+// imported ones, are stamped with `now`. Tasks without an external id get
+// one derived from their id, so every exported task can be matched up again
+// on import.
+pub fn insert<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    task: &Task,
+    now: DateTime<Utc>,
+) -> Result<u32> {
+    let result = conn.exec_iter(
+        "INSERT INTO tasks
+            (user_id, external_id, description, is_completed, list_id, parent_id, priority,
+             start_date, due_date, created_at)
+         VALUES
+            (:user_id, :external_id, :description, :is_completed, :list_id, :parent_id,
+             :priority, :start_date, :due_date, :created_at)",
+        params! {
+            "user_id" => user_id,
+            "external_id" => &task.external_id,
+            "description" => &task.description,
+            "is_completed" => task.is_completed,
+            "list_id" => task.list_id,
+            "parent_id" => task.parent_id,
+            "priority" => task.priority.as_u8(),
+            "start_date" => task.start_date.map(|start_date| start_date.naive_utc()),
+            "due_date" => task.due_date.map(|due_date| due_date.naive_utc()),
+            "created_at" => task.created_at.unwrap_or(now).naive_utc(),
+        },
+    )?;
+
+    let id = result.last_insert_id().unwrap_or_default() as u32;
+    drop(result);
+
+    if task.external_id.is_none() {
+        conn.exec_drop(
+            "UPDATE tasks SET external_id = CONCAT('task-', id) WHERE id = :id",
+            params! {
+                "id" => id,
+            },
+        )?;
+    }
+    if !task.tags.is_empty() {
+        tags::set_for_task(conn, user_id, id, &task.tags)?;
+    }
+    Ok(id)
+}
+
+// Overwrite a task and its tags, returning whether it exists. Leaving out the
+// external id keeps the stored one.
+pub fn update<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32, task: &Task) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE tasks SET
+            external_id = COALESCE(:external_id, external_id),
+            description = :description,
+            is_completed = :is_completed,
+            list_id = :list_id,
+            parent_id = :parent_id,
+            priority = :priority,
+            start_date = :start_date,
+            due_date = :due_date
+         WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "external_id" => &task.external_id,
+            "description" => &task.description,
+            "is_completed" => task.is_completed,
+            "list_id" => task.list_id,
+            "parent_id" => task.parent_id,
+            "priority" => task.priority.as_u8(),
+            "start_date" => task.start_date.map(|start_date| start_date.naive_utc()),
+            "due_date" => task.due_date.map(|due_date| due_date.naive_utc()),
+        },
+    )?;
+
+    let found = result.affected_rows() > 0;
+    drop(result);
+
+    if found {
+        tags::set_for_task(conn, user_id, id, &task.tags)?;
+    }
+    Ok(found)
+}
+
+// Change only the fields set in `patch`, returning whether the task exists
+pub fn patch<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32, patch: &TaskPatch) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE tasks SET
+            external_id = COALESCE(:external_id, external_id),
+            description = COALESCE(:description, description),
+            is_completed = COALESCE(:is_completed, is_completed),
+            list_id = IF(:set_list_id, :list_id, list_id),
+            parent_id = IF(:set_parent_id, :parent_id, parent_id),
+            priority = COALESCE(:priority, priority),
+            start_date = IF(:set_start_date, :start_date, start_date),
+            due_date = IF(:set_due_date, :due_date, due_date)
+         WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "external_id" => &patch.external_id,
+            "description" => &patch.description,
+            "is_completed" => patch.is_completed,
+            "set_list_id" => patch.list_id.is_some(),
+            "list_id" => patch.list_id.flatten(),
+            "set_parent_id" => patch.parent_id.is_some(),
+            "parent_id" => patch.parent_id.flatten(),
+            "priority" => patch.priority.map(Priority::as_u8),
+            "set_start_date" => patch.start_date.is_some(),
+            "start_date" => patch.start_date.flatten().map(|start_date| start_date.naive_utc()),
+            "set_due_date" => patch.due_date.is_some(),
+            "due_date" => patch.due_date.flatten().map(|due_date| due_date.naive_utc()),
+        },
+    )?;
+
+    let found = result.affected_rows() > 0;
+    drop(result);
+
+    match &patch.tags {
+        Some(names) if found => tags::set_for_task(conn, user_id, id, names)?,
+        _ => {}
+    }
+    Ok(found)
+}
+
+// Mark an open task completed, returning whether it was open
+pub fn complete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE tasks SET is_completed = true
+         WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL AND is_completed = false",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// Move a task's start and due dates by `days`, returning whether it has
+// either
+pub fn shift_dates<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32, days: i64) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE tasks SET
+            start_date = start_date + INTERVAL :days DAY,
+            due_date = due_date + INTERVAL :days DAY
+         WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL
+            AND (start_date IS NOT NULL OR due_date IS NOT NULL)",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "days" => days,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// Set a task's due date, bringing a later start date forward to it.
+// Returns whether anything changed; a task already due then is left alone,
+// as CLIENT_FOUND_ROWS would otherwise count it.
+pub fn set_due_date<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    id: u32,
+    due_date: NaiveDateTime,
+) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE tasks SET
+            start_date = IF(start_date > :due_date, :due_date, start_date),
+            due_date = :due_date
+         WHERE id = :id AND user_id = :user_id AND deleted_at IS NULL
+            AND NOT (due_date <=> :due_date)",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "due_date" => due_date,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+// A task and all of its subtasks, found level by level. `deleted_at` picks
+// tasks outside the trash (None) or those trashed at that moment.
+fn subtree_ids<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    id: u32,
+    deleted_at: Option<NaiveDateTime>,
+) -> Result<Vec<u32>> {
+    let mut ids = vec![id];
+    let mut next = 0;
+
+    while let Some(&parent_id) = ids.get(next) {
+        let children: Vec<u32> = conn.exec(
+            "SELECT id FROM tasks
+             WHERE parent_id = :parent_id AND user_id = :user_id AND deleted_at <=> :deleted_at",
+            params! {
+                "parent_id" => parent_id,
+                "user_id" => user_id,
+                "deleted_at" => deleted_at,
+            },
+        )?;
+        ids.extend(children);
+        next += 1;
+    }
+
+    Ok(ids)
+}
+
+fn set_deleted_at<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    ids: &[u32],
+    deleted_at: Option<NaiveDateTime>,
+) -> Result<()> {
+    conn.exec_batch(
+        "UPDATE tasks SET deleted_at = :deleted_at WHERE id = :id AND user_id = :user_id",
+        ids.iter().map(|id| {
+            params! {
+                "id" => id,
+                "user_id" => user_id,
+                "deleted_at" => deleted_at,
+            }
+        }),
+    )
+}
+
+// Move a task and its subtasks to the trash as of `now`, returning the ids
+// moved
+pub fn delete<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    id: u32,
+    now: NaiveDateTime,
+) -> Result<Vec<u32>> {
+    if parent_id(conn, user_id, id)?.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let ids = subtree_ids(conn, user_id, id, None)?;
+    set_deleted_at(conn, user_id, &ids, Some(now))?;
+    Ok(ids)
+}
+
+// One page of a user's trash, most recently deleted first
+pub fn trash<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<Task>> {
+    let tasks = conn.exec_map(
+        format!(
+            "SELECT {} FROM tasks WHERE user_id = :user_id AND deleted_at IS NOT NULL
+             ORDER BY deleted_at DESC, id DESC LIMIT :limit OFFSET :offset",
+            COLUMNS
+        ),
+        params! {
+            "user_id" => user_id,
+            "limit" => limit,
+            "offset" => offset,
+        },
+        from_row,
+    )?;
+
+    with_tags(conn, tasks)
+}
+
+pub fn count_trash<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<u64> {
+    conn.exec_first(
+        "SELECT COUNT(*) FROM tasks WHERE user_id = :user_id AND deleted_at IS NOT NULL",
+        params! {
+            "user_id" => user_id,
+        },
+    )
+    .map(Option::unwrap_or_default)
+}
+
+// Take a task out of the trash along with the subtasks trashed with it,
+// returning whether it was there. A task whose parent is still in the
+// trash comes back at the top level.
+pub fn restore<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<bool> {
+    let trashed: Option<(Option<u32>, NaiveDateTime)> = conn.exec_first(
+        "SELECT parent_id, deleted_at FROM tasks
+         WHERE id = :id AND user_id = :user_id AND deleted_at IS NOT NULL",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )?;
+    let Some((parent, deleted_at)) = trashed else {
+        return Ok(false);
+    };
+
+    let ids = subtree_ids(conn, user_id, id, Some(deleted_at))?;
+    set_deleted_at(conn, user_id, &ids, None)?;
+
+    if let Some(parent) = parent {
+        if parent_id(conn, user_id, parent)?.is_none() {
+            conn.exec_drop(
+                "UPDATE tasks SET parent_id = NULL WHERE id = :id AND user_id = :user_id",
+                params! {
+                    "id" => id,
+                    "user_id" => user_id,
+                },
+            )?;
+        }
+    }
+
+    Ok(true)
+}
+
+// Bring back a trashed task with this external id, so that importing it
+// again updates it rather than clashing with it
+pub fn restore_by_external_id<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    external_id: &str,
+) -> Result<()> {
+    let id: Option<u32> = conn.exec_first(
+        "SELECT id FROM tasks
+         WHERE external_id = :external_id AND user_id = :user_id AND deleted_at IS NOT NULL",
+        params! {
+            "external_id" => external_id,
+            "user_id" => user_id,
+        },
+    )?;
+
+    match id {
+        Some(id) => restore(conn, user_id, id).map(drop),
+        None => Ok(()),
+    }
+}
+
+// Permanently delete everything in a user's trash
+pub fn empty_trash<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM tasks WHERE user_id = :user_id AND deleted_at IS NOT NULL",
+        params! {
+            "user_id" => user_id,
+        },
+    )
+}
+
+// Permanently delete every user's tasks trashed before `cutoff`
+pub fn purge_trashed_before<Q: Queryable>(conn: &mut Q, cutoff: NaiveDateTime) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM tasks WHERE deleted_at < :cutoff",
+        params! {
+            "cutoff" => cutoff,
+        },
+    )
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::User;
+
+type UserRow = (u32, String, NaiveDateTime);
+
+fn from_row((id, email, created_at): UserRow) -> User {
+    User {
+        id,
+        email,
+        created_at: created_at.and_utc(),
+    }
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, id: u32) -> Result<Option<User>> {
+    conn.exec_first(
+        "SELECT id, email, created_at FROM users WHERE id = :id",
+        params! {
+            "id" => id,
+        },
+    )
+    .map(|row| row.map(from_row))
+}
+
+pub fn find_by_email<Q: Queryable>(conn: &mut Q, email: &str) -> Result<Option<User>> {
+    conn.exec_first(
+        "SELECT id, email, created_at FROM users WHERE email = :email",
+        params! {
+            "email" => email,
+        },
+    )
+    .map(|row| row.map(from_row))
+}
+
+// A user and their password hash, for checking a login
+pub fn credentials<Q: Queryable>(conn: &mut Q, email: &str) -> Result<Option<(User, String)>> {
+    conn.exec_first(
+        "SELECT id, email, created_at, password_hash FROM users WHERE email = :email",
+        params! {
+            "email" => email,
+        },
+    )
+    .map(|row| {
+        row.map(|(id, email, created_at, password_hash)| {
+            (from_row((id, email, created_at)), password_hash)
+        })
+    })
+}
+
+// Insert a user registered at `created_at` and return it
+pub fn insert<Q: Queryable>(
+    conn: &mut Q,
+    email: &str,
+    password_hash: &str,
+    created_at: DateTime<Utc>,
+) -> Result<User> {
+    let result = conn.exec_iter(
+        "INSERT INTO users (email, password_hash, created_at)
+         VALUES (:email, :password_hash, :created_at)",
+        params! {
+            "email" => email,
+            "password_hash" => password_hash,
+            "created_at" => created_at.naive_utc(),
+        },
+    )?;
+
+    Ok(User {
+        id: result.last_insert_id().unwrap_or_default() as u32,
+        email: email.to_string(),
+        created_at,
+    })
+}
+
+// Hand tasks and schedules created before accounts existed to `user_id`,
+// returning how many of each were handed over
+pub fn claim_unowned<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<(u64, u64)> {
+    let mut claimed = [0; 2];
+    for (table, count) in ["tasks", "schedules"].into_iter().zip(&mut claimed) {
+        *count = conn
+            .exec_iter(
+                format!(
+                    "UPDATE {} SET user_id = :user_id WHERE user_id IS NULL",
+                    table
+                ),
+                params! {
+                    "user_id" => user_id,
+                },
+            )?
+            .affected_rows();
+    }
+
+    Ok((claimed[0], claimed[1]))
+}
@@ -0,0 +1,113 @@
+use chrono::{NaiveDateTime, Utc};
+use mysql::prelude::*;
+use mysql::*;
+
+use crate::models::Webhook;
+
+type WebhookRow = (u32, String, Option<u32>, NaiveDateTime);
+
+fn from_row((id, url, payload_version, created_at): WebhookRow) -> Webhook {
+    Webhook {
+        id: Some(id),
+        url,
+        payload_version,
+        created_at: Some(created_at.and_utc()),
+    }
+}
+
+pub fn list<Q: Queryable>(conn: &mut Q, user_id: u32) -> Result<Vec<Webhook>> {
+    conn.exec_map(
+        "SELECT id, url, payload_version, created_at FROM webhooks
+         WHERE user_id = :user_id ORDER BY id",
+        params! {
+            "user_id" => user_id,
+        },
+        from_row,
+    )
+}
+
+// URL, signing secret and pinned payload version of each of a user's hooks
+pub fn targets<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+) -> Result<Vec<(String, String, Option<u32>)>> {
+    conn.exec(
+        "SELECT url, secret, payload_version FROM webhooks WHERE user_id = :user_id ORDER BY id",
+        params! {
+            "user_id" => user_id,
+        },
+    )
+}
+
+// Store a hook with its signing secret, returning the hook as saved
+pub fn insert<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    webhook: &Webhook,
+    secret: &str,
+) -> Result<Webhook> {
+    let created_at = webhook.created_at.unwrap_or_else(Utc::now);
+    let url = webhook.url.trim();
+    let result = conn.exec_iter(
+        "INSERT INTO webhooks (user_id, url, secret, payload_version, created_at)
+         VALUES (:user_id, :url, :secret, :payload_version, :created_at)",
+        params! {
+            "user_id" => user_id,
+            "url" => url,
+            "secret" => secret,
+            "payload_version" => webhook.payload_version,
+            "created_at" => created_at.naive_utc(),
+        },
+    )?;
+
+    Ok(Webhook {
+        id: Some(result.last_insert_id().unwrap_or_default() as u32),
+        url: url.to_string(),
+        payload_version: webhook.payload_version,
+        created_at: Some(created_at),
+    })
+}
+
+// Change a hook's URL and pinned version, keeping its secret. False if the
+// user has no such hook.
+pub fn update<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    id: u32,
+    webhook: &Webhook,
+) -> Result<bool> {
+    let result = conn.exec_iter(
+        "UPDATE webhooks SET url = :url, payload_version = :payload_version
+         WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+            "url" => webhook.url.trim(),
+            "payload_version" => webhook.payload_version,
+        },
+    )?;
+
+    Ok(result.affected_rows() > 0)
+}
+
+pub fn find<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<Option<Webhook>> {
+    conn.exec_first(
+        "SELECT id, url, payload_version, created_at FROM webhooks
+         WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+    .map(|row| row.map(from_row))
+}
+
+pub fn delete<Q: Queryable>(conn: &mut Q, user_id: u32, id: u32) -> Result<()> {
+    conn.exec_drop(
+        "DELETE FROM webhooks WHERE id = :id AND user_id = :user_id",
+        params! {
+            "id" => id,
+            "user_id" => user_id,
+        },
+    )
+}
@@ -0,0 +1,578 @@
+use chrono::{DateTime, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use cron::Schedule as CronSchedule;
+use mysql::prelude::Queryable;
+use mysql::*;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::calendar::{self, is_business_day};
+use crate::models::{HolidayPolicy, RunStatus, Schedule, ScheduleException, ScheduleRun, Task};
+use crate::repository;
+
+// Upper bound on cron occurrences inspected when looking for a business day
+const MAX_LOOKAHEAD: usize = 1000;
+
+// Parse a cron expression, accepting the five-field form without seconds
+pub fn parse_cron(expr: &str) -> Result<CronSchedule, String> {
+    let expr = expr.trim();
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {}", expr)
+    } else {
+        expr.to_string()
+    };
+
+    CronSchedule::from_str(&expr).map_err(|e| format!("Invalid cron expression: {}", e))
+}
+
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>()
+        .map_err(|_| format!("Unknown timezone: {}", name))
+}
+
+// Apply the holiday policy to one occurrence; `None` means it is skipped
+fn resolve(
+    at: DateTime<Tz>,
+    policy: HolidayPolicy,
+    calendar: &HashSet<NaiveDate>,
+) -> Option<DateTime<Tz>> {
+    if policy == HolidayPolicy::Run || is_business_day(at.date_naive(), calendar) {
+        return Some(at);
+    }
+
+    match policy {
+        HolidayPolicy::NextBusinessDay => {
+            let mut date = at.date_naive();
+            while !is_business_day(date, calendar) {
+                date = date.checked_add_days(Days::new(1))?;
+            }
+            at.timezone()
+                .from_local_datetime(&date.and_time(at.time()))
+                .earliest()
+        }
+        _ => None,
+    }
+}
+
+// When clocks go back an hour, the local times in that hour happen twice
+// and cron fires at both; only the first counts as the occurrence
+fn first_of_repeated(at: &DateTime<Tz>) -> bool {
+    at.timezone()
+        .from_local_datetime(&at.naive_local())
+        .earliest()
+        .is_some_and(|first| first == *at)
+}
+
+// Whether an exception takes the cron's occurrence at `at` out of the rule
+fn excepted(exceptions: &[ScheduleException], at: DateTime<Utc>) -> bool {
+    exceptions.iter().any(|e| e.recurrence_id == at)
+}
+
+// Whether an occurrence was moved to `at`
+fn moved_to(exceptions: &[ScheduleException], at: DateTime<Utc>) -> bool {
+    exceptions.iter().any(|e| e.moved_to == Some(at))
+}
+
+// Next firing time strictly after `after`, evaluated in the schedule's
+// timezone. Skipped and moved occurrences leave the rule, and moved ones run
+// at their new time whatever the holiday policy.
+fn next_run(
+    cron: &CronSchedule,
+    tz: Tz,
+    policy: HolidayPolicy,
+    calendar: &HashSet<NaiveDate>,
+    exceptions: &[ScheduleException],
+    after: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let from_rule = cron
+        .after(&after.with_timezone(&tz))
+        .take(MAX_LOOKAHEAD)
+        .filter(first_of_repeated)
+        .filter(|at| !excepted(exceptions, at.with_timezone(&Utc)))
+        .find_map(|at| resolve(at, policy, calendar))
+        .map(|at| at.with_timezone(&Utc));
+    let moved = exceptions
+        .iter()
+        .filter_map(|e| e.moved_to)
+        .filter(|&at| at > after);
+
+    from_rule.into_iter().chain(moved).min()
+}
+
+// The time the schedule's cron gives its first occurrence on `date`, in the
+// schedule's timezone, if it runs that day at all
+pub fn occurrence_on(
+    schedule: &Schedule,
+    date: NaiveDate,
+) -> Result<Option<DateTime<Utc>>, String> {
+    let cron = parse_cron(&schedule.cron)?;
+    let tz = parse_timezone(&schedule.timezone)?;
+    let Some(midnight) = tz
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+    else {
+        return Ok(None);
+    };
+
+    // `after` excludes its argument, so start just before midnight
+    Ok(cron
+        .after(&(midnight - Duration::seconds(1)))
+        .next()
+        .filter(|at| at.date_naive() == date)
+        .map(|at| at.with_timezone(&Utc)))
+}
+
+// Validate a schedule payload and compute its first run after `now`
+pub fn validate(
+    schedule: &Schedule,
+    calendar: &HashSet<NaiveDate>,
+    exceptions: &[ScheduleException],
+    now: DateTime<Utc>,
+) -> Result<NaiveDateTime, String> {
+    let cron = parse_cron(&schedule.cron)?;
+    let tz = parse_timezone(&schedule.timezone)?;
+
+    next_run(&cron, tz, schedule.on_holiday, calendar, exceptions, now)
+        .map(|at| at.naive_utc())
+        .ok_or_else(|| "Cron expression never fires".to_string())
+}
+
+// Create the task for every schedule due at `now` and move it to its next
+// run
+pub fn run_due(pool: &Pool, now: DateTime<Utc>) -> Result<()> {
+    let mut conn = pool.get_conn()?;
+    let mut calendars: HashMap<u32, HashSet<NaiveDate>> = HashMap::new();
+    let no_holidays = HashSet::new();
+
+    for schedule in repository::schedules::list_due(&mut conn, now.naive_utc())? {
+        let id = schedule.id.unwrap();
+
+        // Rows are validated on write, so these only fail if the data was
+        // edited by hand; leave such schedules alone rather than spin on them.
+        let (cron, tz) = match (
+            parse_cron(&schedule.cron),
+            parse_timezone(&schedule.timezone),
+        ) {
+            (Ok(cron), Ok(tz)) => (cron, tz),
+            _ => {
+                tracing::warn!(
+                    schedule_id = id,
+                    "Skipping schedule with invalid cron or timezone"
+                );
+                continue;
+            }
+        };
+
+        // Each schedule follows its owner's holidays
+        let calendar: &HashSet<NaiveDate> = match schedule.user_id {
+            Some(user_id) => match calendars.entry(user_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(calendar::load(&mut conn, user_id)?),
+            },
+            None => &no_holidays,
+        };
+
+        // The calendar and exceptions may have changed since next_run_at was
+        // computed, so check the occurrence again before materializing it.
+        let exceptions = repository::schedules::exceptions(&mut conn, id)?;
+        let due_utc = schedule.next_run_at.unwrap();
+        let due_at = due_utc.with_timezone(&tz);
+        let resolved = if moved_to(&exceptions, due_utc) {
+            Some(due_at)
+        } else if excepted(&exceptions, due_utc) {
+            None
+        } else {
+            resolve(due_at, schedule.on_holiday, calendar)
+        };
+
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        // Schedules left over from before accounts existed have nobody to
+        // create tasks for until a user claims them
+        match schedule.user_id {
+            Some(user_id) if resolved == Some(due_at) => {
+                let mut task = Task::new(schedule.description.as_str());
+                task.created_at = Some(now);
+                let task_id = repository::tasks::insert(&mut tx, user_id, &task, now)?;
+                repository::schedules::record_run(&mut tx, id, due_utc.naive_utc(), Some(task_id))?;
+            }
+            Some(_) if resolved.is_none() => {
+                repository::schedules::record_run(&mut tx, id, due_utc.naive_utc(), None)?;
+            }
+            _ => {}
+        }
+
+        // A moved occurrence is kept; otherwise missed runs (e.g. while the
+        // server was down) collapse into the one task created above.
+        let next = match resolved {
+            Some(at) if at > due_at => Some(at.with_timezone(&Utc)),
+            _ => next_run(&cron, tz, schedule.on_holiday, calendar, &exceptions, now),
+        };
+        repository::schedules::delete_past_exceptions(&mut tx, id, now.naive_utc())?;
+
+        match next {
+            Some(at) => repository::schedules::set_next_run(&mut tx, id, at.naive_utc())?,
+            None => repository::schedules::delete_finished(&mut tx, id)?,
+        }
+
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// A schedule's runs since `since`, with each task's completion. Days are
+// judged in the schedule's timezone, so an open task only counts as missed
+// once the day it ran on is over there.
+pub fn history<Q: Queryable>(
+    conn: &mut Q,
+    schedule: &Schedule,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<Vec<ScheduleRun>> {
+    let tz = parse_timezone(&schedule.timezone).unwrap_or(Tz::UTC);
+    let today = now.with_timezone(&tz).date_naive();
+    let runs = repository::schedules::runs_since(
+        conn,
+        schedule.id.unwrap_or_default(),
+        since.naive_utc(),
+    )?;
+
+    Ok(runs
+        .into_iter()
+        .map(|(ran_at, task_id, completed)| {
+            let ran_at = ran_at.and_utc();
+            let date = ran_at.with_timezone(&tz).date_naive();
+            let status = match task_id {
+                None => RunStatus::Skipped,
+                Some(_) if completed => RunStatus::Done,
+                Some(_) if date < today => RunStatus::Missed,
+                Some(_) => RunStatus::Open,
+            };
+            ScheduleRun {
+                date,
+                ran_at,
+                task_id,
+                status,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(at: &str) -> DateTime<Utc> {
+        at.parse().expect("valid RFC 3339 time")
+    }
+
+    fn date(at: &str) -> NaiveDate {
+        at.parse().expect("valid date")
+    }
+
+    fn schedule(cron: &str, timezone: &str) -> Schedule {
+        Schedule {
+            id: None,
+            user_id: None,
+            description: "Water the plants".to_string(),
+            cron: cron.to_string(),
+            timezone: timezone.to_string(),
+            on_holiday: HolidayPolicy::Run,
+            next_run_at: None,
+        }
+    }
+
+    // Runs of `cron` in `timezone` after `after`, following the holiday
+    // policy and exceptions
+    fn runs(
+        cron: &str,
+        timezone: &str,
+        policy: HolidayPolicy,
+        calendar: &HashSet<NaiveDate>,
+        exceptions: &[ScheduleException],
+        after: &str,
+        count: usize,
+    ) -> Vec<DateTime<Utc>> {
+        let cron = parse_cron(cron).unwrap();
+        let tz = parse_timezone(timezone).unwrap();
+        let mut runs = Vec::new();
+        let mut after = utc(after);
+        while runs.len() < count {
+            let Some(at) = next_run(&cron, tz, policy, calendar, exceptions, after) else {
+                break;
+            };
+            runs.push(at);
+            after = at;
+        }
+        runs
+    }
+
+    #[test]
+    fn five_field_cron_gets_a_seconds_field() {
+        assert!(parse_cron("0 9 * * 1-5").is_ok());
+        assert!(parse_cron("0 0 9 * * 1-5").is_ok());
+        assert!(parse_cron("every day").is_err());
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn day_31_skips_shorter_months() {
+        let none = HashSet::new();
+        let runs = runs(
+            "0 9 31 * *",
+            "UTC",
+            HolidayPolicy::Run,
+            &none,
+            &[],
+            "2024-01-31T10:00:00Z",
+            3,
+        );
+        assert_eq!(
+            runs,
+            [
+                utc("2024-03-31T09:00:00Z"),
+                utc("2024-05-31T09:00:00Z"),
+                utc("2024-07-31T09:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn february_29_waits_for_a_leap_year() {
+        let none = HashSet::new();
+        let runs = runs(
+            "0 0 29 2 *",
+            "UTC",
+            HolidayPolicy::Run,
+            &none,
+            &[],
+            "2024-03-01T00:00:00Z",
+            1,
+        );
+        assert_eq!(runs, [utc("2028-02-29T00:00:00Z")]);
+    }
+
+    #[test]
+    fn local_time_holds_across_dst_changes() {
+        let none = HashSet::new();
+        let spring = runs(
+            "0 9 * * *",
+            "Europe/Berlin",
+            HolidayPolicy::Run,
+            &none,
+            &[],
+            "2024-03-30T00:00:00Z",
+            2,
+        );
+        assert_eq!(
+            spring,
+            [utc("2024-03-30T08:00:00Z"), utc("2024-03-31T07:00:00Z")]
+        );
+
+        let autumn = runs(
+            "0 9 * * *",
+            "America/New_York",
+            HolidayPolicy::Run,
+            &none,
+            &[],
+            "2024-11-02T00:00:00Z",
+            2,
+        );
+        assert_eq!(
+            autumn,
+            [utc("2024-11-02T13:00:00Z"), utc("2024-11-03T14:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn a_time_skipped_by_spring_forward_does_not_run_that_day() {
+        let none = HashSet::new();
+        let runs = runs(
+            "30 2 * * *",
+            "Europe/Berlin",
+            HolidayPolicy::Run,
+            &none,
+            &[],
+            "2024-03-30T12:00:00Z",
+            1,
+        );
+        assert_eq!(runs, [utc("2024-04-01T00:30:00Z")]);
+    }
+
+    #[test]
+    fn a_time_repeated_by_fall_back_runs_once() {
+        let none = HashSet::new();
+        let runs = runs(
+            "30 2 * * *",
+            "Europe/Berlin",
+            HolidayPolicy::Run,
+            &none,
+            &[],
+            "2024-10-26T12:00:00Z",
+            2,
+        );
+        assert_eq!(
+            runs,
+            [utc("2024-10-27T00:30:00Z"), utc("2024-10-28T01:30:00Z")]
+        );
+    }
+
+    #[test]
+    fn occurrence_on_a_dst_day_is_in_local_time() {
+        let schedule = schedule("0 9 * * *", "Europe/Berlin");
+        assert_eq!(
+            occurrence_on(&schedule, date("2024-03-31")),
+            Ok(Some(utc("2024-03-31T07:00:00Z")))
+        );
+
+        let weekdays = self::schedule("0 9 * * Mon-Fri", "UTC");
+        assert_eq!(occurrence_on(&weekdays, date("2024-06-01")), Ok(None));
+    }
+
+    #[test]
+    fn holidays_run_by_default() {
+        let calendar = HashSet::from([date("2024-12-25")]);
+        let at = utc("2024-12-25T09:00:00Z").with_timezone(&Tz::UTC);
+        assert_eq!(resolve(at, HolidayPolicy::Run, &calendar), Some(at));
+        // Run also keeps weekend occurrences
+        let saturday = utc("2024-12-28T09:00:00Z").with_timezone(&Tz::UTC);
+        assert_eq!(
+            resolve(saturday, HolidayPolicy::Run, &calendar),
+            Some(saturday)
+        );
+    }
+
+    #[test]
+    fn skip_drops_holidays_and_weekends() {
+        let calendar = HashSet::from([date("2024-12-25")]);
+        let holiday = utc("2024-12-25T09:00:00Z").with_timezone(&Tz::UTC);
+        let sunday = utc("2024-12-29T09:00:00Z").with_timezone(&Tz::UTC);
+        let monday = utc("2024-12-30T09:00:00Z").with_timezone(&Tz::UTC);
+        assert_eq!(resolve(holiday, HolidayPolicy::Skip, &calendar), None);
+        assert_eq!(resolve(sunday, HolidayPolicy::Skip, &calendar), None);
+        assert_eq!(
+            resolve(monday, HolidayPolicy::Skip, &calendar),
+            Some(monday)
+        );
+
+        let runs = runs(
+            "0 9 * * *",
+            "UTC",
+            HolidayPolicy::Skip,
+            &calendar,
+            &[],
+            "2024-12-24T10:00:00Z",
+            2,
+        );
+        assert_eq!(
+            runs,
+            [utc("2024-12-26T09:00:00Z"), utc("2024-12-27T09:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn next_business_day_rolls_past_weekends_and_holidays() {
+        // Friday 31 May is a holiday and Monday 3 June too, so the run rolls
+        // over the month end to Tuesday
+        let calendar = HashSet::from([date("2024-05-31"), date("2024-06-03")]);
+        let at = utc("2024-05-31T09:00:00Z").with_timezone(&Tz::UTC);
+        assert_eq!(
+            resolve(at, HolidayPolicy::NextBusinessDay, &calendar),
+            Some(utc("2024-06-04T09:00:00Z").with_timezone(&Tz::UTC))
+        );
+    }
+
+    #[test]
+    fn next_business_day_keeps_the_local_time_across_dst() {
+        // Saturday before the clocks change rolls to Monday, an hour earlier
+        // in UTC
+        let tz = parse_timezone("Europe/Berlin").unwrap();
+        let saturday = utc("2024-03-30T08:00:00Z").with_timezone(&tz);
+        let rolled = resolve(saturday, HolidayPolicy::NextBusinessDay, &HashSet::new());
+        assert_eq!(
+            rolled.map(|at| at.with_timezone(&Utc)),
+            Some(utc("2024-04-01T07:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn rolled_runs_do_not_repeat() {
+        // A weekly Saturday run rolls to Monday, and the next one comes from
+        // the following Saturday
+        let none = HashSet::new();
+        let runs = runs(
+            "0 9 * * Sat",
+            "UTC",
+            HolidayPolicy::NextBusinessDay,
+            &none,
+            &[],
+            "2024-06-01T00:00:00Z",
+            2,
+        );
+        assert_eq!(
+            runs,
+            [utc("2024-06-03T09:00:00Z"), utc("2024-06-10T09:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn skipped_occurrences_leave_the_rule() {
+        let none = HashSet::new();
+        let exceptions = [ScheduleException {
+            recurrence_id: utc("2024-06-04T09:00:00Z"),
+            moved_to: None,
+        }];
+        let runs = runs(
+            "0 9 * * *",
+            "UTC",
+            HolidayPolicy::Run,
+            &none,
+            &exceptions,
+            "2024-06-03T10:00:00Z",
+            1,
+        );
+        assert_eq!(runs, [utc("2024-06-05T09:00:00Z")]);
+    }
+
+    #[test]
+    fn moved_occurrences_run_at_their_new_time_even_on_holidays() {
+        let calendar = HashSet::from([date("2024-06-06")]);
+        let exceptions = [ScheduleException {
+            recurrence_id: utc("2024-06-04T09:00:00Z"),
+            moved_to: Some(utc("2024-06-06T15:00:00Z")),
+        }];
+        let runs = runs(
+            "0 9 * * *",
+            "UTC",
+            HolidayPolicy::Skip,
+            &calendar,
+            &exceptions,
+            "2024-06-03T10:00:00Z",
+            3,
+        );
+        assert_eq!(
+            runs,
+            [
+                utc("2024-06-05T09:00:00Z"),
+                utc("2024-06-06T15:00:00Z"),
+                utc("2024-06-07T09:00:00Z"),
+            ]
+        );
+        assert!(moved_to(&exceptions, utc("2024-06-06T15:00:00Z")));
+        assert!(excepted(&exceptions, utc("2024-06-04T09:00:00Z")));
+    }
+
+    #[test]
+    fn validate_needs_a_run_to_come() {
+        let none = HashSet::new();
+        let now = utc("2024-06-03T10:00:00Z");
+        assert_eq!(
+            validate(&schedule("0 9 * * *", "UTC"), &none, &[], now),
+            Ok(utc("2024-06-04T09:00:00Z").naive_utc())
+        );
+        // 30 February never comes
+        assert!(validate(&schedule("0 9 30 2 *", "UTC"), &none, &[], now).is_err());
+    }
+}
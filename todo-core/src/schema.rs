@@ -0,0 +1,362 @@
+use mysql::prelude::*;
+use mysql::*;
+
+// Add a column to an existing table unless it is already there
+fn add_column_if_missing<Q: Queryable>(
+    conn: &mut Q,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists: Option<u32> = conn.exec_first(
+        "SELECT 1 FROM information_schema.columns
+         WHERE table_schema = DATABASE() AND table_name = :table AND column_name = :column",
+        params! {
+            "table" => table,
+            "column" => column,
+        },
+    )?;
+
+    if exists.is_none() {
+        conn.query_drop(format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))?;
+    }
+
+    Ok(())
+}
+
+// Whether `table` already has an index called `name`
+fn has_index<Q: Queryable>(conn: &mut Q, table: &str, name: &str) -> Result<bool> {
+    let exists: Option<u32> = conn.exec_first(
+        "SELECT 1 FROM information_schema.statistics
+         WHERE table_schema = DATABASE() AND table_name = :table AND index_name = :name
+         LIMIT 1",
+        params! {
+            "table" => table,
+            "name" => name,
+        },
+    )?;
+
+    Ok(exists.is_some())
+}
+
+fn has_foreign_key<Q: Queryable>(conn: &mut Q, table: &str, name: &str) -> Result<bool> {
+    let exists: Option<u32> = conn.exec_first(
+        "SELECT 1 FROM information_schema.table_constraints
+         WHERE table_schema = DATABASE() AND table_name = :table
+           AND constraint_name = :name AND constraint_type = 'FOREIGN KEY'",
+        params! {
+            "table" => table,
+            "name" => name,
+        },
+    )?;
+
+    Ok(exists.is_some())
+}
+
+// Add an index or unique key, e.g. `UNIQUE KEY name (a, b)`, unless it exists
+fn add_index_if_missing<Q: Queryable>(
+    conn: &mut Q,
+    table: &str,
+    name: &str,
+    definition: &str,
+) -> Result<()> {
+    if !has_index(conn, table, name)? {
+        conn.query_drop(format!("ALTER TABLE {} ADD {}", table, definition))?;
+    }
+
+    Ok(())
+}
+
+fn drop_index_if_exists<Q: Queryable>(conn: &mut Q, table: &str, name: &str) -> Result<()> {
+    if has_index(conn, table, name)? {
+        conn.query_drop(format!("ALTER TABLE {} DROP INDEX {}", table, name))?;
+    }
+
+    Ok(())
+}
+
+// Add a foreign key, e.g. `FOREIGN KEY (a) REFERENCES b (id)`, unless it exists
+fn add_foreign_key_if_missing<Q: Queryable>(
+    conn: &mut Q,
+    table: &str,
+    name: &str,
+    definition: &str,
+) -> Result<()> {
+    if !has_foreign_key(conn, table, name)? {
+        conn.query_drop(format!(
+            "ALTER TABLE {} ADD CONSTRAINT {} {}",
+            table, name, definition
+        ))?;
+    }
+
+    Ok(())
+}
+
+// Bring a database created before migrations existed up to the baseline.
+// The baseline's CREATE TABLE IF NOT EXISTS leaves existing tables alone, so
+// the columns, keys and indexes added to them since are patched in here.
+fn upgrade_legacy<Q: Queryable>(conn: &mut Q) -> Result<()> {
+    // Tasks created before import/export round-trips existed
+    add_column_if_missing(conn, "tasks", "external_id", "VARCHAR(255) NULL")?;
+    // Tasks created before priorities existed
+    add_column_if_missing(conn, "tasks", "priority", "TINYINT NOT NULL DEFAULT 2")?;
+    // Tasks created before due dates existed
+    add_column_if_missing(conn, "tasks", "due_date", "DATETIME NULL")?;
+    // Tasks created before start dates existed
+    add_column_if_missing(conn, "tasks", "start_date", "DATETIME NULL")?;
+    // Tasks created before the list could be sorted by age
+    add_column_if_missing(
+        conn,
+        "tasks",
+        "created_at",
+        "DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP",
+    )?;
+    // Tasks created before accounts existed; they stay ownerless until an
+    // operator runs `todo_web_app adopt <email>`. External ids used to be unique across all tasks
+    // and are now unique per user.
+    add_column_if_missing(conn, "tasks", "user_id", "INT NULL")?;
+    drop_index_if_exists(conn, "tasks", "external_id")?;
+    add_index_if_missing(
+        conn,
+        "tasks",
+        "uq_tasks_user_external_id",
+        "UNIQUE KEY uq_tasks_user_external_id (user_id, external_id)",
+    )?;
+    add_foreign_key_if_missing(
+        conn,
+        "tasks",
+        "fk_tasks_user",
+        "FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE",
+    )?;
+    // Tasks created before lists existed; deleting a list deletes its tasks
+    add_column_if_missing(conn, "tasks", "list_id", "INT NULL")?;
+    add_foreign_key_if_missing(
+        conn,
+        "tasks",
+        "fk_tasks_list",
+        "FOREIGN KEY (list_id) REFERENCES lists (id) ON DELETE CASCADE",
+    )?;
+    // Tasks created before subtasks existed; deleting a task deletes its
+    // subtasks unless they are moved up first
+    add_column_if_missing(conn, "tasks", "parent_id", "INT NULL")?;
+    add_foreign_key_if_missing(
+        conn,
+        "tasks",
+        "fk_tasks_parent",
+        "FOREIGN KEY (parent_id) REFERENCES tasks (id) ON DELETE CASCADE",
+    )?;
+    // Tasks created before the trash existed
+    add_column_if_missing(conn, "tasks", "deleted_at", "DATETIME NULL")?;
+    add_index_if_missing(
+        conn,
+        "tasks",
+        "idx_tasks_deleted_at",
+        "INDEX idx_tasks_deleted_at (deleted_at)",
+    )?;
+
+    // Schedules created before holiday handling existed
+    add_column_if_missing(
+        conn,
+        "schedules",
+        "on_holiday",
+        "VARCHAR(32) NOT NULL DEFAULT 'run'",
+    )?;
+    // Schedules created before accounts existed
+    add_column_if_missing(conn, "schedules", "user_id", "INT NULL")?;
+    add_foreign_key_if_missing(
+        conn,
+        "schedules",
+        "fk_schedules_user",
+        "FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE",
+    )?;
+
+    Ok(())
+}
+
+// A schema change, applied once and recorded in `schema_migrations`
+struct Migration {
+    version: u32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+// Every migration, oldest first. Versions are never reused or edited once
+// released; change the schema by adding a file to todo-core/migrations.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: include_str!("../migrations/0001_baseline.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "webhooks",
+        sql: include_str!("../migrations/0002_webhooks.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "schedule_exceptions",
+        sql: include_str!("../migrations/0003_schedule_exceptions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "schedule_runs",
+        sql: include_str!("../migrations/0004_schedule_runs.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "habits",
+        sql: include_str!("../migrations/0005_habits.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "goals",
+        sql: include_str!("../migrations/0006_goals.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "webhook_payload_versions",
+        sql: include_str!("../migrations/0007_webhook_payload_versions.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "task_external_ids",
+        sql: include_str!("../migrations/0008_task_external_ids.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "user_holidays",
+        sql: include_str!("../migrations/0009_user_holidays.sql"),
+    },
+];
+
+// Serialises servers migrating the same database at startup
+const LOCK_NAME: &str = "todo_schema_migrations";
+const LOCK_TIMEOUT_SECS: u32 = 60;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(Error),
+    // Another process held the migration lock for too long
+    Locked,
+    // The database was migrated by a newer build
+    SchemaAhead { database: u32, binary: u32 },
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "{}", e),
+            MigrationError::Locked => write!(
+                f,
+                "timed out after {}s waiting for another process to finish migrating",
+                LOCK_TIMEOUT_SECS
+            ),
+            MigrationError::SchemaAhead { database, binary } => write!(
+                f,
+                "database schema is at version {} but this build only knows up to {}; \
+                 run a newer build",
+                database, binary
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<Error> for MigrationError {
+    fn from(e: Error) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+// The schema version this build expects
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+// The highest migration applied to the database, 0 for none
+pub fn current_version<Q: Queryable>(conn: &mut Q) -> Result<u32> {
+    let version: Option<Option<u32>> =
+        conn.query_first("SELECT MAX(version) FROM schema_migrations")?;
+    Ok(version.flatten().unwrap_or(0))
+}
+
+// Run a migration's statements one at a time, so a failure points at the
+// statement that caused it
+fn apply<Q: Queryable>(conn: &mut Q, migration: &Migration) -> Result<()> {
+    let sql: String = migration
+        .sql
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    for statement in sql.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+        conn.query_drop(statement)?;
+    }
+
+    conn.exec_drop(
+        "INSERT INTO schema_migrations (version, name) VALUES (:version, :name)",
+        params! {
+            "version" => migration.version,
+            "name" => migration.name,
+        },
+    )
+}
+
+fn migrate<Q: Queryable>(conn: &mut Q) -> std::result::Result<(), MigrationError> {
+    let current = current_version(conn)?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(MigrationError::SchemaAhead {
+            database: current,
+            binary: latest,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        apply(conn, migration)?;
+        if migration.version == 1 {
+            upgrade_legacy(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Apply any migrations the database is missing. Fails without touching the
+// schema if the database was migrated past what this build knows about.
+pub fn init<Q: Queryable>(conn: &mut Q) -> std::result::Result<(), MigrationError> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INT UNSIGNED PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            applied_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )?;
+
+    let locked: Option<u32> = conn.exec_first(
+        "SELECT COALESCE(GET_LOCK(:name, :timeout), 0)",
+        params! {
+            "name" => LOCK_NAME,
+            "timeout" => LOCK_TIMEOUT_SECS,
+        },
+    )?;
+    if locked != Some(1) {
+        return Err(MigrationError::Locked);
+    }
+
+    let result = migrate(conn);
+    conn.exec_drop(
+        "DO RELEASE_LOCK(:name)",
+        params! {
+            "name" => LOCK_NAME,
+        },
+    )?;
+
+    result
+}
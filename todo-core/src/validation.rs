@@ -0,0 +1,286 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::models::{
+    ApiKey, Goal, Habit, HabitPeriod, Holiday, PayloadVersion, Schedule, Tag, Task, TaskList,
+    TaskPatch, Webhook,
+};
+use crate::scheduling::{parse_cron, parse_timezone};
+
+// Longest description a TEXT column holds
+const MAX_DESCRIPTION_BYTES: usize = 65_535;
+const MAX_NAME_CHARS: usize = 255;
+const MAX_URL_CHARS: usize = 2048;
+// Most tasks or lists one goal can link
+const MAX_GOAL_LINKS: usize = 1000;
+
+// One problem with one field of a payload
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+// Checks a payload beyond what deserialization guarantees
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+// Collects errors so every problem is reported at once
+fn finish(errors: Vec<FieldError>) -> Result<(), Vec<FieldError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn check_description(description: &str, errors: &mut Vec<FieldError>) {
+    if description.trim().is_empty() {
+        errors.push(FieldError::new("description", "must not be empty"));
+    } else if description.len() > MAX_DESCRIPTION_BYTES {
+        errors.push(FieldError::new(
+            "description",
+            format!("must be at most {} bytes", MAX_DESCRIPTION_BYTES),
+        ));
+    }
+}
+
+fn check_external_id(external_id: &Option<String>, errors: &mut Vec<FieldError>) {
+    let Some(external_id) = external_id else {
+        return;
+    };
+
+    if external_id.trim().is_empty() {
+        errors.push(FieldError::new("external_id", "must not be empty"));
+    } else if external_id.chars().count() > MAX_NAME_CHARS {
+        errors.push(FieldError::new(
+            "external_id",
+            format!("must be at most {} characters", MAX_NAME_CHARS),
+        ));
+    }
+}
+
+// A required short name, e.g. a holiday's name or a list's
+fn check_name(field: &'static str, name: &str, errors: &mut Vec<FieldError>) {
+    if name.trim().is_empty() {
+        errors.push(FieldError::new(field, "must not be empty"));
+    } else if name.chars().count() > MAX_NAME_CHARS {
+        errors.push(FieldError::new(
+            field,
+            format!("must be at most {} characters", MAX_NAME_CHARS),
+        ));
+    }
+}
+
+fn check_tags(tags: &[String], errors: &mut Vec<FieldError>) {
+    for tag in tags {
+        check_name("tags", tag, errors);
+    }
+}
+
+fn check_date_order(
+    start_date: Option<DateTime<Utc>>,
+    due_date: Option<DateTime<Utc>>,
+    errors: &mut Vec<FieldError>,
+) {
+    if let (Some(start_date), Some(due_date)) = (start_date, due_date) {
+        if start_date > due_date {
+            errors.push(FieldError::new("start_date", "must not be after due_date"));
+        }
+    }
+}
+
+impl Validate for Task {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_description(&self.description, &mut errors);
+        check_external_id(&self.external_id, &mut errors);
+        check_tags(&self.tags, &mut errors);
+        check_date_order(self.start_date, self.due_date, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for TaskPatch {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if let Some(description) = &self.description {
+            check_description(description, &mut errors);
+        }
+        check_external_id(&self.external_id, &mut errors);
+        if let Some(tags) = &self.tags {
+            check_tags(tags, &mut errors);
+        }
+        check_date_order(
+            self.start_date.flatten(),
+            self.due_date.flatten(),
+            &mut errors,
+        );
+        finish(errors)
+    }
+}
+
+// A patch that sends only one of the dates is checked against the other as
+// stored
+pub fn check_patched_dates(patch: &TaskPatch, stored: &Task) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    check_date_order(
+        patch.start_date.unwrap_or(stored.start_date),
+        patch.due_date.unwrap_or(stored.due_date),
+        &mut errors,
+    );
+    finish(errors)
+}
+
+impl Validate for Schedule {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_description(&self.description, &mut errors);
+
+        if let Err(message) = parse_cron(&self.cron) {
+            errors.push(FieldError::new("cron", message));
+        }
+        if let Err(message) = parse_timezone(&self.timezone) {
+            errors.push(FieldError::new("timezone", message));
+        }
+
+        finish(errors)
+    }
+}
+
+impl Validate for Goal {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        check_name("title", &self.title, &mut errors);
+        if let Some(description) = &self.description {
+            check_description(description, &mut errors);
+        }
+        if self.task_ids.len() > MAX_GOAL_LINKS {
+            errors.push(FieldError::new(
+                "task_ids",
+                format!("must list at most {} tasks", MAX_GOAL_LINKS),
+            ));
+        }
+        if self.list_ids.len() > MAX_GOAL_LINKS {
+            errors.push(FieldError::new(
+                "list_ids",
+                format!("must list at most {} lists", MAX_GOAL_LINKS),
+            ));
+        }
+
+        finish(errors)
+    }
+}
+
+impl Validate for Habit {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_description(&self.description, &mut errors);
+
+        let most = match self.period {
+            HabitPeriod::Day => 24,
+            HabitPeriod::Week => 7 * 24,
+            HabitPeriod::Month => 31 * 24,
+        };
+        if self.times == 0 || self.times > most {
+            errors.push(FieldError::new(
+                "times",
+                format!(
+                    "must be between 1 and {} per {}",
+                    most,
+                    self.period.as_str()
+                ),
+            ));
+        }
+        if let Err(message) = parse_timezone(&self.timezone) {
+            errors.push(FieldError::new("timezone", message));
+        }
+
+        finish(errors)
+    }
+}
+
+impl Validate for Holiday {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for ApiKey {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        check_name("label", &self.label, &mut errors);
+        finish(errors)
+    }
+}
+
+// Whether a key is still usable when created at `now`; kept out of Validate
+// so the check follows the server's clock
+pub fn check_key_expiry(api_key: &ApiKey, now: DateTime<Utc>) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    if api_key.expires_at.is_some_and(|at| at <= now) {
+        errors.push(FieldError::new("expires_at", "must be in the future"));
+    }
+    finish(errors)
+}
+
+impl Validate for TaskList {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for Tag {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for Webhook {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let url = self.url.trim();
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            errors.push(FieldError::new("url", "must be an http or https URL"));
+        } else if url.chars().count() > MAX_URL_CHARS {
+            errors.push(FieldError::new(
+                "url",
+                format!("must be at most {} characters", MAX_URL_CHARS),
+            ));
+        }
+        if let Some(version) = self.payload_version {
+            if PayloadVersion::from_number(version).is_none() {
+                errors.push(FieldError::new(
+                    "payload_version",
+                    format!(
+                        "must be between {} and {}",
+                        PayloadVersion::OLDEST.number(),
+                        PayloadVersion::LATEST.number()
+                    ),
+                ));
+            }
+        }
+
+        finish(errors)
+    }
+}